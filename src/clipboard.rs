@@ -0,0 +1,127 @@
+//! This module reads the files copied in a file manager from the system clipboard,
+//! so that they can be added to the store
+//!
+//! The clipboard is read with the tools each platform ships with,
+//! `wl-paste` or `xclip` on Linux, `osascript` on macOS and PowerShell on Windows
+
+use std::{path::PathBuf, process::Command};
+
+/// Reads the paths of the files on the clipboard
+///
+/// Returns an error message if the clipboard can't be read or holds no files
+pub fn read_file_paths() -> Result<Vec<PathBuf>, String> {
+    let paths = read_platform()?;
+
+    if paths.is_empty() {
+        return Err("No files on the clipboard".to_string());
+    }
+
+    Ok(paths)
+}
+
+/// Runs a command and returns its stdout, or `None` if it failed
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Reads the `text/uri-list` target, from Wayland and then X11
+#[cfg(all(unix, not(target_os = "macos")))]
+fn read_platform() -> Result<Vec<PathBuf>, String> {
+    let list = run("wl-paste", &["--no-newline", "--type", "text/uri-list"])
+        .or_else(|| {
+            run(
+                "xclip",
+                &["-selection", "clipboard", "-t", "text/uri-list", "-o"],
+            )
+        })
+        .ok_or("Could not read the clipboard, make sure wl-paste or xclip is installed")?;
+
+    Ok(parse_uri_list(&list))
+}
+
+/// Reads the file URLs on the pasteboard, through JavaScript for Automation,
+/// as AppleScript's `«class furl»` only gives the first one
+#[cfg(target_os = "macos")]
+fn read_platform() -> Result<Vec<PathBuf>, String> {
+    const SCRIPT: &str = r#"ObjC.import('AppKit');
+const urls = $.NSPasteboard.generalPasteboard.readObjectsForClassesOptions(
+    $.NSArray.arrayWithObject($.NSURL),
+    $.NSDictionary.dictionaryWithObjectForKey(true, 'NSPasteboardURLReadingFileURLsOnlyKey')
+);
+(ObjC.deepUnwrap(urls.valueForKey('path')) || []).join('\n')"#;
+
+    let paths = run("osascript", &["-l", "JavaScript", "-e", SCRIPT])
+        .ok_or("Could not read the files from the clipboard")?;
+
+    Ok(paths
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Reads the file drop list (CF_HDROP) on the clipboard
+#[cfg(windows)]
+fn read_platform() -> Result<Vec<PathBuf>, String> {
+    let list = run(
+        "powershell",
+        &[
+            "-NoProfile",
+            "-Command",
+            "Get-Clipboard -Format FileDropList | ForEach-Object { $_.FullName }",
+        ],
+    )
+    .ok_or("Could not read the clipboard")?;
+
+    Ok(list
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| PathBuf::from(l.trim()))
+        .collect())
+}
+
+/// Parses a `text/uri-list`, keeping only the `file://` URIs
+#[cfg(all(unix, not(target_os = "macos")))]
+fn parse_uri_list(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.strip_prefix("file://"))
+        // The host, usually empty or localhost, comes before the path
+        .filter_map(|l| l.find('/').map(|i| &l[i..]))
+        .map(|p| PathBuf::from(percent_decode(p)))
+        .collect()
+}
+
+/// Decodes the `%XX` escapes in a URI path
+#[cfg(all(unix, not(target_os = "macos")))]
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
@@ -2,7 +2,17 @@
 
 use std::path::Path;
 
-use crate::files::get_config_path;
+use colored::Colorize;
+
+use crate::{
+    files::{get_config_path, STORE_PATH_ENV},
+    fs::{self, CopyBackend},
+    owner::{self, Owner},
+    preserve::Preserve,
+    symlinks::SymlinkPolicy,
+    update,
+    utils::{self, Matching, DEFAULT_MATCH_THRESHOLD},
+};
 
 /// ConstructedArgs struct
 /// which is used to emulate or mimic
@@ -24,6 +34,7 @@ use crate::files::get_config_path;
 ///
 /// This takes up a bit more memory than the Arg struct,
 /// but it is worth it in the long run
+#[derive(Clone)]
 pub struct ConstructedArgs {
     pub files: Option<Vec<String>>,
     pub dir: bool,
@@ -32,14 +43,104 @@ pub struct ConstructedArgs {
     pub all: bool,
     pub overwrite: bool,
     pub delete: bool,
+    /// Move the sources of the entries instead of copying them, with `cut`
+    pub move_files: bool,
+    /// What happens to the symlinks in the directories being pasted
+    pub symlinks: SymlinkPolicy,
+    /// The attributes kept on the pasted files, from `--preserve` or `preserve_attributes`
+    pub preserve: Preserve,
+    /// How files are copied while pasting, from `copy_backend`
+    pub copy_backend: CopyBackend,
+    /// How the queries are matched against the entries, from `--exact` or `--threshold`
+    pub matching: Matching,
+    /// Show why each entry matched the queries, with `--explain`
+    pub explain: bool,
+    /// Use every entry a query matches instead of asking which were meant, with `--all-matches`
+    pub all_matches: bool,
+    /// Choose which of the matched entries to paste from a checklist, with `paste --select`
+    pub select: bool,
     pub range: Option<String>,
+    pub glob: Option<String>,
+    pub latest: Option<usize>,
+    pub target: Option<String>,
+    pub default_output: Option<String>,
+    pub suggest_targets: bool,
+    pub target_rules: Vec<TargetRule>,
+    pub picker: String,
     pub specific: Option<String>,
-    pub yes: bool,
+    /// Whether to prompt at all, turned off by `--yes`
+    pub prompt: bool,
+    /// Confirm before clearing entries
+    pub confirm_clear: bool,
+    /// Confirm before deleting entries
+    pub confirm_delete: bool,
+    /// Confirm before adding the current directory when no files are given
+    pub confirm_cwd_add: bool,
+    /// Confirm before restoring a snapshot over its source
+    pub confirm_overwrite: bool,
     pub calculate_size: bool,
     pub preserve_structure: bool,
+    pub warn_entry_size: Option<String>,
+    /// Warn before pasting a single file larger than this, ex: "2GB"
+    pub warn_file_size: Option<String>,
+    /// Copy the files larger than this through a memory map, ex: "1GB"
+    pub mmap_threshold: Option<String>,
+    /// Stream copies through a buffer of this many bytes, from `copy_buffer_size` or `--buffer-size`
+    pub copy_buffer_size: Option<usize>,
+    /// The mode set on the pasted files, from `--chmod`
+    pub file_mode: Option<u32>,
+    /// The mode set on the directories created for the pasted files, from `--dir-mode`
+    pub dir_mode: Option<u32>,
+    /// Who the pasted files are given to, from `--chown`
+    /// or the user who ran ynk through sudo with `chown_sudo_user`
+    pub owner: Option<Owner>,
+    /// Set when `--chown` is passed, which also gives away the files a paste overwrites
+    pub owner_explicit: bool,
+    pub dry_run: bool,
+    pub snapshot: bool,
+    pub snapshot_versions: usize,
+    pub version: Option<i32>,
+    pub sort: Option<String>,
+    pub show_created: bool,
+    pub show_types: bool,
+    /// How `list` displays the entries: "detailed", "compact" or "icons"
+    pub list_style: String,
+    /// Show the full paths in `list` instead of shortening them to fit the terminal
+    pub full_path: bool,
+    /// Print only the names of the entries, one per line
+    pub names_only: bool,
+    /// Print only the stored paths of the entries, one per line
+    pub paths_only: bool,
+    /// Separate the names or paths with NUL instead of newlines
+    pub print0: bool,
+    /// Only list the entries of this type, ex: "image"
+    pub file_type: Option<String>,
+    /// Only list directories when `Some(true)`, or files when `Some(false)`
+    pub is_dir: Option<bool>,
+    /// Only list the first N entries
+    pub top: Option<usize>,
+    /// How the progress bar looks, `None` with `--no-progress`
+    pub progress: Option<ProgressConfig>,
+    /// Set when `--all` is passed, overriding the option stored with an entry
+    pub all_explicit: bool,
+    /// Set when `--noignore` is passed, overriding the option stored with an entry
+    pub ignore_explicit: bool,
+    /// The store path given by `--store`
+    pub store: Option<String>,
+    /// Whether the store is only read, from `read_only` or `--read-only`
+    pub read_only: bool,
 }
 
 impl ConstructedArgs {
+    /// Turns off every prompt, answering them as if confirmed
+    pub fn no_prompt(&mut self) {
+        self.prompt = false;
+        self.confirm_clear = false;
+        self.confirm_delete = false;
+        self.confirm_cwd_add = false;
+        self.confirm_overwrite = false;
+    }
+
     pub fn new(config: Config) -> Self {
         Self {
             files: None,
@@ -49,11 +150,73 @@ impl ConstructedArgs {
             overwrite: config.overwrite,
             ignore: config.ignore,
             delete: config.delete,
+            move_files: false,
+            symlinks: config.symlinks.parse().unwrap_or_default(),
+            preserve: config
+                .preserve_attributes
+                .join(",")
+                .parse()
+                .unwrap_or_default(),
+            copy_backend: config.copy_backend.parse().unwrap_or_default(),
+            matching: match config.exact_match {
+                true => Matching::Exact,
+                false => Matching::Fuzzy(config.match_threshold),
+            },
+            explain: false,
+            all_matches: false,
+            select: false,
             range: None,
+            glob: None,
+            latest: None,
+            target: None,
+            default_output: config.default_output,
+            suggest_targets: config.suggest_targets,
+            target_rules: config.target_rules,
+            picker: config.picker,
             calculate_size: config.calculate_size,
             specific: None,
-            yes: config.prompt,
+            prompt: config.prompt,
+            confirm_clear: config.prompt && config.confirm_clear,
+            confirm_delete: config.prompt && config.confirm_delete,
+            confirm_cwd_add: config.prompt && config.confirm_cwd_add,
+            confirm_overwrite: config.prompt && config.confirm_overwrite,
             preserve_structure: config.preserve_structure,
+            warn_entry_size: config.warn_entry_size,
+            warn_file_size: config.warn_file_size,
+            mmap_threshold: config.mmap_threshold,
+            copy_buffer_size: config
+                .copy_buffer_size
+                .as_deref()
+                .and_then(|size| fs::parse_buffer_size(size).ok()),
+            file_mode: None,
+            dir_mode: None,
+            owner: config.chown_sudo_user.then(owner::sudo_user).flatten(),
+            owner_explicit: false,
+            dry_run: false,
+            snapshot: false,
+            snapshot_versions: config.snapshot_versions,
+            version: None,
+            sort: None,
+            show_created: false,
+            show_types: false,
+            list_style: config.list_style,
+            full_path: config.full_path,
+            names_only: false,
+            paths_only: false,
+            print0: false,
+            file_type: None,
+            is_dir: None,
+            top: None,
+            progress: Some(ProgressConfig {
+                template: config.progress_template,
+                progress_chars: config.progress_chars,
+                spinner_chars: config.spinner_chars,
+                refresh_rate: config.progress_refresh_rate,
+            }),
+            all_explicit: false,
+            ignore_explicit: false,
+            store: None,
+            read_only: config.read_only,
         }
     }
 }
@@ -72,34 +235,565 @@ fn default_config() -> Result<String, toml::ser::Error> {
         overwrite: false,
         delete: false,
         prompt: true,
+        confirm_clear: true,
+        confirm_delete: true,
+        confirm_cwd_add: true,
+        confirm_overwrite: true,
         show_splash: true,
+        check_updates: true,
         calculate_size: true,
         preserve_structure: false,
+        warn_entry_size: None,
+        warn_file_size: None,
+        mmap_threshold: None,
+        copy_buffer_size: None,
+        store_path: None,
+        path_root: None,
+        read_only: false,
+        chown_sudo_user: false,
+        snapshot_versions: default_snapshot_versions(),
+        trash_days: default_trash_days(),
+        default_output: None,
+        suggest_targets: false,
+        target_rules: Vec::new(),
+        picker: default_picker(),
+        list_style: default_list_style(),
+        full_path: false,
+        symlinks: default_symlinks(),
+        preserve_attributes: Vec::new(),
+        exact_match: false,
+        match_threshold: DEFAULT_MATCH_THRESHOLD,
+        copy_backend: default_copy_backend(),
+        progress_template: default_progress_template(),
+        progress_chars: default_progress_chars(),
+        spinner_chars: None,
+        progress_refresh_rate: default_progress_refresh_rate(),
     };
 
     toml::to_string_pretty(&config)
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+/// The config file, where every key is optional
+/// and a missing one takes its default
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub strict: bool,
+    #[serde(default = "default_true")]
     pub ignore: bool,
+    #[serde(default)]
     pub all: bool,
+    #[serde(default)]
     pub overwrite: bool,
+    #[serde(default)]
     pub delete: bool,
+    /// Whether to prompt at all, overriding the confirm options
+    #[serde(default = "default_true")]
     pub prompt: bool,
+    /// Confirm before clearing entries
+    #[serde(default = "default_true")]
+    pub confirm_clear: bool,
+    /// Confirm before deleting entries
+    #[serde(default = "default_true")]
+    pub confirm_delete: bool,
+    /// Confirm before adding the current directory when no files are given
+    #[serde(default = "default_true")]
+    pub confirm_cwd_add: bool,
+    /// Confirm before restoring a snapshot over its source
+    #[serde(default = "default_true")]
+    pub confirm_overwrite: bool,
+    #[serde(default = "default_true")]
     pub show_splash: bool,
+    /// Check crates.io for a new version of ynk, at most once a day
+    #[serde(default = "default_true")]
+    pub check_updates: bool,
+    #[serde(default = "default_true")]
     pub calculate_size: bool,
+    #[serde(default)]
     pub preserve_structure: bool,
+    /// Warn when an added entry is larger than this, ex: "5GB"
+    #[serde(default)]
+    pub warn_entry_size: Option<String>,
+    /// Warn before pasting a single file larger than this, ex: "2GB"
+    #[serde(default)]
+    pub warn_file_size: Option<String>,
+    /// Copy the files larger than this through a memory map, ex: "1GB"
+    #[serde(default)]
+    pub mmap_threshold: Option<String>,
+    /// Stream copies through a buffer of this size, ex: "1MB"
+    #[serde(default)]
+    pub copy_buffer_size: Option<String>,
+    /// Where the store is kept instead of `~/.ynk`, ex: "/mnt/shared/ynk"
+    #[serde(default)]
+    pub store_path: Option<String>,
+    /// Store the paths under this directory relative to it, for a store synced
+    /// between machines where it is elsewhere, ex: "~"
+    #[serde(default)]
+    pub path_root: Option<String>,
+    /// Never change the store, like `--read-only`, for a shared or backed up store
+    #[serde(default)]
+    pub read_only: bool,
+    /// Give the files pasted as root through sudo to the user who ran sudo
+    #[serde(default)]
+    pub chown_sudo_user: bool,
+    /// How many snapshot versions are kept for an entry
+    #[serde(default = "default_snapshot_versions")]
+    pub snapshot_versions: usize,
+    /// How many days deleted entries are kept in the trash
+    #[serde(default = "default_trash_days")]
+    pub trash_days: u32,
+    /// Where entries are pasted when no output is given, ex: "~/inbox"
+    #[serde(default)]
+    pub default_output: Option<String>,
+    /// Suggest targets when pasting without an output
+    #[serde(default)]
+    pub suggest_targets: bool,
+    /// Targets suggested for the entries matching a glob
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub target_rules: Vec<TargetRule>,
+    /// The fuzzy picker used by `paste --pick-target`
+    #[serde(default = "default_picker")]
+    pub picker: String,
+    /// How `list` displays the entries: "detailed", "compact" or "icons"
+    #[serde(default = "default_list_style")]
+    pub list_style: String,
+    /// Show the full paths in `list` instead of shortening them to fit the terminal
+    #[serde(default)]
+    pub full_path: bool,
+    /// What happens to the symlinks in the directories being pasted: "follow", "copy" or "skip"
+    #[serde(default = "default_symlinks")]
+    pub symlinks: String,
+    /// The attributes kept on the pasted files, ex: ["mode", "times", "xattr"]
+    #[serde(default)]
+    pub preserve_attributes: Vec<String>,
+    /// How files are copied while pasting: "tokio", or "io_uring" with the `io-uring` feature
+    #[serde(default = "default_copy_backend")]
+    pub copy_backend: String,
+    /// Only match the exact id, name, path or uuid of the entries, like `--exact`
+    #[serde(default)]
+    pub exact_match: bool,
+    /// How similar a query has to be to fuzzily match an entry name, from 0 to 1
+    #[serde(default = "default_match_threshold")]
+    pub match_threshold: f64,
+    /// The indicatif template of the progress bar
+    #[serde(default = "default_progress_template")]
+    pub progress_template: String,
+    /// The characters the bar is drawn with, filled, current and empty
+    #[serde(default = "default_progress_chars")]
+    pub progress_chars: String,
+    /// The frames of the spinner, ex: "⠁⠂⠄⡀⢀⠠⠐⠈ "
+    #[serde(default)]
+    pub spinner_chars: Option<String>,
+    /// How many times a second the progress bar is redrawn
+    #[serde(default = "default_progress_refresh_rate")]
+    pub progress_refresh_rate: u8,
+}
+
+/// How the progress bar is drawn, from the config
+#[derive(Debug, Clone)]
+pub struct ProgressConfig {
+    pub template: String,
+    pub progress_chars: String,
+    pub spinner_chars: Option<String>,
+    pub refresh_rate: u8,
+}
+
+/// Suggests pasting the entries whose name matches
+/// the glob to the target, ex: "*.ttf" to "~/.local/share/fonts"
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct TargetRule {
+    pub glob: String,
+    pub target: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_snapshot_versions() -> usize {
+    5
+}
+
+fn default_trash_days() -> u32 {
+    7
+}
+
+fn default_picker() -> String {
+    "fzf".to_string()
+}
+
+pub fn default_progress_template() -> String {
+    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}".to_string()
+}
+
+fn default_progress_chars() -> String {
+    "#>-".to_string()
+}
+
+fn default_progress_refresh_rate() -> u8 {
+    20
+}
+
+fn default_list_style() -> String {
+    "detailed".to_string()
+}
+
+fn default_symlinks() -> String {
+    "skip".to_string()
+}
+
+fn default_copy_backend() -> String {
+    "tokio".to_string()
+}
+
+fn default_match_threshold() -> f64 {
+    DEFAULT_MATCH_THRESHOLD
 }
 
 /// Convert config from string to Config struct
+///
+/// A config that can't be parsed is replaced by the defaults,
+/// with a warning unless the output is silenced
 pub fn get_config(config: String) -> Config {
     let default_config = default_config().expect("Failed to serialize default config");
 
+    toml::from_str(config.as_str()).unwrap_or_else(|_| {
+        if !utils::is_silent() {
+            eprintln!(
+                "{} {}",
+                "The config has errors, so the defaults are used.".yellow(),
+                "Run ynk config validate to see them".yellow()
+            );
+        }
+        toml::from_str(default_config.as_str()).unwrap()
+    })
+}
+
+/// A problem found in the config by `validate_config`
+pub struct ConfigProblem {
+    /// The line the problem is on, if it could be found
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Strictly checks a config, reporting unknown keys, values of the wrong type
+/// and values that are of the right type but don't make sense
+pub fn validate_config(content: &str) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    let mut unknown = Vec::new();
     let config: Config =
-        toml::from_str(config.as_str()).unwrap_or(toml::from_str(default_config.as_str()).unwrap());
+        match serde_ignored::deserialize(toml::Deserializer::new(content), |path| {
+            unknown.push(path.to_string())
+        }) {
+            Ok(config) => config,
+            Err(e) => {
+                problems.push(ConfigProblem {
+                    line: e.span().map(|s| line_of(content, s.start)),
+                    message: e.message().trim().to_string(),
+                });
+                return problems;
+            }
+        };
+
+    unknown.iter().for_each(|path| {
+        problems.push(ConfigProblem {
+            line: key_line(content, path),
+            message: format!("unknown key `{}`", path),
+        });
+    });
+
+    let mut invalid = |path: &str, message: String| {
+        problems.push(ConfigProblem {
+            line: key_line(content, path),
+            message: format!("invalid `{}`: {}", path, message),
+        });
+    };
+
+    [
+        ("warn_entry_size", &config.warn_entry_size),
+        ("warn_file_size", &config.warn_file_size),
+        ("mmap_threshold", &config.mmap_threshold),
+    ]
+    .into_iter()
+    .for_each(|(key, size)| {
+        if let Some(size) = size {
+            if utils::parse_size(size).is_none() {
+                invalid(key, format!("\"{}\" is not a size, ex: \"5GB\"", size));
+            }
+        }
+    });
+    if let Some(Err(e)) = config
+        .copy_buffer_size
+        .as_deref()
+        .map(fs::parse_buffer_size)
+    {
+        invalid("copy_buffer_size", e);
+    }
+    if !matches!(config.list_style.as_str(), "detailed" | "compact" | "icons") {
+        invalid(
+            "list_style",
+            format!(
+                "\"{}\" is not one of detailed, compact or icons",
+                config.list_style
+            ),
+        );
+    }
+    if let Err(e) = config.symlinks.parse::<SymlinkPolicy>() {
+        invalid("symlinks", e);
+    }
+    if let Err(e) = config.preserve_attributes.join(",").parse::<Preserve>() {
+        invalid("preserve_attributes", e);
+    }
+    match config.copy_backend.parse::<CopyBackend>() {
+        Ok(CopyBackend::IoUring) if !cfg!(all(target_os = "linux", feature = "io-uring")) => {
+            invalid(
+                "copy_backend",
+                "ynk was built without the io-uring feature, which is only on Linux".to_string(),
+            )
+        }
+        Ok(_) => {}
+        Err(e) => invalid("copy_backend", e),
+    }
+    if !(0.0..=1.0).contains(&config.match_threshold) {
+        invalid(
+            "match_threshold",
+            format!("{} is not between 0 and 1", config.match_threshold),
+        );
+    }
+    if config.picker.trim().is_empty() {
+        invalid("picker", "the command is empty".to_string());
+    }
+    if let Err(e) = indicatif::ProgressStyle::with_template(&config.progress_template) {
+        invalid("progress_template", e.to_string());
+    }
+    if config.progress_chars.chars().count() < 2 {
+        invalid(
+            "progress_chars",
+            "at least 2 characters are needed".to_string(),
+        );
+    }
+    if let Some(spinner) = &config.spinner_chars {
+        if spinner.chars().count() < 2 {
+            invalid(
+                "spinner_chars",
+                "at least 2 characters are needed".to_string(),
+            );
+        }
+    }
+    if config.progress_refresh_rate == 0 {
+        invalid(
+            "progress_refresh_rate",
+            "it has to be at least 1".to_string(),
+        );
+    }
     config
+        .target_rules
+        .iter()
+        .enumerate()
+        .for_each(|(i, rule)| {
+            if utils::glob_matcher(&rule.glob).is_none() {
+                invalid(
+                    &format!("target_rules.{}.glob", i),
+                    format!("\"{}\" is not a valid glob", rule.glob),
+                );
+            }
+        });
+
+    problems.sort_by_key(|p| p.line);
+    problems
+}
+
+/// The line number, starting at 1, of a byte offset
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// Finds the line a key is set on from its path, ex: `target_rules.0.glob`,
+/// by looking for `key =` under the header of the table it is in
+fn key_line(content: &str, path: &str) -> Option<usize> {
+    let lines = content.lines().collect::<Vec<_>>();
+    let segments = path.split('.').collect::<Vec<_>>();
+    let (key, tables) = segments.split_last()?;
+
+    let mut start = 0;
+    let mut i = 0;
+    while i < tables.len() {
+        // An index means the nth of an array of tables, ex: `[[target_rules]]`
+        let (header, nth) = match tables.get(i + 1).and_then(|s| s.parse::<usize>().ok()) {
+            Some(index) => (format!("[[{}]]", tables[i]), index),
+            None => (format!("[{}]", tables[i]), 0),
+        };
+        start = lines
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, line)| line.trim() == header)
+            .nth(nth)?
+            .0
+            + 1;
+        i += if header.starts_with("[[") { 2 } else { 1 };
+    }
+
+    lines[start..]
+        .iter()
+        .position(|line| {
+            line.trim_start()
+                .trim_start_matches('"')
+                .strip_prefix(key)
+                .map(|rest| rest.trim_start_matches('"').trim_start().starts_with('='))
+                .unwrap_or_default()
+        })
+        .map(|i| start + i + 1)
+}
+
+/// Where the effective value of a config key came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    File,
+    /// An environment variable, ex: `YNK_STORE_PATH`
+    Env(&'static str),
+    /// A global flag, ex: `--yes`
+    Flag(&'static str),
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File => write!(f, "config file"),
+            ConfigSource::Env(var) => write!(f, "${}", var),
+            ConfigSource::Flag(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+/// A config key with its effective value
+pub struct ConfigValue {
+    pub key: String,
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// The keys that are left out of the serialized config when they aren't set
+const OPTIONAL_KEYS: [&str; 9] = [
+    "warn_entry_size",
+    "warn_file_size",
+    "mmap_threshold",
+    "copy_buffer_size",
+    "store_path",
+    "path_root",
+    "default_output",
+    "spinner_chars",
+    "target_rules",
+];
+
+/// Lists the effective value of every config key and where it came from,
+/// the defaults, the config file, the environment or the global flags that override it
+pub fn effective_config(config: &Config, args: &ConstructedArgs) -> Vec<ConfigValue> {
+    // A config that can't be parsed is replaced by the defaults, so none of it is used
+    let in_file = std::fs::read_to_string(get_config_path())
+        .ok()
+        .filter(|content| toml::from_str::<Config>(content).is_ok())
+        .and_then(|content| toml::from_str::<toml::Table>(&content).ok())
+        .unwrap_or_default();
+
+    let serialized = toml::to_string(config).expect("Failed to serialize config");
+    let mut values = toml::from_str::<toml::Table>(&serialized)
+        .expect("Failed to parse the serialized config")
+        .into_iter()
+        .map(|(key, value)| ConfigValue {
+            source: if in_file.contains_key(&key) {
+                ConfigSource::File
+            } else {
+                ConfigSource::Default
+            },
+            value: value.to_string(),
+            key,
+        })
+        .collect::<Vec<_>>();
+
+    OPTIONAL_KEYS.iter().for_each(|key| {
+        if !values.iter().any(|v| v.key == *key) {
+            values.push(ConfigValue {
+                key: key.to_string(),
+                value: "unset".to_string(),
+                source: ConfigSource::Default,
+            });
+        }
+    });
+
+    if let Ok(path) = std::env::var(STORE_PATH_ENV) {
+        let store_path = values.iter_mut().find(|v| v.key == "store_path");
+        if let (false, Some(v)) = (path.is_empty(), store_path) {
+            v.value = toml::Value::String(path).to_string();
+            v.source = ConfigSource::Env(STORE_PATH_ENV);
+        }
+    }
+
+    if config.check_updates && !update::enabled(config) {
+        if let Some(v) = values.iter_mut().find(|v| v.key == "check_updates") {
+            v.value = false.to_string();
+            v.source = ConfigSource::Env(update::NO_UPDATE_CHECK_ENV);
+        }
+    }
+
+    if let Some(store) = &args.store {
+        if let Some(v) = values.iter_mut().find(|v| v.key == "store_path") {
+            v.value = toml::Value::String(store.clone()).to_string();
+            v.source = ConfigSource::Flag("--store");
+        }
+    }
+
+    let flagged = match args.matching {
+        Matching::Exact if !config.exact_match => {
+            Some(("exact_match", toml::Value::Boolean(true), "--exact"))
+        }
+        Matching::Fuzzy(threshold) if threshold != config.match_threshold => Some((
+            "match_threshold",
+            toml::Value::Float(threshold),
+            "--threshold",
+        )),
+        _ => None,
+    };
+    if let Some((key, value, flag)) = flagged {
+        if let Some(v) = values.iter_mut().find(|v| v.key == key) {
+            v.value = value.to_string();
+            v.source = ConfigSource::Flag(flag);
+        }
+    }
+
+    let mut set_by_flag = |key: &str, value: bool, flag: &'static str| {
+        if let Some(v) = values.iter_mut().find(|v| v.key == key) {
+            v.value = value.to_string();
+            v.source = ConfigSource::Flag(flag);
+        }
+    };
+
+    if args.all_explicit {
+        set_by_flag("all", args.all, "--all");
+    }
+    if args.ignore_explicit {
+        set_by_flag("ignore", args.ignore, "--noignore");
+    }
+    if args.read_only && !config.read_only {
+        set_by_flag("read_only", true, "--read-only");
+    }
+    if config.prompt && !args.prompt {
+        set_by_flag("prompt", false, "--yes");
+        [
+            ("confirm_clear", config.confirm_clear),
+            ("confirm_delete", config.confirm_delete),
+            ("confirm_cwd_add", config.confirm_cwd_add),
+            ("confirm_overwrite", config.confirm_overwrite),
+        ]
+        .into_iter()
+        .filter(|(_, confirm)| *confirm)
+        .for_each(|(key, _)| set_by_flag(key, false, "--yes"));
+    }
+
+    values
 }
 
 pub fn get_config_from_file() -> Config {
@@ -0,0 +1,146 @@
+//! `ynk daemon`, serving the JSON-RPC API of [`crate::rpc`] over a local socket,
+//! so that editor plugins don't have to spawn ynk for every call
+//!
+//! On Unix it listens on a Unix socket in the store directory,
+//! and on Windows on a named pipe
+
+use std::io;
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+
+use crate::{config::ConstructedArgs, rpc};
+
+/// The name of the socket in the store directory, or of the named pipe on Windows
+pub const SOCKET_NAME: &str = "ynk.sock";
+
+/// Where the daemon listens by default
+#[cfg(unix)]
+pub fn default_address() -> String {
+    crate::files::get_path(SOCKET_NAME)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Where the daemon listens by default
+#[cfg(windows)]
+pub fn default_address() -> String {
+    format!(r"\\.\pipe\{}", SOCKET_NAME)
+}
+
+/// Listens on the address until interrupted, serving every client that connects
+///
+/// The clients share the connection to the store, so their requests are handled
+/// one at a time, each to its end before the next is started,
+/// while waiting on one client to send a request doesn't hold up the others
+pub async fn run(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    address: &str,
+) -> io::Result<()> {
+    let mut listener = Listener::bind(address)?;
+    tracing::info!(address, "the daemon is listening");
+
+    let busy = tokio::sync::Mutex::new(());
+    let mut clients = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(stream) => {
+                    tracing::debug!("a client connected");
+                    clients.push(serve(conn, args, &busy, stream));
+                }
+                Err(e) => tracing::warn!(error = %e, "could not accept a client"),
+            },
+            Some(()) = clients.next(), if !clients.is_empty() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    tracing::info!("the daemon stopped");
+    Ok(())
+}
+
+/// Serves a client until it disconnects
+async fn serve<S>(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    busy: &tokio::sync::Mutex<()>,
+    stream: S,
+) where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    match rpc::serve_lines(conn, args, busy, BufReader::new(reader), writer).await {
+        Ok(()) => tracing::debug!("a client disconnected"),
+        Err(e) => tracing::warn!(error = %e, "a client disconnected"),
+    }
+}
+
+#[cfg(unix)]
+struct Listener {
+    inner: tokio::net::UnixListener,
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Listener {
+    /// Binds the socket, replacing one left behind by a daemon that didn't stop cleanly
+    fn bind(address: &str) -> io::Result<Self> {
+        let path = std::path::PathBuf::from(address);
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a daemon is already listening on {}", address),
+                ));
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        Ok(Self {
+            inner: tokio::net::UnixListener::bind(&path)?,
+            path,
+        })
+    }
+
+    async fn accept(&mut self) -> io::Result<tokio::net::UnixStream> {
+        self.inner.accept().await.map(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A named pipe takes a new instance for every client,
+/// so the next one is created as soon as one is connected
+#[cfg(windows)]
+struct Listener {
+    name: String,
+    next: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl Listener {
+    fn bind(address: &str) -> io::Result<Self> {
+        let next = tokio::net::windows::named_pipe::ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(address)?;
+
+        Ok(Self {
+            name: address.to_string(),
+            next,
+        })
+    }
+
+    async fn accept(&mut self) -> io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        self.next.connect().await?;
+        let next = tokio::net::windows::named_pipe::ServerOptions::new().create(&self.name)?;
+
+        Ok(std::mem::replace(&mut self.next, next))
+    }
+}
@@ -1,25 +1,74 @@
 //! This module contains all the functions that are used to interact with the database
 //! The database is used to store the files that are uploaded
 
-use std::str::FromStr;
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
-use chrono::{DateTime, Local};
-use rusqlite::Connection;
-use sea_query::{ColumnDef, Expr, Iden, Order, Query, SqliteQueryBuilder, Table};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use hashbrown::HashMap;
+use rusqlite::{Connection, OpenFlags, Row};
+use sea_query::{
+    Alias, Cond, Condition, Expr, Func, Iden, IntoIden, OnConflict, Order, Query,
+    SqliteQueryBuilder,
+};
 
-use crate::{files::get_path, utils::sort_entries};
+use crate::{
+    files::{get_path, is_memory_store},
+    identity::FileId,
+    migrations, portable,
+    snapshot::SnapshotFile,
+    utils::sort_entries,
+};
 
 /// The name of the database
 const DB_NAME: &str = "store.db";
 
+/// How long a write waits for another invocation to finish writing to the store
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the store is only read, set by `set_read_only`
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the store is only read, from `read_only` or `--read-only`
+///
+/// The database is then opened read only, and what is cached about the entries,
+/// such as when they were last pasted, is left as it is instead of being updated
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+/// Whether the store is only read
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
 /// Establishes a connection to the database
-/// The database name is specified in the DB_NAME constant
+/// The database name is specified in the DB_NAME constant,
+/// unless the store is kept in memory
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn connect_to_db() -> Result<Connection, rusqlite::Error> {
-    Connection::open(get_path(DB_NAME))
+    if is_memory_store() {
+        return Connection::open_in_memory();
+    }
+    if is_read_only() {
+        return Connection::open_with_flags(
+            get_path(DB_NAME),
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        );
+    }
+    let conn = Connection::open(get_path(DB_NAME))?;
+    // Writes made while another invocation is writing wait for it, instead of failing
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+
+    Ok(conn)
 }
 
-#[derive(Iden)]
-enum Store {
+#[derive(Iden, Clone, Copy)]
+pub enum Store {
     Table,
     Id,
     Name,
@@ -27,6 +76,225 @@ enum Store {
     IsDir,
     AccessedAt,
     CreatedAt,
+    Hidden,
+    RespectIgnore,
+    Uuid,
+    Target,
+    FileType,
+    FileCount,
+    Size,
+    Device,
+    Inode,
+    BornAt,
+    Pinned,
+    Tags,
+}
+
+#[derive(Iden, Clone, Copy)]
+pub enum Snapshots {
+    Table,
+    Id,
+    Path,
+    Version,
+    Location,
+    CreatedAt,
+}
+
+#[derive(Iden, Clone, Copy)]
+pub enum SnapshotFiles {
+    Table,
+    Id,
+    SnapshotId,
+    Path,
+    Hash,
+}
+
+#[derive(Iden, Clone, Copy)]
+pub enum Blobs {
+    Table,
+    Hash,
+    Size,
+    Refs,
+}
+
+/// Deleted entries, kept until they are restored or purged
+/// Apart from `DeletedAt`, it shares the columns of the store
+#[derive(Iden, Clone, Copy)]
+pub enum Trash {
+    Table,
+    Id,
+    DeletedAt,
+}
+
+/// Every target an entry was pasted to
+#[derive(Iden, Clone, Copy)]
+pub enum PasteTargets {
+    Table,
+    Id,
+    Path,
+    EntryPath,
+    PastedAt,
+}
+
+/// The append-only record of the changes made to the store
+#[derive(Iden, Clone, Copy)]
+pub enum AuditLog {
+    Table,
+    Id,
+    Action,
+    User,
+    Paths,
+    At,
+}
+
+/// The ids the entries had when they were last listed
+#[derive(Iden, Clone, Copy)]
+pub enum ListedIds {
+    Table,
+    Id,
+    Uuid,
+    ListedAt,
+}
+
+/// A change made to the store, as recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Add,
+    Delete,
+    Clear,
+    /// Entries deleted as they were pasted, with `paste --delete`, `cut` or `pop`
+    Paste,
+    Restore,
+    /// Entries whose files were found again after being moved, with `verify --fix`
+    Relocate,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Add => "add",
+            AuditAction::Delete => "delete",
+            AuditAction::Clear => "clear",
+            AuditAction::Paste => "paste",
+            AuditAction::Restore => "restore",
+            AuditAction::Relocate => "relocate",
+        }
+    }
+}
+
+/// A change recorded in the audit log, by who and to which entries
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub id: i32,
+    /// What [`AuditAction::as_str`] was at the time
+    pub action: String,
+    pub user: String,
+    /// The paths of the entries that were changed
+    pub paths: Vec<String>,
+    pub at: DateTime<Local>,
+}
+
+/// Represents a snapshot of an entry
+/// ie a copy of the entry kept in the store directory
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub id: i32,
+    /// The path of the entry the snapshot was taken of
+    pub path: String,
+    /// Starts at 1 and increases with every snapshot of the same path
+    pub version: i32,
+    /// Where the copy of the entry is kept
+    pub location: String,
+    pub created_at: DateTime<Local>,
+}
+
+/// Formats a time the way it is stored in the database, as RFC3339 in UTC
+pub fn format_timestamp(time: &DateTime<Local>) -> String {
+    time.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Generates a new uuid to identify an entry
+pub fn new_uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// The current time, formatted the way it is stored in the database
+fn now_timestamp() -> String {
+    format_timestamp(&Local::now())
+}
+
+/// Parses a time stored in the database
+///
+/// Times are stored as RFC3339, but older versions of ynk stored them
+/// in chrono's default format with the local offset, so that is accepted too
+pub fn try_parse_timestamp(time: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(time)
+        .or_else(|_| DateTime::from_str(time))
+        .map(|t| t.with_timezone(&Local))
+        .ok()
+}
+
+/// Parses a time stored in the database,
+/// falling back to the unix epoch if it can't be parsed
+fn parse_timestamp(time: &str) -> DateTime<Local> {
+    try_parse_timestamp(time).unwrap_or_else(|| {
+        tracing::warn!(time, "Could not parse a stored time");
+        DateTime::<Utc>::UNIX_EPOCH.with_timezone(&Local)
+    })
+}
+
+/// The columns selected for every entry, in the order
+/// expected by `entry_from_row`
+fn entry_columns() -> [Store; 18] {
+    [
+        Store::Id,
+        Store::Name,
+        Store::Path,
+        Store::IsDir,
+        Store::AccessedAt,
+        Store::CreatedAt,
+        Store::Hidden,
+        Store::RespectIgnore,
+        Store::Uuid,
+        Store::Target,
+        Store::FileType,
+        Store::FileCount,
+        Store::Size,
+        Store::Device,
+        Store::Inode,
+        Store::BornAt,
+        Store::Pinned,
+        Store::Tags,
+    ]
+}
+
+/// Maps a row selected with `entry_columns` to an Entry
+fn entry_from_row(row: &Row) -> Result<Entry, rusqlite::Error> {
+    let accessed_at = parse_timestamp(&row.get::<_, String>(4)?);
+    let created_at = parse_timestamp(&row.get::<_, String>(5)?);
+
+    Ok(Entry {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        path: portable::from_stored(&row.get::<_, String>(2)?),
+        is_dir: row.get(3)?,
+        accessed_at,
+        created_at,
+        hidden: row.get(6)?,
+        respect_ignore: row.get(7)?,
+        uuid: row.get(8)?,
+        target: portable::from_stored_opt(row.get(9)?),
+        file_type: row.get(10)?,
+        file_count: row.get(11)?,
+        size: row.get(12)?,
+        file_id: FileId::from_stored(row.get(13)?, row.get(14)?, row.get(15)?),
+        pinned: row.get(16)?,
+        tags: row
+            .get::<_, Option<String>>(17)?
+            .map(|tags| tags.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+    })
 }
 
 /// Represents a Database Entry
@@ -36,13 +304,50 @@ pub struct Entry {
     pub id: i32,
     pub name: String,
     pub path: String,
-    /// Represents if an entry is a dir or not, left for legacy reasons
-    #[allow(dead_code)]
+    /// Represents if an entry is a dir or not
     pub is_dir: bool,
     pub accessed_at: DateTime<Local>,
-    /// The time the entry was created. Currently not in use anywhere.
-    #[allow(dead_code)]
+    /// The time the entry was added to the store
     pub created_at: DateTime<Local>,
+    /// Whether hidden files are discovered when pasting,
+    /// `None` for entries added before this was recorded
+    pub hidden: Option<bool>,
+    /// Whether the .gitignore is respected when pasting,
+    /// `None` for entries added before this was recorded
+    pub respect_ignore: Option<bool>,
+    /// Identifies the entry for good, unlike the id
+    /// which changes whenever the entries are renumbered
+    pub uuid: String,
+    /// Where the entry is pasted when no output is given
+    pub target: Option<String>,
+    /// The MIME type of the entry, `None` until it is detected
+    pub file_type: Option<String>,
+    /// The number of files pasted from the entry, `None` until it is counted
+    pub file_count: Option<i64>,
+    /// The size of the files in the entry in bytes, `None` until it is counted
+    pub size: Option<i64>,
+    /// The identity of the file the entry was added from,
+    /// `None` for entries added before this was recorded
+    pub file_id: Option<FileId>,
+    /// Whether the entry is kept by `clear --keep-pinned`
+    pub pinned: bool,
+    /// The tags the entry was given with `tag`, to select it by
+    pub tags: Vec<String>,
+}
+
+impl Entry {
+    /// The first few characters of the uuid, enough to tell entries apart
+    pub fn short_uuid(&self) -> &str {
+        &self.uuid[..self.uuid.len().min(8)]
+    }
+}
+
+/// An entry that was deleted from the store
+/// The id of the entry is its id in the trash
+#[derive(Debug, Clone)]
+pub struct DeletedEntry {
+    pub entry: Entry,
+    pub deleted_at: DateTime<Local>,
 }
 
 /// Builder struct that converts to an Entry
@@ -51,6 +356,10 @@ pub struct EntryBuilder {
     pub name: String,
     pub path: String,
     pub is_dir: bool,
+    pub hidden: bool,
+    pub respect_ignore: bool,
+    pub target: Option<String>,
+    pub file_type: Option<String>,
 }
 
 impl EntryBuilder {
@@ -59,30 +368,49 @@ impl EntryBuilder {
             name: name.to_string(),
             path: path.to_string(),
             is_dir,
+            hidden: false,
+            respect_ignore: true,
+            target: None,
+            file_type: None,
         }
     }
+
+    /// Sets the discovery options that are used
+    /// when the entry is pasted
+    pub fn with_discovery(mut self, hidden: bool, respect_ignore: bool) -> Self {
+        self.hidden = hidden;
+        self.respect_ignore = respect_ignore;
+        self
+    }
+
+    /// Sets where the entry is pasted when no output is given
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets the MIME type of the entry
+    pub fn with_file_type(mut self, file_type: String) -> Self {
+        self.file_type = Some(file_type);
+        self
+    }
 }
 
 /// Prepares the Database, creates all the tables and defines the schema
+/// by running the migrations that haven't been applied to it yet
+///
+/// # Returns
+///
+/// The number of migrations that were applied
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn prep_db(conn: &Connection) -> rusqlite::Result<usize, rusqlite::Error> {
-    let query = Table::create()
-        .table(Store::Table)
-        .if_not_exists()
-        .col(
-            ColumnDef::new(Store::Id)
-                .integer()
-                .not_null()
-                .auto_increment()
-                .primary_key(),
-        )
-        .col(ColumnDef::new(Store::Name).string().not_null())
-        .col(ColumnDef::new(Store::Path).string().not_null())
-        .col(ColumnDef::new(Store::IsDir).boolean().not_null())
-        .col(ColumnDef::new(Store::AccessedAt).date_time().not_null())
-        .col(ColumnDef::new(Store::CreatedAt).date_time().not_null())
-        .build(SqliteQueryBuilder);
+    migrations::run(conn)
+}
 
-    conn.execute(&query, [])
+/// The number of migrations the store is missing, which a read only store can't apply
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn pending_migrations(conn: &Connection) -> rusqlite::Result<usize, rusqlite::Error> {
+    migrations::pending(conn)
 }
 
 /// Inserts an entry into the database
@@ -97,8 +425,14 @@ pub fn prep_db(conn: &Connection) -> rusqlite::Result<usize, rusqlite::Error> {
 ///
 /// * `Entry` - The entry that was inserted into the database
 /// * `rusqlite::Error` - The error that was encountered while inserting into the database
+#[tracing::instrument(level = "debug", skip_all, fields(path = %eb.path))]
 pub fn insert_into_db(conn: &Connection, eb: EntryBuilder) -> Result<Entry, rusqlite::Error> {
-    let time_now = Local::now().to_string();
+    let time_now = now_timestamp();
+    let uuid = new_uuid();
+    let (dev, ino, born) = match FileId::of(Path::new(&eb.path)).map(FileId::to_stored) {
+        Some((dev, ino, born)) => (Some(dev), Some(ino), born),
+        None => (None, None, None),
+    };
 
     let query = Query::insert()
         .into_table(Store::Table)
@@ -108,13 +442,29 @@ pub fn insert_into_db(conn: &Connection, eb: EntryBuilder) -> Result<Entry, rusq
             Store::IsDir,
             Store::AccessedAt,
             Store::CreatedAt,
+            Store::Hidden,
+            Store::RespectIgnore,
+            Store::Uuid,
+            Store::Target,
+            Store::FileType,
+            Store::Device,
+            Store::Inode,
+            Store::BornAt,
         ])
         .values_panic([
             eb.name.clone().into(),
-            eb.path.clone().into(),
+            portable::to_stored(&eb.path).into(),
             eb.is_dir.into(),
             time_now.clone().into(),
             time_now.into(),
+            eb.hidden.into(),
+            eb.respect_ignore.into(),
+            uuid.clone().into(),
+            portable::to_stored_opt(&eb.target).into(),
+            eb.file_type.clone().into(),
+            dev.into(),
+            ino.into(),
+            born.into(),
         ])
         .to_string(SqliteQueryBuilder);
 
@@ -130,68 +480,13 @@ pub fn insert_into_db(conn: &Connection, eb: EntryBuilder) -> Result<Entry, rusq
         .expect("Failed to insert into database");
 
     let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::IsDir,
-            Store::AccessedAt,
-            Store::CreatedAt,
-        ])
+        .columns(entry_columns())
         .from(Store::Table)
-        .and_where(Expr::col(Store::Name).eq(eb.name))
+        .and_where(Expr::col(Store::Uuid).eq(uuid))
         .limit(1)
         .to_string(SqliteQueryBuilder);
 
-    conn.query_row(&query, [], |row| {
-        let accessed_at =
-            chrono::DateTime::from_str(row.get::<_, String>(4)?.as_str()).unwrap_or(Local::now());
-        let created_at =
-            chrono::DateTime::from_str(row.get::<_, String>(5)?.as_str()).unwrap_or(Local::now());
-
-        Ok(Entry {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            path: row.get(2)?,
-            is_dir: row.get(3)?,
-            accessed_at,
-            created_at,
-        })
-    })
-}
-
-/// Inserts an entry into the database
-///
-/// # Arguments
-///
-/// * `conn` - A reference to the database connection
-/// * `e` - An Entry struct
-///
-/// # Returns
-/// A Result enum with the following variants:
-///
-/// * `Entry` - The entry that was inserted into the database
-/// * `rusqlite::Error` - The error that was encountered while inserting into the database
-pub fn insert_entry(conn: &Connection, e: Entry) -> Result<usize, rusqlite::Error> {
-    let query = Query::insert()
-        .into_table(Store::Table)
-        .columns([
-            Store::Name,
-            Store::Path,
-            Store::IsDir,
-            Store::AccessedAt,
-            Store::CreatedAt,
-        ])
-        .values_panic([
-            e.name.clone().into(),
-            e.path.clone().into(),
-            e.is_dir.into(),
-            e.accessed_at.to_string().into(),
-            e.created_at.to_string().into(),
-        ])
-        .to_string(SqliteQueryBuilder);
-
-    conn.execute(&query, [])
+    conn.query_row(&query, [], entry_from_row)
 }
 
 /// Gets all the entries from the database
@@ -205,16 +500,10 @@ pub fn insert_entry(conn: &Connection, e: Entry) -> Result<usize, rusqlite::Erro
 ///
 /// * `Vec<Entry>` - A vector of all the entries in the database
 /// * `rusqlite::Error` - The error that was encountered while getting the entries from the database
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn get_all(conn: &Connection) -> Result<Vec<Entry>, rusqlite::Error> {
     let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::IsDir,
-            Store::AccessedAt,
-            Store::CreatedAt,
-        ])
+        .columns(entry_columns())
         .order_by(Store::Id, Order::Desc)
         .from(Store::Table)
         .to_string(SqliteQueryBuilder);
@@ -222,27 +511,82 @@ pub fn get_all(conn: &Connection) -> Result<Vec<Entry>, rusqlite::Error> {
     let mut stmt = conn.prepare(&query)?;
 
     let entries = stmt
-        .query_map([], |row| {
-            let accessed_at = chrono::DateTime::from_str(row.get::<_, String>(4)?.as_str())
-                .unwrap_or(Local::now());
-            let created_at = chrono::DateTime::from_str(row.get::<_, String>(5)?.as_str())
-                .unwrap_or(Local::now());
-
-            Ok(Entry {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                path: row.get(2)?,
-                is_dir: row.get(3)?,
-                accessed_at,
-                created_at,
-            })
-        })?
+        .query_map([], entry_from_row)?
         .map(|x| x.unwrap())
         .collect::<Vec<Entry>>();
 
     Ok(entries)
 }
 
+/// Counts the entries in the store, without reading them
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn count_entries(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let query = Query::select()
+        .expr(Func::count(Expr::col(Store::Id)))
+        .from(Store::Table)
+        .to_string(SqliteQueryBuilder);
+
+    conn.query_row(&query, [], |row| row.get::<_, i64>(0))
+        .map(|count| count as usize)
+}
+
+/// A summary of the store, read from what is cached without touching the filesystem
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub count: usize,
+    /// The name and path of the top entry, the one `pop` would paste
+    pub top: Option<(String, String)>,
+    /// The size of the entries whose size is cached, in bytes
+    pub total_size: i64,
+    /// How many entries have no cached size, so aren't in `total_size`
+    pub uncounted: usize,
+}
+
+/// Summarizes the store in two queries, for prompts that run on every command line
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn summary(conn: &Connection) -> Result<Summary, rusqlite::Error> {
+    let query = Query::select()
+        .expr(Func::count(Expr::col(Store::Id)))
+        .expr(Func::coalesce([
+            Func::sum(Expr::col(Store::Size)).into(),
+            Expr::val(0).into(),
+        ]))
+        .expr(Func::count(Expr::col(Store::Size)))
+        .from(Store::Table)
+        .to_string(SqliteQueryBuilder);
+    let (count, total_size, sized) = conn.query_row(&query, [], |row| {
+        Ok((
+            row.get::<_, i64>(0)? as usize,
+            row.get::<_, i64>(1)?,
+            row.get::<_, i64>(2)? as usize,
+        ))
+    })?;
+
+    let query = Query::select()
+        .columns([Store::Name, Store::Path])
+        .from(Store::Table)
+        .order_by(Store::Id, Order::Desc)
+        .limit(1)
+        .to_string(SqliteQueryBuilder);
+    let top = match conn.query_row(&query, [], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            portable::from_stored(&row.get::<_, String>(1)?),
+        ))
+    }) {
+        Ok(top) => Some(top),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(Summary {
+        count,
+        top,
+        total_size,
+        uncounted: count - sized,
+    })
+}
+
 /// Gets an entry from the database
 /// using the path of the file
 /// essentially checking if the file exists
@@ -265,40 +609,21 @@ pub fn get_all(conn: &Connection) -> Result<Vec<Entry>, rusqlite::Error> {
 /// To essentially check if an entry exists, the Error `rusqlite::Error::QueryReturnedNoRows` is
 /// returned if the entry does not exist in the database
 /// Otherwise, the entry can be essentially used as a normal entry
+#[tracing::instrument(level = "debug", skip(conn))]
 pub fn does_exist(conn: &Connection, path: &str) -> Result<Entry, rusqlite::Error> {
     let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::IsDir,
-            Store::AccessedAt,
-            Store::CreatedAt,
-        ])
+        .columns(entry_columns())
         .from(Store::Table)
-        .and_where(Expr::col(Store::Path).eq(path))
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
         .limit(1)
         .to_string(SqliteQueryBuilder);
 
-    conn.query_row(&query, [], |row| {
-        let accessed_at =
-            chrono::DateTime::from_str(row.get::<_, String>(4)?.as_str()).unwrap_or(Local::now());
-        let created_at =
-            chrono::DateTime::from_str(row.get::<_, String>(5)?.as_str()).unwrap_or(Local::now());
-
-        Ok(Entry {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            path: row.get(2)?,
-            is_dir: row.get(3)?,
-            accessed_at,
-            created_at,
-        })
-    })
+    conn.query_row(&query, [], entry_from_row)
 }
 
 /// Delete an entry from the database
 /// using the path of the file
+/// The entry is moved to the trash, from where it can be restored
 ///
 /// # Arguments
 ///
@@ -311,23 +636,146 @@ pub fn does_exist(conn: &Connection, path: &str) -> Result<Entry, rusqlite::Erro
 ///
 /// * `usize` - The number of rows that were deleted
 /// * `rusqlite::Error` - The error that was encountered while deleting the entry from the database
+#[tracing::instrument(level = "debug", skip(conn))]
 pub fn delete_entry(conn: &Connection, path: &str) -> Result<usize, rusqlite::Error> {
-    let query = Query::delete()
-        .from_table(Store::Table)
-        .and_where(Expr::col(Store::Path).eq(path))
-        .to_string(SqliteQueryBuilder);
+    delete_entries(conn, &[path])
+}
 
-    conn.execute(&query, [])
+/// Delete multiple entries from the database at once,
+/// so they can all be restored together
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `paths` - The paths of the entries
+///
+/// # Returns
+///
+/// A Result enum with the following variants:
+///
+/// * `usize` - The number of rows that were deleted
+/// * `rusqlite::Error` - The error that was encountered while deleting the entries from the database
+pub fn delete_entries(conn: &Connection, paths: &[&str]) -> Result<usize, rusqlite::Error> {
+    delete_entries_as(conn, paths, AuditAction::Delete)
+}
+
+/// Delete multiple entries from the database at once,
+/// recording them in the audit log as deleted by `action`
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn delete_entries_as(
+    conn: &Connection,
+    paths: &[&str],
+    action: AuditAction,
+) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let deleted = move_to_trash(
+        &tx,
+        Cond::all().add(
+            Expr::col(Store::Path).is_in(paths.iter().flat_map(|p| portable::stored_forms(p))),
+        ),
+        action,
+    )?;
+    tx.commit()?;
+
+    Ok(deleted)
 }
 
-/// Delete all the entries from the database
-/// Basically, it drops the table
+/// Which entries `clear_entries` removes from the store
+/// Left as the default, every entry is cleared
+#[derive(Debug, Default)]
+pub struct ClearFilter {
+    /// Leaves out this many of the most recently added entries
+    pub keep_newest: Option<usize>,
+    /// Only clears the entries with these ids
+    pub ids: Option<Vec<i32>>,
+    /// Only clears the entries added before this time
+    pub created_before: Option<DateTime<Local>>,
+    /// Only clears directories when true, or files when false, as they were stored
+    pub is_dir: Option<bool>,
+    /// Only clears the entries with this tag,
+    /// which [`ClearFilter::narrow_by_tag`] narrows `ids` down to
+    pub tag: Option<String>,
+    /// Leaves out the pinned entries
+    pub keep_pinned: bool,
+}
+
+impl ClearFilter {
+    /// Whether the filter clears every entry in the store
+    pub fn is_all(&self) -> bool {
+        self.keep_newest.is_none()
+            && self.ids.is_none()
+            && self.created_before.is_none()
+            && self.is_dir.is_none()
+            && self.tag.is_none()
+            && !self.keep_pinned
+    }
+
+    /// Narrows `ids` down to the entries with the tag, when `tag` is set
+    pub fn narrow_by_tag(&mut self, entries: &[Entry]) {
+        let Some(tag) = &self.tag else {
+            return;
+        };
+
+        let ids = entries
+            .iter()
+            .filter(|e| !matches!(&self.ids, Some(ids) if !ids.contains(&e.id)))
+            .filter(|e| e.tags.contains(tag))
+            .map(|e| e.id)
+            .collect();
+        self.ids = Some(ids);
+    }
+
+    fn condition(&self) -> Condition {
+        let mut cond = Cond::all();
+
+        if let Some(ids) = &self.ids {
+            cond = cond.add(Expr::col(Store::Id).is_in(ids.iter().copied()));
+        }
+        if let Some(before) = &self.created_before {
+            cond = cond.add(Expr::col(Store::CreatedAt).lt(format_timestamp(before)));
+        }
+        if self.keep_pinned {
+            cond = cond.add(Expr::col(Store::Pinned).eq(false));
+        }
+        // `is_dir` is only set for the directories added with `--dir`,
+        // so the others are told apart by the file type detected when they were added
+        if let Some(is_dir) = self.is_dir {
+            let dir = Cond::any().add(Expr::col(Store::IsDir).eq(true)).add(
+                // Entries added before the file type was detected have none
+                Expr::expr(Func::coalesce([
+                    Expr::col(Store::FileType).into(),
+                    Expr::val("").into(),
+                ]))
+                .eq("inode/directory"),
+            );
+            cond = cond.add(if is_dir { dir } else { dir.not() });
+        }
+        if let Some(n) = self.keep_newest {
+            cond = cond.add(
+                Expr::col(Store::Id).not_in_subquery(
+                    Query::select()
+                        .column(Store::Id)
+                        .from(Store::Table)
+                        .order_by(Store::Id, Order::Desc)
+                        .limit(n as u64)
+                        .to_owned(),
+                ),
+            );
+        }
+
+        cond
+    }
+}
+
+/// Delete the entries matching the filter from the database
 ///
-/// Better than deleting all the entries one by one
+/// The rows are moved to the trash in a single transaction,
+/// so either all of them are cleared or the store is left untouched
 ///
 /// # Arguments
 ///
 /// * `conn` - A reference to the database connection
+/// * `filter` - Which entries to clear
 ///
 /// # Returns
 ///
@@ -335,74 +783,1133 @@ pub fn delete_entry(conn: &Connection, path: &str) -> Result<usize, rusqlite::Er
 ///
 /// * `usize` - The number of rows that were deleted
 /// * `rusqlite::Error` - The error that was encountered while deleting the entries from the database
-pub fn delete_all(conn: &Connection) -> Result<usize, rusqlite::Error> {
-    let table_del = Table::drop()
-        .table(Store::Table)
-        .if_exists()
-        .to_string(SqliteQueryBuilder);
-
-    conn.execute(&table_del, [])?;
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn clear_entries(conn: &Connection, filter: &ClearFilter) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let deleted = move_to_trash(&tx, filter.condition(), AuditAction::Clear)?;
+    tx.commit()?;
 
-    //create the table again
-    //so that the program doesn't crash
-    //when trying to insert into the database
-    prep_db(conn)
+    Ok(deleted)
 }
 
-pub fn pop_one(conn: &Connection) -> Result<Entry, rusqlite::Error> {
+/// Moves the entries matching the condition from the store to the trash,
+/// all marked as deleted at the same time and recorded in the audit log as `action`
+fn move_to_trash(
+    conn: &Connection,
+    condition: Condition,
+    action: AuditAction,
+) -> Result<usize, rusqlite::Error> {
     let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::IsDir,
-            Store::AccessedAt,
-            Store::CreatedAt,
-        ])
-        .order_by(Store::Id, Order::Desc)
+        .column(Store::Path)
         .from(Store::Table)
-        .limit(1)
+        .cond_where(condition.clone())
         .to_string(SqliteQueryBuilder);
+    let mut stmt = conn.prepare(&query)?;
+    let paths = stmt
+        .query_map([], |row| {
+            Ok(portable::from_stored(&row.get::<_, String>(0)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    record_audit(
+        conn,
+        action,
+        &paths.iter().map(String::as_str).collect::<Vec<_>>(),
+    )?;
 
-    conn.query_row(&query, [], |row| {
-        let accessed_at =
-            chrono::DateTime::from_str(row.get::<_, String>(4)?.as_str()).unwrap_or(Local::now());
-        let created_at =
-            chrono::DateTime::from_str(row.get::<_, String>(5)?.as_str()).unwrap_or(Local::now());
-
-        Ok(Entry {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            path: row.get(2)?,
-            is_dir: row.get(3)?,
-            accessed_at,
-            created_at,
-        })
-    })
-}
+    let mut columns = entry_columns()[1..]
+        .iter()
+        .map(|c| c.into_iden())
+        .collect::<Vec<_>>();
 
-pub fn update_accessed_at(conn: &Connection, path: &str) -> Result<usize, rusqlite::Error> {
-    let time_now = Local::now().to_string();
+    let select = Query::select()
+        .columns(columns.clone())
+        .expr(Expr::val(now_timestamp()))
+        .from(Store::Table)
+        .cond_where(condition.clone())
+        .order_by(Store::Id, Order::Asc)
+        .to_owned();
 
-    let query = Query::update()
-        .table(Store::Table)
-        .values([(Store::AccessedAt, time_now.into())])
-        .and_where(Expr::col(Store::Path).eq(path))
+    columns.push(Trash::DeletedAt.into_iden());
+    let query = Query::insert()
+        .into_table(Trash::Table)
+        .columns(columns)
+        .select_from(select)
+        .expect("Trash columns should match the selected ones")
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    let query = Query::delete()
+        .from_table(Store::Table)
+        .cond_where(condition)
         .to_string(SqliteQueryBuilder);
 
     conn.execute(&query, [])
 }
 
-pub fn reid(conn: &Connection) -> Result<usize, rusqlite::Error> {
-    let mut entries = get_all(conn)?;
-    sort_entries(&mut entries);
-    entries.reverse();
+/// Gets all the deleted entries in the trash, latest first
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn get_trash(conn: &Connection) -> Result<Vec<DeletedEntry>, rusqlite::Error> {
+    let query = Query::select()
+        .columns(entry_columns())
+        .column(Trash::DeletedAt)
+        .from(Trash::Table)
+        .order_by(Trash::Id, Order::Desc)
+        .to_string(SqliteQueryBuilder);
 
-    delete_all(conn)?;
+    let mut stmt = conn.prepare(&query)?;
 
-    for e in entries {
-        insert_entry(conn, e)?;
-    }
+    let deleted = stmt
+        .query_map([], |row| {
+            Ok(DeletedEntry {
+                entry: entry_from_row(row)?,
+                deleted_at: parse_timestamp(&row.get::<_, String>(18)?),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(deleted)
+}
+
+/// Moves a deleted entry from the trash back into the store
+/// The entry keeps its uuid and times, but gets a new id
+///
+/// # Returns
+///
+/// A Result enum with the following variants:
+///
+/// * `Entry` - The entry as it is in the store again
+/// * `rusqlite::Error` - The error that was encountered while restoring the entry
+#[tracing::instrument(level = "debug", skip_all, fields(path = %deleted.entry.path))]
+pub fn restore_entry(conn: &Connection, deleted: &DeletedEntry) -> Result<Entry, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let e = &deleted.entry;
 
-    Ok(0)
+    let query = Query::insert()
+        .into_table(Store::Table)
+        .columns(entry_columns()[1..].to_vec())
+        .values_panic([
+            e.name.clone().into(),
+            portable::to_stored(&e.path).into(),
+            e.is_dir.into(),
+            format_timestamp(&e.accessed_at).into(),
+            format_timestamp(&e.created_at).into(),
+            e.hidden.into(),
+            e.respect_ignore.into(),
+            e.uuid.clone().into(),
+            portable::to_stored_opt(&e.target).into(),
+            e.file_type.clone().into(),
+            e.file_count.into(),
+            e.size.into(),
+            e.file_id.map(|id| id.to_stored().0).into(),
+            e.file_id.map(|id| id.to_stored().1).into(),
+            e.file_id.and_then(|id| id.to_stored().2).into(),
+            e.pinned.into(),
+            (!e.tags.is_empty()).then(|| e.tags.join(",")).into(),
+        ])
+        .to_string(SqliteQueryBuilder);
+    tx.execute(&query, [])?;
+
+    let query = Query::delete()
+        .from_table(Trash::Table)
+        .and_where(Expr::col(Trash::Id).eq(e.id))
+        .to_string(SqliteQueryBuilder);
+    tx.execute(&query, [])?;
+
+    let query = Query::select()
+        .columns(entry_columns())
+        .from(Store::Table)
+        .and_where(Expr::col(Store::Uuid).eq(e.uuid.clone()))
+        .limit(1)
+        .to_string(SqliteQueryBuilder);
+    let entry = tx.query_row(&query, [], entry_from_row)?;
+    record_audit(&tx, AuditAction::Restore, &[entry.path.as_str()])?;
+
+    tx.commit()?;
+
+    Ok(entry)
+}
+
+/// Permanently removes the entries that were deleted before the given time
+///
+/// # Returns
+///
+/// The number of entries that were purged
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn purge_trash(conn: &Connection, before: DateTime<Local>) -> Result<usize, rusqlite::Error> {
+    let query = Query::delete()
+        .from_table(Trash::Table)
+        .and_where(Expr::col(Trash::DeletedAt).lt(format_timestamp(&before)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Sets the last used id of the store table,
+/// so that the next inserted entry gets the id after it
+fn reset_id_sequence(conn: &Connection, last_id: usize) -> Result<usize, rusqlite::Error> {
+    let query = Query::update()
+        .table(Alias::new("sqlite_sequence"))
+        .value(Alias::new("seq"), last_id as i64)
+        .and_where(Expr::col(Alias::new("name")).eq(Store::Table.to_string()))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn pop_one(conn: &Connection) -> Result<Entry, rusqlite::Error> {
+    let query = Query::select()
+        .columns(entry_columns())
+        .order_by(Store::Id, Order::Desc)
+        .from(Store::Table)
+        .limit(1)
+        .to_string(SqliteQueryBuilder);
+
+    conn.query_row(&query, [], entry_from_row)
+}
+
+/// Marks the entry with the given path as just accessed,
+/// which is left as it is when the store is read only
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_accessed_at(conn: &Connection, path: &str) -> Result<usize, rusqlite::Error> {
+    if is_read_only() {
+        return Ok(0);
+    }
+    let time_now = now_timestamp();
+
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::AccessedAt, time_now.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Renumbers the entries so that their ids run from 1 without gaps
+///
+/// The ids are updated in place, oldest entry first, within a transaction,
+/// so a crash midway leaves the store as it was instead of losing entries
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn reid(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+
+    let mut entries = get_all(&tx)?;
+    sort_entries(&mut entries);
+    entries.reverse();
+
+    // Every new id is at most the old one, so going in ascending order
+    // an id is always free by the time it is assigned
+    for (i, e) in entries.iter().enumerate() {
+        let new_id = i as i32 + 1;
+        if e.id == new_id {
+            continue;
+        }
+
+        let query = Query::update()
+            .table(Store::Table)
+            .value(Store::Id, new_id)
+            .and_where(Expr::col(Store::Id).eq(e.id))
+            .to_string(SqliteQueryBuilder);
+        tx.execute(&query, [])?;
+    }
+
+    reset_id_sequence(&tx, entries.len())?;
+    tx.commit()?;
+
+    Ok(entries.len())
+}
+
+/// Pins or unpins the entry with the given path
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_pinned(
+    conn: &Connection,
+    path: &str,
+    pinned: bool,
+) -> Result<usize, rusqlite::Error> {
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::Pinned, pinned.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Sets the tags of the entry with the given path,
+/// which are stored separated by commas
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_tags(
+    conn: &Connection,
+    path: &str,
+    tags: &[String],
+) -> Result<usize, rusqlite::Error> {
+    let tags = (!tags.is_empty()).then(|| tags.join(","));
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::Tags, tags.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Caches the detected MIME type of the entry with the given path,
+/// unless the store is read only
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_file_type(
+    conn: &Connection,
+    path: &str,
+    file_type: &str,
+) -> Result<usize, rusqlite::Error> {
+    if is_read_only() {
+        return Ok(0);
+    }
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::FileType, file_type.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Caches the number of files pasted from the entry with the given path
+/// and their size in bytes, unless the store is read only
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_usage(
+    conn: &Connection,
+    path: &str,
+    count: i64,
+    size: i64,
+) -> Result<usize, rusqlite::Error> {
+    if is_read_only() {
+        return Ok(0);
+    }
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::FileCount, count.into()), (Store::Size, size.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Sets whether the entry with the given path is a directory
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_is_dir(
+    conn: &Connection,
+    path: &str,
+    is_dir: bool,
+) -> Result<usize, rusqlite::Error> {
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::IsDir, is_dir.into())])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Records the identity of the file of the entry with the given path,
+/// for the entries added before it was recorded
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn update_file_id(conn: &Connection, path: &str, id: FileId) -> Result<usize, rusqlite::Error> {
+    let (dev, ino, born) = id.to_stored();
+    let query = Query::update()
+        .table(Store::Table)
+        .values([
+            (Store::Device, dev.into()),
+            (Store::Inode, ino.into()),
+            (Store::BornAt, born.into()),
+        ])
+        .and_where(Expr::col(Store::Path).is_in(portable::stored_forms(path)))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Points the entry with the given path at where its file was moved to,
+/// along with its snapshots and paste targets, in a single transaction
+///
+/// # Returns
+///
+/// A Result enum with the following variants:
+///
+/// * `usize` - The number of entries that were changed
+/// * `rusqlite::Error` - The error that was encountered while updating the entry
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn relocate_entry(conn: &Connection, from: &str, to: &str) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let (from_forms, to_stored) = (portable::stored_forms(from), portable::to_stored(to));
+
+    let query = Query::update()
+        .table(Store::Table)
+        .values([(Store::Path, to_stored.clone().into())])
+        .and_where(Expr::col(Store::Path).is_in(from_forms.clone()))
+        .to_string(SqliteQueryBuilder);
+    let relocated = tx.execute(&query, [])?;
+
+    let query = Query::update()
+        .table(Snapshots::Table)
+        .values([(Snapshots::Path, to_stored.clone().into())])
+        .and_where(Expr::col(Snapshots::Path).is_in(from_forms.clone()))
+        .to_string(SqliteQueryBuilder);
+    tx.execute(&query, [])?;
+
+    let query = Query::update()
+        .table(PasteTargets::Table)
+        .values([(PasteTargets::EntryPath, to_stored.into())])
+        .and_where(Expr::col(PasteTargets::EntryPath).is_in(from_forms))
+        .to_string(SqliteQueryBuilder);
+    tx.execute(&query, [])?;
+
+    record_audit(&tx, AuditAction::Relocate, &[to])?;
+    tx.commit()?;
+
+    Ok(relocated)
+}
+
+/// Rotates the top `n` entries of the store, moving the `n`th entry
+/// from the top onto the top and the ones above it down by one
+///
+/// The entries only exchange ids, in a single transaction
+///
+/// # Returns
+///
+/// The number of entries that were rotated, less than `n`
+/// if the store doesn't have that many
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn roll(conn: &Connection, n: usize) -> Result<usize, rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+
+    let query = Query::select()
+        .column(Store::Id)
+        .from(Store::Table)
+        .order_by(Store::Id, Order::Desc)
+        .limit(n as u64)
+        .to_string(SqliteQueryBuilder);
+    let mut stmt = tx.prepare(&query)?;
+    let ids = stmt
+        .query_map([], |row| row.get::<_, i32>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    if ids.len() < 2 {
+        return Ok(ids.len());
+    }
+
+    // Every entry takes the id below it, and the bottom one the top id
+    let mut new_ids = ids.clone();
+    new_ids.rotate_left(1);
+
+    // Ids are unique, so they are moved out of the way first
+    let moves = ids
+        .iter()
+        .map(|id| (*id, -id))
+        .chain(
+            ids.iter()
+                .zip(new_ids.iter())
+                .map(|(old, new)| (-old, *new)),
+        )
+        .collect::<Vec<_>>();
+    for (old, new) in moves {
+        let query = Query::update()
+            .table(Store::Table)
+            .value(Store::Id, new)
+            .and_where(Expr::col(Store::Id).eq(old))
+            .to_string(SqliteQueryBuilder);
+        tx.execute(&query, [])?;
+    }
+
+    tx.commit()?;
+
+    Ok(ids.len())
+}
+
+/// Records a new snapshot of the entry with the given path
+/// The version is one more than the latest snapshot of the path
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `path` - The path of the entry the snapshot was taken of
+/// * `location` - Where the copy of the entry is kept
+///
+/// # Returns
+///
+/// A Result enum with the following variants:
+///
+/// * `Snapshot` - The snapshot that was inserted into the database
+/// * `rusqlite::Error` - The error that was encountered while inserting into the database
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn insert_snapshot(
+    conn: &Connection,
+    path: &str,
+    location: &str,
+) -> Result<Snapshot, rusqlite::Error> {
+    let version = get_snapshots(conn, path)?
+        .first()
+        .map(|s| s.version + 1)
+        .unwrap_or(1);
+    let created_at = Local::now();
+
+    let query = Query::insert()
+        .into_table(Snapshots::Table)
+        .columns([
+            Snapshots::Path,
+            Snapshots::Version,
+            Snapshots::Location,
+            Snapshots::CreatedAt,
+        ])
+        .values_panic([
+            portable::to_stored(path).into(),
+            version.into(),
+            location.into(),
+            format_timestamp(&created_at).into(),
+        ])
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    Ok(Snapshot {
+        id: conn.last_insert_rowid() as i32,
+        path: path.to_string(),
+        version,
+        location: location.to_string(),
+        created_at,
+    })
+}
+
+/// Gets all the snapshots of the entry with the given path
+/// ordered from the latest version to the oldest
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn get_snapshots(conn: &Connection, path: &str) -> Result<Vec<Snapshot>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([
+            Snapshots::Id,
+            Snapshots::Path,
+            Snapshots::Version,
+            Snapshots::Location,
+            Snapshots::CreatedAt,
+        ])
+        .from(Snapshots::Table)
+        .and_where(Expr::col(Snapshots::Path).is_in(portable::stored_forms(path)))
+        .order_by(Snapshots::Version, Order::Desc)
+        .to_string(SqliteQueryBuilder);
+
+    query_snapshots(conn, &query)
+}
+
+/// Gets all the snapshots in the database, of every entry
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn get_all_snapshots(conn: &Connection) -> Result<Vec<Snapshot>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([
+            Snapshots::Id,
+            Snapshots::Path,
+            Snapshots::Version,
+            Snapshots::Location,
+            Snapshots::CreatedAt,
+        ])
+        .from(Snapshots::Table)
+        .order_by(Snapshots::Id, Order::Asc)
+        .to_string(SqliteQueryBuilder);
+
+    query_snapshots(conn, &query)
+}
+
+fn query_snapshots(conn: &Connection, query: &str) -> Result<Vec<Snapshot>, rusqlite::Error> {
+    let mut stmt = conn.prepare(query)?;
+
+    let snapshots = stmt
+        .query_map([], |row| {
+            let created_at = parse_timestamp(&row.get::<_, String>(4)?);
+
+            Ok(Snapshot {
+                id: row.get(0)?,
+                path: portable::from_stored(&row.get::<_, String>(1)?),
+                version: row.get(2)?,
+                location: row.get(3)?,
+                created_at,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(snapshots)
+}
+
+/// Deletes a snapshot record using its id
+/// This does not remove the copy kept in the store directory
+pub fn delete_snapshot(conn: &Connection, id: i32) -> Result<usize, rusqlite::Error> {
+    let query = Query::delete()
+        .from_table(Snapshots::Table)
+        .and_where(Expr::col(Snapshots::Id).eq(id))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Records the files stored in a snapshot
+/// and adds a reference to the blob of each one
+#[tracing::instrument(level = "debug", skip(conn, files), fields(files = files.len()))]
+pub fn insert_snapshot_files(
+    conn: &Connection,
+    snapshot_id: i32,
+    files: &[SnapshotFile],
+) -> Result<(), rusqlite::Error> {
+    for file in files {
+        let query = Query::insert()
+            .into_table(SnapshotFiles::Table)
+            .columns([
+                SnapshotFiles::SnapshotId,
+                SnapshotFiles::Path,
+                SnapshotFiles::Hash,
+            ])
+            .values_panic([
+                snapshot_id.into(),
+                file.path.clone().into(),
+                file.hash.clone().into(),
+            ])
+            .to_string(SqliteQueryBuilder);
+        conn.execute(&query, [])?;
+
+        let query = Query::insert()
+            .into_table(Blobs::Table)
+            .columns([Blobs::Hash, Blobs::Size, Blobs::Refs])
+            .values_panic([file.hash.clone().into(), file.size.into(), 1.into()])
+            .on_conflict(
+                OnConflict::column(Blobs::Hash)
+                    .value(Blobs::Refs, Expr::col(Blobs::Refs).add(1))
+                    .to_owned(),
+            )
+            .to_string(SqliteQueryBuilder);
+        conn.execute(&query, [])?;
+    }
+
+    Ok(())
+}
+
+/// Points a file stored in a snapshot at the blob of its new hash,
+/// moving the reference it held on the blob of the old one
+#[tracing::instrument(level = "debug", skip(conn, file), fields(path = %file.path))]
+pub fn rehash_snapshot_file(
+    conn: &Connection,
+    snapshot_id: i32,
+    old_hash: &str,
+    file: &SnapshotFile,
+) -> Result<(), rusqlite::Error> {
+    let query = Query::update()
+        .table(SnapshotFiles::Table)
+        .value(SnapshotFiles::Hash, file.hash.clone())
+        .and_where(Expr::col(SnapshotFiles::SnapshotId).eq(snapshot_id))
+        .and_where(Expr::col(SnapshotFiles::Path).eq(file.path.clone()))
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    let query = Query::update()
+        .table(Blobs::Table)
+        .value(Blobs::Refs, Expr::col(Blobs::Refs).sub(1))
+        .and_where(Expr::col(Blobs::Hash).eq(old_hash))
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    let query = Query::insert()
+        .into_table(Blobs::Table)
+        .columns([Blobs::Hash, Blobs::Size, Blobs::Refs])
+        .values_panic([file.hash.clone().into(), file.size.into(), 1.into()])
+        .on_conflict(
+            OnConflict::column(Blobs::Hash)
+                .value(Blobs::Refs, Expr::col(Blobs::Refs).add(1))
+                .to_owned(),
+        )
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// Gets the hashes of the files stored in a snapshot
+pub fn get_snapshot_hashes(
+    conn: &Connection,
+    snapshot_id: i32,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let query = Query::select()
+        .column(SnapshotFiles::Hash)
+        .from(SnapshotFiles::Table)
+        .and_where(Expr::col(SnapshotFiles::SnapshotId).eq(snapshot_id))
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let hashes = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    Ok(hashes)
+}
+
+/// Gets the files stored in a snapshot, as their paths relative
+/// to the snapshot location along with the hashes of their contents
+pub fn get_snapshot_files(
+    conn: &Connection,
+    snapshot_id: i32,
+) -> Result<Vec<(String, String)>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([SnapshotFiles::Path, SnapshotFiles::Hash])
+        .from(SnapshotFiles::Table)
+        .and_where(Expr::col(SnapshotFiles::SnapshotId).eq(snapshot_id))
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let files = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(files)
+}
+
+/// Removes the records of the files stored in a snapshot
+/// and drops the reference they held to their blobs
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn release_snapshot_files(conn: &Connection, snapshot_id: i32) -> Result<(), rusqlite::Error> {
+    for hash in get_snapshot_hashes(conn, snapshot_id)? {
+        let query = Query::update()
+            .table(Blobs::Table)
+            .value(Blobs::Refs, Expr::col(Blobs::Refs).sub(1))
+            .and_where(Expr::col(Blobs::Hash).eq(hash))
+            .to_string(SqliteQueryBuilder);
+        conn.execute(&query, [])?;
+    }
+
+    let query = Query::delete()
+        .from_table(SnapshotFiles::Table)
+        .and_where(Expr::col(SnapshotFiles::SnapshotId).eq(snapshot_id))
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// Gets every blob along with its size and the number of references to it
+pub fn get_blobs(conn: &Connection) -> Result<Vec<(String, u64, i32)>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([Blobs::Hash, Blobs::Size, Blobs::Refs])
+        .from(Blobs::Table)
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let blobs = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(blobs)
+}
+
+/// Deletes the record of a blob
+/// This does not remove the blob from the blobs directory
+pub fn delete_blob(conn: &Connection, hash: &str) -> Result<usize, rusqlite::Error> {
+    let query = Query::delete()
+        .from_table(Blobs::Table)
+        .and_where(Expr::col(Blobs::Hash).eq(hash))
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Gets the total size of the files stored in all the snapshots,
+/// counting every copy of a file that is stored more than once
+pub fn snapshot_files_size(conn: &Connection) -> Result<u64, rusqlite::Error> {
+    let query = Query::select()
+        .expr(Func::coalesce([
+            Func::sum(Expr::col((Blobs::Table, Blobs::Size))).into(),
+            Expr::val(0).into(),
+        ]))
+        .from(SnapshotFiles::Table)
+        .inner_join(
+            Blobs::Table,
+            Expr::col((SnapshotFiles::Table, SnapshotFiles::Hash))
+                .equals((Blobs::Table, Blobs::Hash)),
+        )
+        .to_string(SqliteQueryBuilder);
+
+    conn.query_row(&query, [], |row| row.get(0))
+}
+
+/// Records that an entry was pasted to the target, unless the store is read only
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `target` - The absolute path of the directory the entry was pasted to
+/// * `entry_path` - The path of the entry
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn insert_paste_target(
+    conn: &Connection,
+    target: &str,
+    entry_path: &str,
+) -> Result<usize, rusqlite::Error> {
+    if is_read_only() {
+        return Ok(0);
+    }
+    let query = Query::insert()
+        .into_table(PasteTargets::Table)
+        .columns([
+            PasteTargets::Path,
+            PasteTargets::EntryPath,
+            PasteTargets::PastedAt,
+        ])
+        .values_panic([
+            portable::to_stored(target).into(),
+            portable::to_stored(entry_path).into(),
+            now_timestamp().into(),
+        ])
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// Gets the targets entries were pasted to, most recently used first
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `entry_path` - Only gets the targets of the entry with this path if given
+/// * `limit` - The maximum number of targets to get
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn get_recent_targets(
+    conn: &Connection,
+    entry_path: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let query = Query::select()
+        .column(PasteTargets::Path)
+        .from(PasteTargets::Table)
+        .and_where_option(
+            entry_path.map(|p| Expr::col(PasteTargets::EntryPath).is_in(portable::stored_forms(p))),
+        )
+        .group_by_col(PasteTargets::Path)
+        .order_by_expr(
+            Func::max(Expr::col(PasteTargets::PastedAt)).into(),
+            Order::Desc,
+        )
+        .limit(limit as u64)
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let targets = stmt
+        .query_map([], |row| {
+            Ok(portable::from_stored(&row.get::<_, String>(0)?))
+        })?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    Ok(targets)
+}
+
+/// An entry being pasted to a target, as recorded in the paste history
+#[derive(Debug, Clone)]
+pub struct PasteRecord {
+    /// The absolute path of the directory the entry was pasted to
+    pub target: String,
+    pub entry_path: String,
+    pub pasted_at: DateTime<Local>,
+}
+
+/// Gets every paste recorded, most recent first
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn get_paste_history(conn: &Connection) -> Result<Vec<PasteRecord>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([
+            PasteTargets::Path,
+            PasteTargets::EntryPath,
+            PasteTargets::PastedAt,
+        ])
+        .from(PasteTargets::Table)
+        .order_by(PasteTargets::PastedAt, Order::Desc)
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let history = stmt
+        .query_map([], |row| {
+            Ok(PasteRecord {
+                target: portable::from_stored(&row.get::<_, String>(0)?),
+                entry_path: portable::from_stored(&row.get::<_, String>(1)?),
+                pasted_at: parse_timestamp(&row.get::<_, String>(2)?),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(history)
+}
+
+/// Records a change to the store in the audit log, along with who made it,
+/// doing nothing when no entries were changed
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `action` - What was done to the entries
+/// * `paths` - The paths of the entries that were changed
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn record_audit(
+    conn: &Connection,
+    action: AuditAction,
+    paths: &[&str],
+) -> Result<usize, rusqlite::Error> {
+    if paths.is_empty() {
+        return Ok(0);
+    }
+
+    let query = Query::insert()
+        .into_table(AuditLog::Table)
+        .columns([
+            AuditLog::Action,
+            AuditLog::User,
+            AuditLog::Paths,
+            AuditLog::At,
+        ])
+        .values_panic([
+            action.as_str().into(),
+            current_user().into(),
+            paths.join("\n").into(),
+            now_timestamp().into(),
+        ])
+        .to_string(SqliteQueryBuilder);
+
+    conn.execute(&query, [])
+}
+
+/// The ids the entries had when they were last listed, by the uuid of each
+pub struct Listing {
+    pub uuids: HashMap<i32, String>,
+    pub at: DateTime<Local>,
+}
+
+/// Records the ids of the entries as they are listed, replacing the last listing,
+/// so that ranges read off `list` can be resolved as they were listed,
+/// unless the store is read only or the ids are the same as last listed
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `entries` - Every entry in the store
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn record_listing(conn: &Connection, entries: &[Entry]) -> Result<(), rusqlite::Error> {
+    if is_read_only() {
+        return Ok(());
+    }
+    let listed = get_listing(conn)?.map(|l| l.uuids).unwrap_or_default();
+    if listed.len() == entries.len()
+        && entries
+            .iter()
+            .all(|e| listed.get(&e.id).is_some_and(|uuid| *uuid == e.uuid))
+    {
+        return Ok(());
+    }
+
+    let query = Query::delete()
+        .from_table(ListedIds::Table)
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let now = now_timestamp();
+    let mut query = Query::insert();
+    query.into_table(ListedIds::Table).columns([
+        ListedIds::Id,
+        ListedIds::Uuid,
+        ListedIds::ListedAt,
+    ]);
+    for e in entries {
+        query.values_panic([e.id.into(), e.uuid.clone().into(), now.clone().into()]);
+    }
+    conn.execute(&query.to_string(SqliteQueryBuilder), [])?;
+
+    Ok(())
+}
+
+/// Gets the ids the entries had when they were last listed, if they ever were
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn get_listing(conn: &Connection) -> Result<Option<Listing>, rusqlite::Error> {
+    let query = Query::select()
+        .columns([ListedIds::Id, ListedIds::Uuid, ListedIds::ListedAt])
+        .from(ListedIds::Table)
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let Some((_, _, at)) = rows.first() else {
+        return Ok(None);
+    };
+    let at = parse_timestamp(at);
+
+    Ok(Some(Listing {
+        uuids: rows.into_iter().map(|(id, uuid, _)| (id, uuid)).collect(),
+        at,
+    }))
+}
+
+/// Gets the changes recorded in the audit log, latest first
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `limit` - The maximum number of changes to get, all of them if not given
+#[tracing::instrument(level = "debug", skip(conn))]
+pub fn get_audit_log(
+    conn: &Connection,
+    limit: Option<usize>,
+) -> Result<Vec<AuditRecord>, rusqlite::Error> {
+    let mut query = Query::select();
+    query
+        .columns([
+            AuditLog::Id,
+            AuditLog::Action,
+            AuditLog::User,
+            AuditLog::Paths,
+            AuditLog::At,
+        ])
+        .from(AuditLog::Table)
+        .order_by(AuditLog::Id, Order::Desc);
+    if let Some(limit) = limit {
+        query.limit(limit as u64);
+    }
+    let query = query.to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let records = stmt
+        .query_map([], |row| {
+            Ok(AuditRecord {
+                id: row.get(0)?,
+                action: row.get(1)?,
+                user: row.get(2)?,
+                paths: row
+                    .get::<_, String>(3)?
+                    .lines()
+                    .map(str::to_string)
+                    .collect(),
+                at: parse_timestamp(&row.get::<_, String>(4)?),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(records)
+}
+
+/// The user running ynk, as recorded in the audit log,
+/// along with the one who ran it through sudo
+fn current_user() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    match std::env::var("SUDO_USER") {
+        Ok(sudo_user) if sudo_user != user => format!("{} (sudo by {})", user, sudo_user),
+        _ => user,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add, store};
+
+    #[test]
+    fn store_changes_are_audited() {
+        let conn = store();
+
+        let entries = add(&conn, &["/home/me/notes.txt"]);
+        delete_entries(&conn, &[entries[0].path.as_str()]).unwrap();
+
+        let log = get_audit_log(&conn, None).unwrap();
+        let actions = log.iter().map(|r| r.action.as_str()).collect::<Vec<_>>();
+        assert_eq!(actions, ["delete", "add"]);
+        assert_eq!(log[0].paths, [entries[0].path.clone()]);
+
+        // The log can only be appended to
+        assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+        assert!(conn
+            .execute("UPDATE audit_log SET action = 'none'", [])
+            .is_err());
+    }
+
+    #[test]
+    fn clear_keeps_pinned_and_untagged_entries() {
+        let conn = store();
+
+        let entries = add(
+            &conn,
+            &["/home/me/notes.txt", "/home/me/todo.txt", "/home/me/.vimrc"],
+        );
+        update_pinned(&conn, &entries[0].path, true).unwrap();
+        update_tags(&conn, &entries[0].path, &["work".to_string()]).unwrap();
+        update_tags(&conn, &entries[1].path, &["work".to_string()]).unwrap();
+
+        let mut filter = ClearFilter {
+            tag: Some("work".to_string()),
+            keep_pinned: true,
+            ..Default::default()
+        };
+        filter.narrow_by_tag(&get_all(&conn).unwrap());
+        assert_eq!(clear_entries(&conn, &filter).unwrap(), 1);
+
+        let left = get_all(&conn).unwrap();
+        let mut paths = left.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, ["/home/me/.vimrc", "/home/me/notes.txt"]);
+        let pinned = left.iter().find(|e| e.pinned).unwrap();
+        assert_eq!(pinned.tags, ["work"]);
+    }
+
+    #[test]
+    fn clear_tells_dirs_and_files_apart_by_what_was_stored() {
+        let conn = store();
+
+        // Neither exists on disk, so only what was stored tells them apart
+        let entries = add(&conn, &["/gone/project", "/gone/notes.txt"]);
+        update_file_type(&conn, &entries[0].path, "inode/directory").unwrap();
+
+        let filter = ClearFilter {
+            is_dir: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(clear_entries(&conn, &filter).unwrap(), 1);
+
+        let left = get_all(&conn).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].path, "/gone/project");
+    }
+
+    #[test]
+    fn deleted_entries_are_restored_as_they_were() {
+        let conn = store();
+
+        let entries = add(&conn, &["/home/me/notes.txt"]);
+        update_pinned(&conn, &entries[0].path, true).unwrap();
+        update_tags(&conn, &entries[0].path, &["work".to_string()]).unwrap();
+        delete_entries(&conn, &[entries[0].path.as_str()]).unwrap();
+        assert!(get_all(&conn).unwrap().is_empty());
+
+        let trash = get_trash(&conn).unwrap();
+        let restored = restore_entry(&conn, &trash[0]).unwrap();
+        assert_eq!(restored.path, entries[0].path);
+        assert_eq!(restored.uuid, entries[0].uuid);
+        assert!(restored.pinned);
+        assert_eq!(restored.tags, ["work"]);
+        assert!(get_trash(&conn).unwrap().is_empty());
+    }
 }
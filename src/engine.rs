@@ -0,0 +1,710 @@
+//! The engine that adds entries to the store and pastes them
+//!
+//! Unlike the handlers, it doesn't prompt, print or exit,
+//! returning what happened instead, so it can be driven by tests
+
+use std::path::{Path, PathBuf};
+
+use hashbrown::{HashMap, HashSet};
+use indicatif::ProgressBar;
+use tokio::task;
+
+use crate::{
+    db::{self, Entry},
+    fs::Fs,
+    owner::Owner,
+    preserve::Preserve,
+    utils::{self, ListDirConfig},
+    ConstructedArgs,
+};
+
+/// A file copied from an entry to where it is pasted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasteFile {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// Move the source instead of copying it
+    pub move_source: bool,
+}
+
+/// The files to paste for the entries, worked out by [`plan`]
+#[derive(Debug, Default)]
+pub struct PastePlan {
+    pub files: Vec<PasteFile>,
+    /// The directory each entry is pasted to, by the path of the entry
+    pub entry_targets: Vec<(String, String)>,
+    /// The size of the files discovered in directories, in KB
+    pub size: f64,
+    /// The directories whose files are moved, removed once they are left empty
+    pub move_roots: Vec<PathBuf>,
+    /// The snapshots whose files are moved, by their id and location,
+    /// which [`finish`] forgets once they are emptied
+    pub moved_snapshots: Vec<(i32, PathBuf)>,
+    /// The paths in directories that couldn't be read due to their permissions
+    /// or aren't regular files, such as sockets
+    pub skipped: Vec<PathBuf>,
+    /// The symlinks in directories that are recreated rather than copied,
+    /// with [`SymlinkPolicy::Copy`](crate::symlinks::SymlinkPolicy::Copy)
+    pub links: Vec<PasteFile>,
+    /// The attributes kept on the pasted files
+    pub preserve: Preserve,
+    /// The mode set on the pasted files, with `--chmod`
+    pub file_mode: Option<u32>,
+    /// The mode set on the directories created for the pasted files, with `--dir-mode`
+    pub dir_mode: Option<u32>,
+    /// Who the pasted files and the directories created for them are given to
+    pub owner: Option<Owner>,
+    /// Whether the files the paste overwrites are given to the owner too,
+    /// only when it was asked for with `--chown`
+    pub chown_overwritten: bool,
+}
+
+/// What happened to the files of a [`PastePlan`]
+#[derive(Debug, Default)]
+pub struct PasteResult {
+    /// The targets that were written
+    pub pasted: Vec<PathBuf>,
+    /// The targets that already existed and were left as they were
+    pub conflicts: Vec<PathBuf>,
+    /// The targets that could not be written, with why
+    pub failed: Vec<(PathBuf, std::io::Error)>,
+    /// The sources that were skipped as their permissions kept them from being read
+    pub skipped: Vec<PathBuf>,
+}
+
+impl PasteResult {
+    /// Whether every file of the plan was pasted,
+    /// not counting those skipped due to their permissions
+    pub fn is_success(&self) -> bool {
+        self.conflicts.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// Why a paste couldn't be planned
+#[derive(Debug)]
+pub enum PlanError {
+    /// An entry doesn't have the snapshot version asked for
+    MissingVersion(String),
+    /// Two files would be pasted to the same target, as their sources and the target
+    Clash(PathBuf, PathBuf, PathBuf),
+}
+
+impl std::fmt::Display for PlanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanError::MissingVersion(e) => write!(f, "{}", e),
+            PlanError::Clash(first, second, target) => write!(
+                f,
+                "{} and {} would both be pasted to {}",
+                first.display(),
+                second.display(),
+                target.display()
+            ),
+        }
+    }
+}
+
+/// Adds the files to the store, keyed by the name each one is pasted as
+pub fn add(
+    conn: &rusqlite::Connection,
+    files: &HashMap<String, PathBuf>,
+    args: &ConstructedArgs,
+) -> Result<Vec<Entry>, rusqlite::Error> {
+    let entries = utils::construct_entry_builders(files, args.dir, args.all, args.ignore)
+        .into_iter()
+        .map(|x| x.with_target(args.target.clone()))
+        .map(|x| db::insert_into_db(conn, x))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    db::record_audit(conn, db::AuditAction::Add, &paths)?;
+
+    Ok(entries)
+}
+
+/// Works out the files to paste for the entries,
+/// with each entry pasted into the directory given by `target_of`
+///
+/// The files of a directory are pasted into it directly, or into a directory
+/// named after the entry for the entries added with `--dir`
+///
+/// With `move_files`, as `cut` does, the files are moved out of the sources of the entries.
+/// The entries pasted from a snapshot are moved out of it whenever they are deleted
+/// from the store afterwards, as with `paste --delete`, which leaves their sources alone
+///
+/// Fails if an entry doesn't have the snapshot version asked for,
+/// or if two files would be pasted to the same target
+pub fn plan<F: Fs>(
+    fs: &F,
+    conn: &rusqlite::Connection,
+    entries: &[Entry],
+    args: &ConstructedArgs,
+    target_of: impl Fn(&Entry) -> String,
+) -> Result<PastePlan, PlanError> {
+    let mut plan = PastePlan {
+        preserve: args.preserve,
+        file_mode: args.file_mode,
+        dir_mode: args.dir_mode,
+        owner: args.owner,
+        chown_overwritten: args.owner_explicit,
+        ..Default::default()
+    };
+
+    for e in entries {
+        let snapshot =
+            resolve_snapshot(conn, e, args.version).map_err(PlanError::MissingVersion)?;
+        let (source, move_source) = match snapshot {
+            Some(snap) => {
+                let location = PathBuf::from(&snap.location);
+                if args.delete {
+                    // The directory the snapshot was taken into goes along with it
+                    plan.move_roots
+                        .extend(location.parent().map(Path::to_path_buf));
+                    plan.moved_snapshots.push((snap.id, location.clone()));
+                }
+                (location, args.delete)
+            }
+            None => (PathBuf::from(&e.path), args.move_files),
+        };
+        let target = target_of(e);
+        plan.entry_targets.push((e.path.clone(), target.clone()));
+
+        let target = PathBuf::from(target);
+        if fs.is_dir(&source) {
+            let listing = fs.list_dir(&source, &entry_list_dir_config(args, e));
+            plan.size += listing.size;
+            plan.skipped.extend(listing.skipped);
+            let root = match e.is_dir {
+                true => target.join(&e.name),
+                false => target.clone(),
+            };
+            let paste_file = |x: &PathBuf| {
+                let (name, _) = utils::wrap_from_path(&source, x);
+                PasteFile {
+                    source: x.clone(),
+                    target: root.join(name),
+                    move_source,
+                }
+            };
+            plan.files.extend(listing.paths.iter().map(paste_file));
+            plan.links.extend(listing.links.iter().map(paste_file));
+            if move_source && !plan.move_roots.iter().any(|root| source.starts_with(root)) {
+                plan.move_roots.push(source);
+            }
+        } else {
+            plan.files.push(PasteFile {
+                source,
+                target: target.join(&e.name),
+                move_source,
+            });
+        }
+    }
+
+    let mut sources = HashMap::new();
+    for f in plan.files.iter().chain(&plan.links) {
+        if let Some(first) = sources.insert(&f.target, &f.source) {
+            return Err(PlanError::Clash(
+                first.clone(),
+                f.source.clone(),
+                f.target.clone(),
+            ));
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Finds the files of the plan that are larger than `threshold` bytes,
+/// along with their size, largest first
+///
+/// The files are looked up concurrently, as they are pasted
+pub async fn large_files<F: Fs>(fs: &F, plan: &PastePlan, threshold: u64) -> Vec<(PathBuf, u64)> {
+    let lookups = plan.files.iter().map(|f| async move {
+        match fs.metadata(&f.source).await {
+            Ok(metadata) if !metadata.is_dir && metadata.len > threshold => {
+                Some((f.source.clone(), metadata.len))
+            }
+            _ => None,
+        }
+    });
+
+    let mut large = futures::future::join_all(lookups)
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    large.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    large
+}
+
+/// Copies or moves the files of the plan concurrently, ticking `pb` for each one
+///
+/// Files that already exist are only replaced with `overwrite`,
+/// otherwise they are left as they are and reported as conflicts
+pub async fn paste<F: Fs>(
+    fs: &F,
+    plan: &PastePlan,
+    overwrite: bool,
+    pb: &ProgressBar,
+) -> PasteResult {
+    // Found before anything is pasted, as the pasting creates them
+    let created_dirs = missing_dirs(fs, plan).await;
+    let mut overwritten = HashSet::new();
+    if overwrite && plan.owner.is_some() && !plan.chown_overwritten {
+        for f in &plan.files {
+            if fs.exists(&f.target).await {
+                overwritten.insert(f.target.clone());
+            }
+        }
+    }
+
+    let tasks = plan.files.iter().map(|f| {
+        let (source, target, move_source) = (f.source.clone(), f.target.clone(), f.move_source);
+        let (fs, pb, preserve) = (fs.clone(), pb.clone(), plan.preserve);
+
+        // Spawn a new asynchronous task for each file copy operation
+        task::spawn(async move {
+            let copied = if move_source {
+                move_file(&fs, &source, &target, overwrite, preserve).await
+            } else {
+                copy_file(&fs, &source, &target, overwrite, preserve).await
+            };
+            if copied.is_ok() {
+                pb.inc(1);
+            }
+            // Only a source that can't be read is skipped, as a target that can't be
+            // written to has to fail the paste for its entry to be kept
+            let unreadable = match &copied {
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    !fs.is_readable(&source).await
+                }
+                _ => false,
+            };
+            (copied, unreadable)
+        })
+    });
+
+    let mut result = PasteResult::default();
+    let mut moved = Vec::new();
+    let copied = futures::future::join_all(tasks).await;
+    plan.files
+        .iter()
+        .zip(copied)
+        .for_each(|(f, copied)| match copied {
+            Ok((Ok(()), _)) => {
+                if f.move_source {
+                    moved.push(f.source.as_path());
+                }
+                result.pasted.push(f.target.clone())
+            }
+            Ok((Err(e), _)) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                result.conflicts.push(f.target.clone())
+            }
+            Ok((Err(_), true)) => result.skipped.push(f.source.clone()),
+            Ok((Err(e), false)) => result.failed.push((f.target.clone(), e)),
+            Err(e) => {
+                tracing::error!(error = %e, "Paste task failed");
+                result
+                    .failed
+                    .push((f.target.clone(), std::io::Error::other(e)));
+            }
+        });
+
+    let pasted_files = result.pasted.len();
+    // Links are quick to recreate, so they aren't worth spawning tasks for
+    for link in &plan.links {
+        match copy_link(fs, &link.source, &link.target, overwrite, link.move_source).await {
+            Ok(()) => {
+                pb.inc(1);
+                if link.move_source {
+                    moved.push(link.source.as_path());
+                }
+                result.pasted.push(link.target.clone())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                result.conflicts.push(link.target.clone())
+            }
+            Err(e) => result.failed.push((link.target.clone(), e)),
+        }
+    }
+
+    remove_empty_dirs(fs, &plan.move_roots, &moved).await;
+    // Set once the links are in place, so a directory mode can't keep them from being created
+    let chowned = result.pasted[..pasted_files]
+        .iter()
+        .filter(|p| !overwritten.contains(*p))
+        .cloned()
+        .collect::<Vec<_>>();
+    set_ownership(
+        fs,
+        plan,
+        &result.pasted[..pasted_files],
+        &chowned,
+        &created_dirs,
+    )
+    .await;
+
+    result
+}
+
+/// The directories the files of the plan are pasted into that don't exist yet,
+/// deepest first, when the plan sets an owner or a directory mode
+///
+/// Only these are given the owner and mode, so pasting into a directory
+/// that already holds other files leaves it and the ones above it as they were
+async fn missing_dirs<F: Fs>(fs: &F, plan: &PastePlan) -> Vec<PathBuf> {
+    if plan.owner.is_none() && plan.dir_mode.is_none() {
+        return Vec::new();
+    }
+
+    let mut missing = HashSet::new();
+    for f in plan.files.iter().chain(&plan.links) {
+        for dir in f.target.ancestors().skip(1) {
+            if missing.contains(dir) || fs.exists(dir).await {
+                break;
+            }
+            missing.insert(dir.to_path_buf());
+        }
+    }
+
+    let mut dirs = missing.into_iter().collect::<Vec<_>>();
+    dirs.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then(a.cmp(b))
+    });
+    dirs
+}
+
+/// Sets the owner and modes asked for on the pasted files and the directories created for them,
+/// where failing to is only worth a warning since the files made it
+///
+/// The owner is only given the files in `chowned`, which leaves out
+/// the files that were overwritten unless `--chown` was passed
+///
+/// The owner goes first, as changing it can clear the setuid and setgid bits of a mode.
+/// The directories go deepest first, so that one losing its
+/// execute permission doesn't keep those inside it from being set
+async fn set_ownership<F: Fs>(
+    fs: &F,
+    plan: &PastePlan,
+    pasted: &[PathBuf],
+    chowned: &[PathBuf],
+    created_dirs: &[PathBuf],
+) {
+    if plan.owner.is_none() && plan.file_mode.is_none() && plan.dir_mode.is_none() {
+        return;
+    }
+
+    if let Some(owner) = plan.owner {
+        for path in chowned.iter().chain(created_dirs) {
+            if let Err(e) = fs.set_owner(path, owner).await {
+                tracing::warn!(
+                    path = %path.display(),
+                    error = %e,
+                    "Could not set the owner of a file"
+                );
+            }
+        }
+    }
+
+    let mut modes = Vec::new();
+    if let Some(mode) = plan.file_mode {
+        modes.extend(pasted.iter().map(|p| (p.as_path(), mode)));
+    }
+    if let Some(mode) = plan.dir_mode {
+        modes.extend(created_dirs.iter().map(|dir| (dir.as_path(), mode)));
+    }
+    for (path, mode) in modes {
+        if let Err(e) = fs.set_mode(path, mode).await {
+            tracing::warn!(
+                path = %path.display(),
+                error = %e,
+                "Could not set the mode of {:o}",
+                mode
+            );
+        }
+    }
+}
+
+/// Removes the directories that moving files out of left empty,
+/// up to and including the directories of the moved entries
+async fn remove_empty_dirs<F: Fs>(fs: &F, roots: &[PathBuf], moved: &[&Path]) {
+    let mut dirs = moved
+        .iter()
+        .flat_map(|&p| p.ancestors().skip(1))
+        .filter(|dir| roots.iter().any(|root| dir.starts_with(root)))
+        .collect::<Vec<_>>();
+    // The deepest directories go first, so that their parents can be emptied
+    dirs.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then(a.cmp(b))
+    });
+    dirs.dedup();
+
+    for dir in dirs {
+        // Directories still holding ignored files or failed moves are left as they are
+        let _ = fs.remove_dir(dir).await;
+    }
+}
+
+/// Records where the entries were pasted and when,
+/// deleting them from the store afterwards with `delete`
+pub fn finish(
+    conn: &rusqlite::Connection,
+    entries: &[Entry],
+    plan: &PastePlan,
+    delete: bool,
+) -> Result<(), rusqlite::Error> {
+    record_paste_targets(conn, &plan.entry_targets)?;
+
+    // A snapshot that every file was moved out of is gone, while one that
+    // still has some is kept so that it is reported as broken
+    for (id, location) in &plan.moved_snapshots {
+        if !location.exists() {
+            db::release_snapshot_files(conn, *id)?;
+            db::delete_snapshot(conn, *id)?;
+        }
+    }
+
+    for e in entries {
+        db::update_accessed_at(conn, e.path.as_str())?;
+    }
+    if delete {
+        let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        db::delete_entries_as(conn, &paths, db::AuditAction::Paste)?;
+        // Reid all the remaining files
+        db::reid(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Remembers the targets the entries were pasted to,
+/// so they can be pasted there again with `--to-recent`
+fn record_paste_targets(
+    conn: &rusqlite::Connection,
+    entry_targets: &[(String, String)],
+) -> Result<(), rusqlite::Error> {
+    for (path, target) in entry_targets {
+        let Ok(target) = PathBuf::from(target).canonicalize() else {
+            continue;
+        };
+        db::insert_paste_target(conn, &target.to_string_lossy(), path)?;
+    }
+
+    Ok(())
+}
+
+/// Finds the path that an entry is pasted from
+///
+/// Entries with snapshots are pasted from the requested version,
+/// or the latest one, while the rest are pasted from their source
+pub fn resolve_source(
+    conn: &rusqlite::Connection,
+    entry: &Entry,
+    version: Option<i32>,
+) -> Result<PathBuf, String> {
+    Ok(match resolve_snapshot(conn, entry, version)? {
+        Some(snap) => PathBuf::from(&snap.location),
+        None => PathBuf::from(&entry.path),
+    })
+}
+
+/// Finds the snapshot that an entry is pasted from, as [`resolve_source`] does,
+/// `None` for the entries without snapshots
+pub fn resolve_snapshot(
+    conn: &rusqlite::Connection,
+    entry: &Entry,
+    version: Option<i32>,
+) -> Result<Option<db::Snapshot>, String> {
+    let snapshots = db::get_snapshots(conn, &entry.path).expect("Could not get snapshots");
+
+    match version {
+        Some(version) => match snapshots.into_iter().find(|s| s.version == version) {
+            Some(snap) => Ok(Some(snap)),
+            None => Err(format!(
+                "Entry {} has no snapshot version {}",
+                entry.name, version
+            )),
+        },
+        None => Ok(snapshots.into_iter().next()),
+    }
+}
+
+/// Builds the discovery config for an entry
+///
+/// The options recorded when the entry was added are used,
+/// unless the matching flag was explicitly passed on the command line
+/// or the entry predates them
+pub fn entry_list_dir_config(args: &ConstructedArgs, entry: &Entry) -> ListDirConfig {
+    let hidden = match entry.hidden {
+        Some(hidden) if !args.all_explicit => hidden,
+        _ => args.all,
+    };
+    let respect_ignore = match entry.respect_ignore {
+        Some(respect_ignore) if !args.ignore_explicit => respect_ignore,
+        _ => args.ignore,
+    };
+
+    ListDirConfig {
+        filter_file: !args.dir,
+        full_path: false,
+        strict: args.strict,
+        hidden,
+        respect_ignore,
+        symlinks: args.symlinks,
+    }
+}
+
+/// The async function in charge of copying a file
+/// from the source to the target, creating the directories it is in
+///
+/// Pasting copies every file of the entries concurrently with this,
+/// which makes it a parallelized version of the `cp` command
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.display(), target = %target.display()),
+    err
+)]
+pub async fn copy_file<F: Fs>(
+    fs: &F,
+    source: &Path,
+    target: &Path,
+    overwrite: bool,
+    preserve: Preserve,
+) -> Result<(), std::io::Error> {
+    if !overwrite && fs.exists(target).await {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    fs.create_dir_all(target.parent().unwrap()).await?;
+
+    fs.copy(source, target).await?;
+    keep_attributes(fs, source, target, preserve).await;
+
+    Ok(())
+}
+
+/// Copies the attributes asked for over to a copied file,
+/// where failing to is only worth a warning since the contents made it
+async fn keep_attributes<F: Fs>(fs: &F, source: &Path, target: &Path, preserve: Preserve) {
+    if !preserve.any() {
+        return;
+    }
+
+    if let Err(e) = fs.copy_attributes(source, target, preserve).await {
+        tracing::warn!(
+            target = %target.display(),
+            error = %e,
+            "Could not keep the attributes of a file"
+        );
+    }
+}
+
+/// Moves a file from the source to the target, creating the directories it is in
+///
+/// Across filesystems, where it can't just be renamed, the file is copied
+/// and the source is only removed once the hash of the copy matches it.
+/// So is a file with other hard links, as the files of snapshots have to their blobs,
+/// which would otherwise share its contents with them once pasted
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.display(), target = %target.display()),
+    err
+)]
+pub async fn move_file<F: Fs>(
+    fs: &F,
+    source: &Path,
+    target: &Path,
+    overwrite: bool,
+    preserve: Preserve,
+) -> Result<(), std::io::Error> {
+    if !overwrite && fs.exists(target).await {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    fs.create_dir_all(target.parent().unwrap()).await?;
+
+    if fs.metadata(source).await?.links > 1 {
+        tracing::debug!("the source has other hard links, copying instead");
+    } else {
+        match fs.rename(source, target).await {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                tracing::debug!("moving across filesystems, copying instead");
+            }
+            renamed => return renamed,
+        }
+    }
+
+    fs.copy(source, target).await?;
+    let (copied, original) = futures::future::try_join(fs.hash(target), fs.hash(source)).await?;
+    if copied != original {
+        return Err(std::io::Error::other(format!(
+            "the copy of {} doesn't match it, so it was kept",
+            source.display()
+        )));
+    }
+    // A rename keeps every attribute, which a copy has to be asked to
+    keep_attributes(fs, source, target, preserve).await;
+
+    fs.remove_file(source).await
+}
+
+/// Recreates a symlink at the target, pointing to the same path as the source,
+/// or moves it with `move_source`
+///
+/// A relative link is kept relative, so it may point elsewhere from the target
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(source = %source.display(), target = %target.display()),
+    err
+)]
+pub async fn copy_link<F: Fs>(
+    fs: &F,
+    source: &Path,
+    target: &Path,
+    overwrite: bool,
+    move_source: bool,
+) -> Result<(), std::io::Error> {
+    if fs.exists(target).await {
+        if !overwrite {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target.display()),
+            ));
+        }
+        // Unlike a write, creating a link doesn't replace what is there
+        fs.remove_file(target).await?;
+    }
+
+    fs.create_dir_all(target.parent().unwrap()).await?;
+
+    if move_source {
+        match fs.rename(source, target).await {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {}
+            renamed => return renamed,
+        }
+    }
+
+    let original = fs.read_link(source).await?;
+    fs.symlink(&original, target).await?;
+    if move_source {
+        fs.remove_file(source).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! This module contains the exit codes of ynk,
+//! so that wrappers and scripts can tell why a command failed
+//!
+//! Errors in the command line itself are exited with 2 by clap,
+//! the same code as [`ExitCode::Usage`]
+
+/// The code ynk exits with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Everything went as asked
+    Success = 0,
+    /// An unexpected error, such as a file or the store not being readable
+    Failure = 1,
+    /// The arguments don't make sense, such as an invalid range or glob
+    Usage = 2,
+    /// Nothing matched the queries, or there was nothing to act on
+    NotFound = 3,
+    /// An existing file or a limit was in the way
+    Conflict = 4,
+    /// Some of the files could not be pasted
+    PartialFailure = 5,
+    /// A prompt was cancelled
+    Cancelled = 6,
+}
+
+impl ExitCode {
+    /// Exits right away with the code, for errors deep within a command
+    pub fn exit(self) -> ! {
+        crate::files::remove_memory_store();
+        std::process::exit(self as i32)
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
+    }
+}
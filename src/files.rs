@@ -1,19 +1,98 @@
 //! This module contains functions related to files and directories
 //! It has functions to get the store and config paths
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
 
 use dirs::{config_dir, home_dir};
 
+use crate::utils::absolute_path;
+
 const NAME: &str = "ynk";
 
+/// The environment variable that overrides the store path
+pub const STORE_PATH_ENV: &str = "YNK_STORE_PATH";
+
+/// The store path that keeps the database in memory
+pub const MEMORY_STORE: &str = ":memory:";
+
+/// The store path set by `set_store_path`
+static STORE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Whether the store is kept in memory, set by `set_store_path`
+static IN_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// This function sets where the store is kept, from `--store`,
+/// the `YNK_STORE_PATH` environment variable or else the
+/// `store_path` set in the config
+///
+/// `:memory:` keeps the database in memory, with the snapshots
+/// and logs in a temporary directory, all gone once ynk exits
+///
+/// It has to be called before the store is used,
+/// later calls are ignored
+pub fn set_store_path(flag: Option<&str>, configured: Option<&str>) {
+    let path = flag
+        .map(|p| p.to_string())
+        .or_else(|| std::env::var(STORE_PATH_ENV).ok().filter(|p| !p.is_empty()))
+        .or_else(|| configured.map(|p| p.to_string()));
+
+    match path.as_deref() {
+        Some(MEMORY_STORE) => {
+            let temp = std::env::temp_dir().join(format!("{}-{}", NAME, uuid::Uuid::new_v4()));
+            if STORE_PATH.set(temp).is_ok() {
+                IN_MEMORY.store(true, Ordering::Relaxed);
+            }
+        }
+        Some(path) => {
+            let _ = STORE_PATH.set(absolute_path(path));
+        }
+        None => {}
+    }
+}
+
+/// This function returns whether the store is kept in memory
+pub fn is_memory_store() -> bool {
+    IN_MEMORY.load(Ordering::Relaxed)
+}
+
+/// This function removes the temporary directory
+/// of an in-memory store, doing nothing otherwise
+pub fn remove_memory_store() {
+    if is_memory_store() {
+        let _ = std::fs::remove_dir_all(get_store_path());
+    }
+}
+
+/// Removes the temporary directory of an in-memory store once dropped,
+/// so it is gone however ynk returns from main, even on a panic
+///
+/// [`crate::exit::ExitCode::exit`] doesn't run destructors, so it removes the directory itself
+pub struct MemoryStoreGuard;
+
+impl Drop for MemoryStoreGuard {
+    fn drop(&mut self) {
+        remove_memory_store();
+    }
+}
+
 /// This function returns the path to the store directory
-/// ie the directory where all the files related to ynk are stored
+/// ie the directory where all the files related to ynk are stored,
+/// `~/.ynk` unless it was set by `set_store_path`
 ///
 /// # Panics
 ///
 /// This function panics if it fails to get the home directory
 pub fn get_store_path() -> PathBuf {
+    if let Some(path) = STORE_PATH.get() {
+        return path.clone();
+    }
+
     let home_path = home_dir().expect("Failed to get home directory");
     home_path.join(PathBuf::from(".".to_owned() + NAME))
 }
@@ -45,6 +124,24 @@ pub fn get_config_path() -> PathBuf {
     config_path.join("./config.toml")
 }
 
+/// This function returns the path to the directory
+/// where the snapshots of entries are kept
+pub fn get_snapshots_path() -> PathBuf {
+    get_store_path().join("snapshots")
+}
+
+/// This function returns the path to the directory
+/// where the deduplicated contents of snapshot files are kept
+pub fn get_blobs_path() -> PathBuf {
+    get_store_path().join("blobs")
+}
+
+/// This function returns the path to the directory
+/// where the log files are written
+pub fn get_logs_path() -> PathBuf {
+    get_store_path().join("logs")
+}
+
 /// This function returns the path to the file in the store directory
 /// basically it joins the path passed to it with the store directory
 pub fn get_path(path: &str) -> PathBuf {
@@ -0,0 +1,653 @@
+//! The filesystem the engine discovers and copies files with
+//!
+//! [`RealFs`] is the actual filesystem, while [`MemoryFs`] keeps
+//! everything in memory so the engine can be tested without touching
+//! the disk. Other backends, such as an archive writer, can implement [`Fs`],
+//! while the ways of copying on the actual filesystem only implement [`Copier`]
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use hashbrown::HashMap;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    owner::Owner,
+    preserve::{self, Preserve},
+    utils::{self, DirListing, ListDirConfig},
+};
+
+/// What is known about a file or directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// The size in bytes, 0 for directories
+    pub len: u64,
+    pub is_dir: bool,
+    /// The number of hard links to it, 1 where they aren't counted
+    pub links: u64,
+}
+
+/// The operations the engine needs from a filesystem
+///
+/// Discovery is synchronous, like the walk it is based on,
+/// while the copying is done concurrently and is async
+pub trait Fs: Clone + Send + Sync + 'static {
+    fn read(&self, path: &Path) -> impl Future<Output = io::Result<Vec<u8>>> + Send;
+
+    fn write(&self, path: &Path, contents: Vec<u8>) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Copies a file over to another path, replacing what is there
+    ///
+    /// By default the file is read whole and written back,
+    /// which backends that can copy without going through memory override
+    fn copy(&self, from: &Path, to: &Path) -> impl Future<Output = io::Result<()>> + Send {
+        async move {
+            let contents = self.read(from).await?;
+            self.write(to, contents).await
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Hashes the contents of a file, as [`utils::hash_file`] does
+    ///
+    /// By default the file is read whole,
+    /// which backends that can stream it override
+    fn hash(&self, path: &Path) -> impl Future<Output = io::Result<String>> + Send {
+        async move {
+            let contents = self.read(path).await?;
+            Ok(blake3::hash(&contents).to_hex().to_string())
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> impl Future<Output = io::Result<Metadata>> + Send;
+
+    /// Moves a file or directory, failing with [`io::ErrorKind::CrossesDevices`]
+    /// when the two paths are on different filesystems
+    fn rename(&self, from: &Path, to: &Path) -> impl Future<Output = io::Result<()>> + Send;
+
+    fn remove_file(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Removes a directory, which has to be empty
+    fn remove_dir(&self, path: &Path) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Whether anything exists at the path
+    fn exists(&self, path: &Path) -> impl Future<Output = bool> + Send {
+        async move { self.metadata(path).await.is_ok() }
+    }
+
+    /// Whether the file at the path can be opened for reading,
+    /// which by default is whether it exists, for backends without permissions
+    fn is_readable(&self, path: &Path) -> impl Future<Output = bool> + Send {
+        self.exists(path)
+    }
+
+    /// Reads the path a symlink points to,
+    /// failing with [`io::ErrorKind::Unsupported`] on backends without links
+    fn read_link(&self, path: &Path) -> impl Future<Output = io::Result<PathBuf>> + Send {
+        async move { Err(no_links(path)) }
+    }
+
+    /// Creates a symlink at `link` pointing to `original`,
+    /// failing with [`io::ErrorKind::Unsupported`] on backends without links
+    fn symlink(
+        &self,
+        _original: &Path,
+        link: &Path,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async move { Err(no_links(link)) }
+    }
+
+    /// Copies the attributes asked for from one file to another,
+    /// doing nothing on backends without them
+    fn copy_attributes(
+        &self,
+        _from: &Path,
+        _to: &Path,
+        _preserve: Preserve,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Sets the Unix permissions of a file or directory, such as `0o644`,
+    /// doing nothing on backends without them
+    fn set_mode(&self, _path: &Path, _mode: u32) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Gives a file or directory to another user or group,
+    /// doing nothing on backends without owners
+    fn set_owner(
+        &self,
+        _path: &Path,
+        _owner: Owner,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Lists the files in a directory along with their total size in KB
+    /// and the paths it skipped, as [`utils::walk_dir`] does
+    fn list_dir(&self, path: &Path, config: &ListDirConfig) -> DirListing;
+}
+
+fn no_links(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} can't be a link on this filesystem", path.display()),
+    )
+}
+
+/// The actual filesystem, through `tokio::fs`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+
+    async fn write(&self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        tokio::fs::write(path, contents).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        KernelCopy.copy(from, to).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    async fn hash(&self, path: &Path) -> io::Result<String> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || utils::hash_file(&path))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    async fn is_readable(&self, path: &Path) -> bool {
+        tokio::fs::File::open(path).await.is_ok()
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = tokio::fs::metadata(path).await?;
+        #[cfg(unix)]
+        let links = std::os::unix::fs::MetadataExt::nlink(&metadata);
+        #[cfg(not(unix))]
+        let links = 1;
+
+        Ok(Metadata {
+            len: metadata.len(),
+            is_dir: metadata.is_dir(),
+            links,
+        })
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::rename(from, to).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        tokio::fs::remove_dir(path).await
+    }
+
+    async fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        tokio::fs::read_link(path).await
+    }
+
+    #[cfg(unix)]
+    async fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        tokio::fs::symlink(original, link).await
+    }
+
+    /// Windows has separate links for files and directories,
+    /// so the one made depends on what the original is
+    #[cfg(windows)]
+    async fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        // A relative original is relative to the directory of the link
+        let resolved = link.parent().unwrap_or(link).join(original);
+        if tokio::fs::metadata(&resolved)
+            .await
+            .is_ok_and(|m| m.is_dir())
+        {
+            tokio::fs::symlink_dir(original, link).await
+        } else {
+            tokio::fs::symlink_file(original, link).await
+        }
+    }
+
+    async fn copy_attributes(&self, from: &Path, to: &Path, preserve: Preserve) -> io::Result<()> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        tokio::task::spawn_blocking(move || preserve::copy_attributes(&from, &to, preserve))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    #[cfg(unix)]
+    async fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+    }
+
+    /// Windows only has the read-only flag, which is set when the mode lacks
+    /// the write permission of the owner
+    #[cfg(windows)]
+    async fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut permissions = tokio::fs::metadata(path).await?.permissions();
+        permissions.set_readonly(mode & 0o200 == 0);
+        tokio::fs::set_permissions(path, permissions).await
+    }
+
+    #[cfg(unix)]
+    async fn set_owner(&self, path: &Path, owner: Owner) -> io::Result<()> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || std::os::unix::fs::chown(path, owner.uid, owner.gid))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn list_dir(&self, path: &Path, config: &ListDirConfig) -> DirListing {
+        utils::walk_dir(path.to_str().unwrap(), config)
+    }
+}
+
+/// How files are copied while pasting, from `copy_backend`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CopyBackend {
+    /// tokio, which copies in the kernel where the platform allows
+    #[default]
+    Tokio,
+    /// io_uring, with the `io-uring` feature on Linux
+    IoUring,
+}
+
+impl FromStr for CopyBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokio" => Ok(CopyBackend::Tokio),
+            "io_uring" => Ok(CopyBackend::IoUring),
+            _ => Err(format!("\"{}\" is not one of tokio or io_uring", s)),
+        }
+    }
+}
+
+/// A way of copying files on the actual filesystem
+pub trait Copier: Clone + Send + Sync + 'static {
+    /// Copies a file over to another path, replacing what is there
+    /// and keeping its permissions like `std::fs::copy`
+    fn copy(&self, from: &Path, to: &Path) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Copies in the kernel where it can, with `copy_file_range` on Linux,
+/// `fclonefileat` on macOS and `CopyFileEx` on Windows
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KernelCopy;
+
+impl Copier for KernelCopy {
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        tokio::fs::copy(from, to).await.map(|_| ())
+    }
+}
+
+/// The actual filesystem, copying files with a [`Copier`]
+/// while everything else goes through [`RealFs`]
+#[derive(Debug, Clone)]
+pub struct CopyingFs<C>(pub C);
+
+impl<C: Copier> Fs for CopyingFs<C> {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        RealFs.read(path).await
+    }
+
+    async fn write(&self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        RealFs.write(path, contents).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.0.copy(from, to).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        RealFs.create_dir_all(path).await
+    }
+
+    async fn hash(&self, path: &Path) -> io::Result<String> {
+        RealFs.hash(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        RealFs.metadata(path).await
+    }
+
+    async fn is_readable(&self, path: &Path) -> bool {
+        RealFs.is_readable(path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        RealFs.rename(from, to).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_file(path).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_dir(path).await
+    }
+
+    async fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        RealFs.read_link(path).await
+    }
+
+    async fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        RealFs.symlink(original, link).await
+    }
+
+    async fn copy_attributes(&self, from: &Path, to: &Path, preserve: Preserve) -> io::Result<()> {
+        RealFs.copy_attributes(from, to, preserve).await
+    }
+
+    async fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        RealFs.set_mode(path, mode).await
+    }
+
+    async fn set_owner(&self, path: &Path, owner: Owner) -> io::Result<()> {
+        RealFs.set_owner(path, owner).await
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        RealFs.is_dir(path)
+    }
+
+    fn list_dir(&self, path: &Path, config: &ListDirConfig) -> DirListing {
+        RealFs.list_dir(path, config)
+    }
+}
+
+/// The largest buffer copies can be streamed through, in bytes,
+/// as one is allocated for every file that is copied at once
+pub const MAX_BUFFER_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Parses the size of the buffer copies are streamed through, ex: "4MB",
+/// which has to be between 1B and [`MAX_BUFFER_SIZE`]
+pub fn parse_buffer_size(size: &str) -> Result<usize, String> {
+    match utils::parse_size(size) {
+        None => Err(format!("\"{}\" is not a size, ex: \"4MB\"", size)),
+        Some(0) => Err("it has to be at least 1B".to_string()),
+        Some(bytes) if bytes > MAX_BUFFER_SIZE => Err(format!("\"{}\" is more than 64MB", size)),
+        Some(bytes) => Ok(bytes as usize),
+    }
+}
+
+/// Streams copies through a buffer of a set size rather than leaving them
+/// to the kernel, with `copy_buffer_size`
+#[derive(Debug, Clone, Copy)]
+pub struct StreamCopy {
+    /// The size of the buffer in bytes
+    pub buffer_size: usize,
+}
+
+impl Copier for StreamCopy {
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let source = tokio::fs::File::open(from).await?;
+        let permissions = source.metadata().await?.permissions();
+        let mut reader = tokio::io::BufReader::with_capacity(self.buffer_size, source);
+        let mut target = tokio::fs::File::create(to).await?;
+
+        tokio::io::copy_buf(&mut reader, &mut target).await?;
+        target.flush().await?;
+        target.set_permissions(permissions).await
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+/// A filesystem kept in memory, for tests
+///
+/// Paths are compared as they are given, so they should be absolute.
+/// There are no ignore files nor links, but hidden files are left out unless asked for
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    nodes: Arc<Mutex<HashMap<PathBuf, Node>>>,
+    /// The directories that are on a filesystem of their own
+    mounts: Vec<PathBuf>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file along with the directories it is in
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> Self {
+        let path = path.as_ref();
+        {
+            let mut nodes = self.nodes.lock().unwrap();
+            path.ancestors().skip(1).for_each(|dir| {
+                nodes.insert(dir.to_path_buf(), Node::Dir);
+            });
+            nodes.insert(path.to_path_buf(), Node::File(contents.into()));
+        }
+        self
+    }
+
+    /// Puts the directory on a filesystem of its own,
+    /// so that renames into or out of it cross devices
+    pub fn with_mount(mut self, path: impl AsRef<Path>) -> Self {
+        self.mounts.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The mount a path is on, `None` for the root filesystem
+    fn device(&self, path: &Path) -> Option<&Path> {
+        self.mounts
+            .iter()
+            .filter(|m| path.starts_with(m))
+            .max_by_key(|m| m.components().count())
+            .map(|m| m.as_path())
+    }
+
+    /// Whether anything exists at the path
+    pub fn exists_at(&self, path: impl AsRef<Path>) -> bool {
+        self.nodes.lock().unwrap().contains_key(path.as_ref())
+    }
+
+    /// The contents of a file, `None` if there is no such file
+    pub fn contents(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(path.as_ref()) {
+            Some(Node::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+impl Fs for MemoryFs {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::File(contents)) => Ok(contents.clone()),
+            Some(Node::Dir) => Err(io::Error::other(format!(
+                "{} is a directory",
+                path.display()
+            ))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    async fn write(&self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(parent) if !matches!(nodes.get(parent), Some(Node::Dir)) => {
+                return Err(not_found(parent));
+            }
+            _ => {}
+        }
+        if let Some(Node::Dir) = nodes.get(path) {
+            return Err(io::Error::other(format!(
+                "{} is a directory",
+                path.display()
+            )));
+        }
+
+        nodes.insert(path.to_path_buf(), Node::File(contents));
+        Ok(())
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(file) = path
+            .ancestors()
+            .find(|dir| matches!(nodes.get(*dir), Some(Node::File(_))))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is a file", file.display()),
+            ));
+        }
+
+        path.ancestors()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .for_each(|dir| {
+                nodes.insert(dir.to_path_buf(), Node::Dir);
+            });
+        Ok(())
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::File(contents)) => Ok(Metadata {
+                len: contents.len() as u64,
+                is_dir: false,
+                links: 1,
+            }),
+            Some(Node::Dir) => Ok(Metadata {
+                len: 0,
+                is_dir: true,
+                links: 1,
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.device(from) != self.device(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                format!("{} is on another filesystem", to.display()),
+            ));
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(from) {
+            return Err(not_found(from));
+        }
+
+        // A directory is moved along with everything in it
+        let moved = nodes
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        moved.into_iter().for_each(|p| {
+            let node = nodes.remove(&p).unwrap();
+            let rest = p.strip_prefix(from).unwrap();
+            let target = if rest.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(rest)
+            };
+            nodes.insert(target, node);
+        });
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(Node::File(_)) => {
+                nodes.remove(path);
+                Ok(())
+            }
+            Some(Node::Dir) => Err(io::Error::other(format!(
+                "{} is a directory",
+                path.display()
+            ))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !matches!(nodes.get(path), Some(Node::Dir)) {
+            return Err(not_found(path));
+        }
+        if nodes.keys().any(|p| p != path && p.starts_with(path)) {
+            return Err(io::Error::other(format!("{} is not empty", path.display())));
+        }
+
+        nodes.remove(path);
+        Ok(())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.nodes.lock().unwrap().get(path), Some(Node::Dir))
+    }
+
+    fn list_dir(&self, path: &Path, config: &ListDirConfig) -> DirListing {
+        let nodes = self.nodes.lock().unwrap();
+        let mut size = 0;
+        let paths = nodes
+            .iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .filter(|(p, _)| {
+                config.hidden
+                    || !p
+                        .strip_prefix(path)
+                        .unwrap()
+                        .components()
+                        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            })
+            .filter(|(_, node)| !config.filter_file || matches!(node, Node::File(_)))
+            .map(|(p, node)| {
+                if let Node::File(contents) = node {
+                    size += contents.len() as u64;
+                }
+                p.clone()
+            })
+            .collect::<Vec<_>>();
+
+        DirListing {
+            paths,
+            size: size as f64 / 1024.0,
+            skipped: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+}
@@ -2,25 +2,36 @@
 //! this is essentially the router of the program
 
 use std::{
-    path::PathBuf,
-    sync::{Arc, OnceLock},
+    io::Write,
+    path::{Path, PathBuf},
 };
 
 use clap::Command;
 use clap_complete::{generate, Shell};
 use colored::Colorize;
-use hashbrown::HashMap;
-use indicatif::{ProgressBar, ProgressStyle};
+use hashbrown::{HashMap, HashSet};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use tabled::{
-    settings::{Panel, Style},
+    settings::{location::ByColumnName, Format, Modify, Panel, Remove, Style},
     Table, Tabled,
 };
-use tokio::{sync::Mutex, task};
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    db::{self, Entry},
-    utils::{self, deep_search, does_file_exist, list_dir, sort_entries, ListDirConfig},
-    ConstructedArgs,
+    config::{self, default_progress_template, ProgressConfig},
+    daemon,
+    db::{self, DeletedEntry, Entry},
+    engine,
+    exit::ExitCode,
+    files::get_config_path,
+    fs::{Copier, CopyBackend, CopyingFs, KernelCopy, RealFs, StreamCopy},
+    identity::{self, FileId},
+    mmap::MmapCopy,
+    qr, rpc, serve, snapshot, status, suggest,
+    symlinks::{self, SymlinkPolicy},
+    update,
+    utils::{self, deep_search, does_file_exist, list_dir, sort_entries, ListDirConfig, Matching},
+    widgets, ConstructedArgs,
 };
 
 pub async fn handle_delete(args: ConstructedArgs, conn: &rusqlite::Connection) {
@@ -29,8 +40,16 @@ pub async fn handle_delete(args: ConstructedArgs, conn: &rusqlite::Connection) {
     sort_entries(&mut entries);
 
     if entries.is_empty() {
-        println!("No entries in the store");
-        std::process::exit(1);
+        eprintln!("No entries in the store");
+        ExitCode::NotFound.exit();
+    }
+
+    if let Some(pattern) = &args.glob {
+        let Some(matcher) = utils::glob_matcher(pattern) else {
+            eprintln!("{} \"{}\"", "Invalid glob".red(), pattern);
+            ExitCode::Usage.exit();
+        };
+        entries.retain(|e| matcher.is_match(&e.path));
     }
 
     let choices = entries
@@ -39,15 +58,36 @@ pub async fn handle_delete(args: ConstructedArgs, conn: &rusqlite::Connection) {
         .collect::<HashMap<_, _>>();
 
     let mut to_delete = Vec::new();
-
-    if let Some(queries) = args.files {
-        to_delete = deep_search(queries, &entries)
+    let (confirm, dry_run) = (args.confirm_delete, args.dry_run);
+
+    if let Some(range) = args.range {
+        let queries = args.files.unwrap_or_default();
+        to_delete = parse_range(
+            conn,
+            range,
+            &search(queries, &entries, args.matching, args.explain),
+        )
+        .iter()
+        .map(|e| PathBuf::from(e.path.clone()))
+        .collect();
+    } else if let Some(queries) = args.files {
+        let matched = search(queries.clone(), &entries, args.matching, args.explain);
+        let matched = match !args.all_matches && args.prompt && atty::is(atty::Stream::Stdin) {
+            true => pick_matches(&queries, &entries, args.matching, "delete"),
+            false => matched,
+        };
+        to_delete = matched
+            .iter()
+            .map(|e| PathBuf::from(e.path.clone()))
+            .collect();
+    } else if args.glob.is_some() {
+        to_delete = entries
             .iter()
             .map(|e| PathBuf::from(e.path.clone()))
             .collect();
     } else {
         handle_list(args, conn).await;
-        println!(
+        status!(
             "{}",
             "Enter the id of the files to delete seperate by a space".yellow()
         );
@@ -64,401 +104,2833 @@ pub async fn handle_delete(args: ConstructedArgs, conn: &rusqlite::Connection) {
                 if let Some(entry) = e {
                     to_delete.push(PathBuf::from(entry.path.clone()));
                 } else {
-                    println!("{}", "Invalid index".red());
-                    std::process::exit(1);
+                    eprintln!("{}", "Invalid index".red());
+                    ExitCode::Usage.exit();
                 }
             } else if let Some(entry) = choices.get(*x) {
                 to_delete.push(entry.clone());
+            } else if let Some(entry) = entries.iter().find(|e| utils::matches_uuid(x, e)) {
+                to_delete.push(PathBuf::from(entry.path.clone()));
             } else {
-                println!("{}", "Invalid index".red());
-                std::process::exit(1);
+                eprintln!("{}", "Invalid index".red());
+                ExitCode::Usage.exit();
             }
         });
     }
 
-    to_delete.iter().for_each(|x| {
-        db::delete_entry(conn, x.to_str().unwrap()).expect("Unable to delete entry");
-    });
+    let matched = entries
+        .iter()
+        .filter(|e| to_delete.contains(&PathBuf::from(&e.path)))
+        .collect::<Vec<_>>();
 
-    // Reid all the remaining files
-    let _ = db::reid(conn).expect("Failed to reid");
-    println!("Deleted {} files", to_delete.len().to_string().green());
-}
+    if matched.is_empty() {
+        eprintln!("{}", "No matching entries to delete".red());
+        ExitCode::NotFound.exit();
+    }
 
-pub async fn handle_clear(args: ConstructedArgs, conn: &rusqlite::Connection) {
-    if args.yes {
-        let choice = inquire::Confirm::new("Are you sure you want to clear all the copied files?")
-            .with_default(false)
+    // Queries are fuzzy, so show exactly what matched before deleting
+    #[derive(Tabled)]
+    struct DisplayDelete {
+        id: usize,
+        uuid: String,
+        name: String,
+        path: String,
+    }
+
+    let table = Table::new(matched.iter().map(|e| DisplayDelete {
+        id: e.id as usize,
+        uuid: e.short_uuid().to_string(),
+        name: e.name.clone(),
+        path: utils::display_path(&e.path),
+    }))
+    .with(Style::modern_rounded())
+    .with(Panel::header("Entries to Delete"))
+    .to_string();
+    status!("{}", table);
+
+    if dry_run {
+        status!(
+            "Would delete {} entries",
+            matched.len().to_string().yellow()
+        );
+        return;
+    }
+
+    if confirm {
+        let choice = inquire::Confirm::new(&format!("Delete these {} entries?", matched.len()))
+            .with_default(true)
             .prompt()
             .unwrap();
 
         if !choice {
-            println!("Ok! Quitting");
+            status!("Ok! Quitting");
+            return;
         }
     }
 
-    db::delete_all(conn).expect("Unable to delete the indexes");
-    println!("Emptied the store");
+    let paths = matched.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    let deleted = db::delete_entries(conn, &paths).expect("Unable to delete entries");
+
+    // Reid all the remaining files
+    let _ = db::reid(conn).expect("Failed to reid");
+    tracing::info!(entries = ?paths, "deleted entries");
+    status!("Deleted {} files", deleted.to_string().green());
+    status!("Use ynk restore --deleted to bring them back");
 }
 
-pub async fn handle_pop(
-    args: ConstructedArgs,
-    conn: &rusqlite::Connection,
-    output: Option<String>,
-) {
-    let entry = match db::pop_one(conn) {
-        Ok(entry) => entry,
-        Err(e) => {
-            println!("Could not pop entry from database: {:?}", e);
-            std::process::exit(1);
-        }
-    };
+pub async fn handle_show(args: ConstructedArgs, conn: &rusqlite::Connection, versions: bool) {
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = search(
+        args.files.unwrap_or_default(),
+        &entries,
+        args.matching,
+        args.explain,
+    );
 
-    let mut paste_config = args;
-    paste_config.range = None;
-    paste_config.specific = Some(entry.path);
-    paste_config.delete = true;
+    sort_entries(&mut entries);
 
-    handle_paste(paste_config, conn, output).await
-}
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries in the store".red());
+        ExitCode::NotFound.exit();
+    }
 
-pub async fn handle_add(args: ConstructedArgs, conn: &rusqlite::Connection) {
-    let mut files: HashMap<String, PathBuf> = HashMap::new();
-    let req = args.files.unwrap_or_else(|| {
-        println!("{}", "No files or directories specified".yellow());
-        println!("Copying the current directory");
+    #[derive(Tabled)]
+    struct DisplayVersion {
+        version: i32,
+        created_at: String,
+        location: String,
+    }
 
-        if args.yes {
-            let choice = inquire::Confirm::new("Do you want to continue?")
-                .with_default(true)
-                .prompt()
-                .unwrap();
+    entries.iter().for_each(|e| {
+        let snapshots = db::get_snapshots(conn, &e.path).expect("Could not get snapshots");
 
-            if !choice {
-                std::process::exit(0);
-            }
+        println!("{} {}", e.id.to_string().green(), e.name.blue());
+        println!("  uuid: {}", e.uuid);
+        println!("  path: {}", utils::display_path(&e.path));
+        println!("  is_dir: {}", e.is_dir);
+        if let Some(target) = &e.target {
+            println!("  target: {}", utils::display_path(target));
         }
-
-        vec![".".to_string()]
-    });
-    req.iter().for_each(|x| {
-        if !does_file_exist(x) {
-            println!(
-                "{} \"{}\" {}",
-                "File or directory with path".red(),
-                x.red(),
-                "does not exist.".red(),
-            );
-            std::process::exit(1);
+        if e.pinned {
+            println!("  pinned: true");
+        }
+        if !e.tags.is_empty() {
+            println!("  tags: {}", e.tags.join(", "));
+        }
+        println!("  created: {}", e.created_at.to_rfc2822());
+        println!("  last accessed: {}", e.accessed_at.to_rfc2822());
+        println!("  snapshots: {}", snapshots.len());
+
+        if versions && !snapshots.is_empty() {
+            let display_contents = snapshots
+                .iter()
+                .map(|s| DisplayVersion {
+                    version: s.version,
+                    created_at: s.created_at.to_rfc2822(),
+                    location: s.location.clone(),
+                })
+                .collect::<Vec<_>>();
+
+            let table = Table::new(display_contents)
+                .with(Style::modern_rounded())
+                .to_string();
+            println!("{}", table);
         }
+    });
+}
 
-        let path = if args.preserve_structure {
-            x.clone()
-        } else {
-            utils::parse_file_name(x)
-        };
+/// Number of changed files printed in the restore preview
+const RESTORE_PREVIEW_SIZE: usize = 20;
 
-        files.insert(path, PathBuf::from(x).canonicalize().unwrap());
-    });
+/// Restores a snapshot of the entries matching the queries over their source,
+/// or with `deleted`, brings back the matching entries from the trash
+pub async fn handle_restore(args: ConstructedArgs, conn: &rusqlite::Connection, deleted: bool) {
+    if deleted {
+        restore_from_trash(&args, conn);
+        return;
+    }
 
-    let entries = utils::construct_entry_builders(&files, args.dir)
+    let queries = args.files.clone().unwrap_or_default();
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let entries = search(queries, &entries, args.matching, args.explain);
+    let entries = entries
         .iter()
-        .map(|x| db::insert_into_db(conn, x.to_owned()).expect("Could not insert into database"))
+        .filter(|e| {
+            !db::get_snapshots(conn, &e.path)
+                .expect("Could not get snapshots")
+                .is_empty()
+        })
         .collect::<Vec<_>>();
 
-    println!("Copied {} files", entries.len());
-}
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries with snapshots".red());
+        ExitCode::NotFound.exit();
+    }
+
+    entries.iter().for_each(|e| {
+        let location = engine::resolve_source(conn, e, args.version).unwrap_or_else(|e| {
+            eprintln!("{}", e.red());
+            ExitCode::NotFound.exit();
+        });
+        let source = PathBuf::from(&e.path);
 
-fn parse_range(range: String, s_files: &[db::Entry]) -> Vec<Entry> {
-    let mut files = Vec::new();
-    if range.contains("..") {
-        let range = range.split("..").collect::<Vec<&str>>();
-        let start = range[0].parse::<usize>().unwrap();
-        let end = range[1].parse::<usize>().unwrap();
-        for i in start..=end {
-            let entry = s_files.iter().find(|x| x.id as usize == i).unwrap();
-            files.push(entry.clone());
+        let diff = snapshot::diff_snapshot(&location, &source).expect("Could not diff snapshot");
+        if diff.is_empty() {
+            status!("{} already matches its snapshot", e.name.blue());
+            return;
         }
-    } else {
-        let ids = range
-            .split(',')
-            .map(|x| x.parse::<usize>().unwrap())
-            .collect::<Vec<usize>>();
-        for i in ids {
-            let entry = s_files.iter().find(|x| x.id as usize == i).unwrap();
-            files.push(entry.clone());
+
+        status!(
+            "{}: {} changed, {} missing, {} unchanged",
+            e.name.blue(),
+            diff.changed.len().to_string().yellow(),
+            diff.missing.len().to_string().red(),
+            diff.unchanged
+        );
+        diff.changed
+            .iter()
+            .map(|p| ("M".yellow(), p))
+            .chain(diff.missing.iter().map(|p| ("A".green(), p)))
+            .take(RESTORE_PREVIEW_SIZE)
+            .for_each(|(status, p)| status!("  {} {}", status, p.to_string_lossy()));
+
+        let total = diff.changed.len() + diff.missing.len();
+        if total > RESTORE_PREVIEW_SIZE {
+            status!("  ... and {} more", total - RESTORE_PREVIEW_SIZE);
         }
-    };
 
-    files
+        if args.confirm_overwrite {
+            let choice = inquire::Confirm::new(&format!("Restore {} over its source?", e.name))
+                .with_default(false)
+                .prompt()
+                .unwrap();
+
+            if !choice {
+                status!("Skipping {}", e.name);
+                return;
+            }
+        }
+
+        let count =
+            snapshot::restore_snapshot(&location, &source).expect("Could not restore snapshot");
+        status!("Restored {} files of {}", count.to_string().green(), e.name);
+    });
 }
 
-pub async fn handle_paste(
-    paste_config: ConstructedArgs,
-    conn: &rusqlite::Connection,
-    output: Option<String>,
-) {
-    let s_files = db::get_all(conn).expect("Could not get entries from database");
-    let queries = paste_config.files.unwrap_or_default();
-    let s_files = deep_search(queries, &s_files);
-    let range = paste_config.range.clone();
-    let files = if let Some(range) = range {
-        parse_range(range, &s_files)
-    } else if let Some(specific_path) = paste_config.specific {
-        s_files
+/// Brings back the deleted entries matching the queries,
+/// or without queries, the entries removed by the last delete or clear
+fn restore_from_trash(args: &ConstructedArgs, conn: &rusqlite::Connection) {
+    let trash = db::get_trash(conn).expect("Could not get deleted entries from database");
+    let queries = args.files.clone().unwrap_or_default();
+
+    let deleted = if queries.is_empty() {
+        let last = trash.iter().map(|d| d.deleted_at).max();
+        trash
             .iter()
-            .filter(|e| e.path == specific_path)
-            .cloned()
-            .collect()
+            .filter(|d| Some(d.deleted_at) == last)
+            .collect::<Vec<_>>()
     } else {
-        s_files
+        let deleted_entries = trash.iter().map(|d| d.entry.clone()).collect::<Vec<_>>();
+        let matched = search(queries, &deleted_entries, args.matching, args.explain);
+        trash
+            .iter()
+            .filter(|d| matched.iter().any(|e| e.id == d.entry.id))
+            .collect::<Vec<_>>()
     };
 
-    let user_target = output.unwrap_or_else(|| ".".to_string()).clone();
+    if deleted.is_empty() {
+        eprintln!("{}", "No matching deleted entries to restore".red());
+        ExitCode::NotFound.exit();
+    }
 
-    static LIST_DIR_CONFIG: OnceLock<ListDirConfig> = OnceLock::new();
-    LIST_DIR_CONFIG.get_or_init(|| ListDirConfig {
-        filter_file: !paste_config.dir,
-        full_path: false,
-        strict: paste_config.strict,
-        hidden: paste_config.all,
-        respect_ignore: paste_config.ignore,
+    restore_deleted(args, conn, &deleted);
+}
+
+/// Moves deleted entries back into the store,
+/// skipping those whose path was added to the store again since
+fn restore_deleted(args: &ConstructedArgs, conn: &rusqlite::Connection, deleted: &[&DeletedEntry]) {
+    status!(
+        "Restoring {} deleted entries:",
+        deleted.len().to_string().yellow()
+    );
+    deleted.iter().for_each(|d| {
+        status!(
+            "  {} {} (deleted {})",
+            d.entry.name.blue(),
+            d.entry.path,
+            d.deleted_at.to_rfc2822()
+        );
     });
 
-    // TODO: Port this functionality to a struct
-    let mut final_files = HashMap::new();
-    let mut file_sizes = 0.0;
-
-    files.iter().for_each(|e| {
-        let path = PathBuf::from(e.path.clone());
-        let og_name = e.name.clone();
-        if path.is_dir() {
-            let (entries, got_size) =
-                list_dir(path.to_str().unwrap(), LIST_DIR_CONFIG.get().unwrap());
-            file_sizes += got_size;
-            final_files.extend(entries.iter().map(|x| {
-                let (name, path) = utils::wrap_from_path(&path, x);
-                (name, (path, e.is_dir, og_name.clone()))
-            }));
-        } else {
-            final_files.insert(og_name.clone(), (path.clone(), false, og_name));
+    if args.prompt {
+        let choice = inquire::Confirm::new("Restore these entries?")
+            .with_default(true)
+            .prompt()
+            .unwrap();
+
+        if !choice {
+            status!("Ok! Quitting");
+            return;
         }
+    }
+
+    // The trash is latest first, so the entries are put back in their original order
+    let mut restored = 0;
+    deleted.iter().rev().for_each(|d| {
+        if db::does_exist(conn, &d.entry.path).is_ok() {
+            status!(
+                "{} is already in the store, skipping",
+                d.entry.path.yellow()
+            );
+            return;
+        }
+
+        db::restore_entry(conn, d).expect("Could not restore entry");
+        restored += 1;
     });
 
-    let pb = Arc::new(Mutex::new(ProgressBar::new(final_files.len() as u64).with_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("#>-"),
-    )));
+    let _ = db::reid(conn).expect("Failed to reid");
+    tracing::info!(restored, "restored deleted entries");
+    status!("Restored {} entries", restored.to_string().green());
+}
+
+pub async fn handle_gc(conn: &rusqlite::Connection, dry_run: bool) {
+    let mut entries = db::get_all(conn).expect("Could not get entries from database");
+    let snapshots = db::get_all_snapshots(conn).expect("Could not get snapshots from database");
+
+    // Deleted entries can still be restored along with their snapshots
+    db::get_trash(conn)
+        .expect("Could not get deleted entries from database")
+        .into_iter()
+        .for_each(|d| entries.push(d.entry));
 
-    let tasks = final_files
+    // Snapshots of entries that were purged from the store
+    let (orphaned, retained): (Vec<_>, Vec<_>) = snapshots
+        .into_iter()
+        .partition(|s| !entries.iter().any(|e| e.path == s.path));
+
+    let retained = retained
         .iter()
-        .map(|(name, (path, consider_dir, dir_name))| {
-            if !PathBuf::from(user_target.clone()).exists() {
-                println!("{}", "Target directory does not exist".yellow());
-                println!("Creating the directory");
-                std::fs::create_dir(&user_target).expect("Could not create directory");
-            }
-            let mut target_file = PathBuf::from(user_target.clone());
-            if *consider_dir {
-                target_file = target_file.join(dir_name);
+        .map(|s| PathBuf::from(&s.location))
+        .collect::<Vec<_>>();
+    let unused = snapshot::unreferenced_snapshots(&retained).expect("Could not read snapshots");
+
+    // The references left on each blob once the orphaned snapshots are released
+    let mut refs = db::get_blobs(conn)
+        .expect("Could not get blobs from database")
+        .into_iter()
+        .map(|(hash, _, refs)| (hash, refs))
+        .collect::<HashMap<_, _>>();
+
+    let mut reclaimed = 0.0;
+    orphaned.iter().for_each(|s| {
+        let hashes = db::get_snapshot_hashes(conn, s.id).expect("Could not get snapshot files");
+
+        // Snapshots that don't share blobs take up their own space
+        if hashes.is_empty() {
+            reclaimed += snapshot::disk_size(&PathBuf::from(&s.location));
+        }
+        hashes.iter().for_each(|h| {
+            if let Some(r) = refs.get_mut(h) {
+                *r -= 1;
             }
-            target_file = target_file.join(name);
-            let pb_clone = Arc::clone(&pb);
-
-            // Spawn a new asynchronous task for each file copy operation
-            task::spawn(copy_paste(
-                pb_clone,
-                path.clone(),
-                target_file.clone(),
-                paste_config.overwrite,
-            ))
         });
+    });
 
-    match futures::future::try_join_all(tasks).await {
-        Ok(res) => {
-            let mut count: u64 = 0;
+    let orphaned_dirs = orphaned
+        .iter()
+        .filter_map(|s| PathBuf::from(&s.location).parent().map(|p| p.to_path_buf()))
+        .collect::<Vec<_>>();
+    unused
+        .iter()
+        .filter(|p| !orphaned_dirs.contains(p))
+        .for_each(|p| reclaimed += snapshot::disk_size(p));
 
-            res.iter().for_each(|x| {
-                if let Err(e) = x {
-                    println!(
-                        "Failed to paste file: {:?}\nUse the -v flag to see the error",
-                        e
-                    );
-                } else {
-                    count += 1
-                }
-            });
+    let unused_blobs = snapshot::stored_blobs()
+        .expect("Could not read blobs")
+        .into_iter()
+        .filter(|hash| refs.get(hash).map(|r| *r <= 0).unwrap_or(true))
+        .collect::<Vec<_>>();
+    unused_blobs.iter().for_each(|hash| {
+        reclaimed += snapshot::disk_size(&snapshot::blob_path(hash));
+    });
 
-            let pb = pb.lock().await;
-            pb.finish_with_message(format!(
-                "\nPasted {} files in {} seconds",
-                count,
-                pb.elapsed().as_secs_f32()
-            ));
+    unused.iter().for_each(|p| {
+        status!("  {}", p.to_string_lossy());
+    });
 
-            println!(
-                "Total size of files: {}",
-                utils::convert_size(file_sizes).to_string().green()
-            );
+    if dry_run {
+        status!(
+            "Would remove {} snapshots and {} blobs, reclaiming {}",
+            unused.len().to_string().yellow(),
+            unused_blobs.len().to_string().yellow(),
+            utils::convert_size(reclaimed).green()
+        );
+        return;
+    }
 
-            files.iter().for_each(|e| {
-                // update access time
-                db::update_accessed_at(conn, e.path.as_str())
-                    .expect("Could not update access time");
+    orphaned.iter().for_each(|s| {
+        db::release_snapshot_files(conn, s.id).expect("Could not release snapshot files");
+        db::delete_snapshot(conn, s.id).expect("Could not delete snapshot");
+    });
+    unused.iter().for_each(|p| {
+        std::fs::remove_dir_all(p).expect("Could not remove snapshot");
+    });
+    unused_blobs.iter().for_each(|hash| {
+        snapshot::remove_blob(hash).expect("Could not remove blob");
+        db::delete_blob(conn, hash).expect("Could not delete blob");
+    });
 
-                if paste_config.delete {
-                    db::delete_entry(conn, e.path.as_str()).expect("Unable to delete entry");
-                }
-            });
-            if paste_config.delete {
-                // Reid all the remaining files
-                let _ = db::reid(conn).expect("Failed to reid");
-            }
-        }
-        Err(e) => {
-            println!(
-                "Failed to paste files: {:?}\nUse the -v flag to see the error",
-                e
-            );
-        }
-    }
+    status!(
+        "Removed {} snapshots and {} blobs, reclaiming {}",
+        unused.len().to_string().green(),
+        unused_blobs.len().to_string().green(),
+        utils::convert_size(reclaimed).green()
+    );
 }
 
-/// The Async function in charge of copying and pasting files
-/// from the source to the target
-/// This is at the core of the program
-/// So, essentially, this function acts as an async and completely
-/// parallelized version of the `cp` command
-async fn copy_paste(
-    pb: Arc<Mutex<ProgressBar>>,
-    source: PathBuf,
-    target: PathBuf,
-    overwrite: bool,
-) -> Result<(), std::io::Error> {
-    tokio::fs::create_dir_all(target.parent().unwrap()).await?;
-
-    let contents = tokio::fs::read(source).await?;
+pub async fn handle_stats(conn: &rusqlite::Connection) {
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let snapshots = db::get_all_snapshots(conn).expect("Could not get snapshots from database");
+    let blobs = db::get_blobs(conn).expect("Could not get blobs from database");
 
-    if target.exists() && !overwrite {
-        println!("File {} already exists", target.to_str().unwrap());
+    let stored = blobs.iter().map(|(_, size, _)| size).sum::<u64>();
+    let logical = db::snapshot_files_size(conn).expect("Could not get snapshot sizes");
+    let saved = logical.saturating_sub(stored);
 
-        println!("Use the --overwrite flag to overwrite the any and all files");
-        std::process::exit(1);
+    println!("Entries: {}", entries.len().to_string().green());
+    println!("Snapshots: {}", snapshots.len().to_string().green());
+    println!("Unique files stored: {}", blobs.len().to_string().green());
+    println!(
+        "Snapshot size: {} stored for {} of files",
+        utils::convert_size(stored as f64 / 1024.0).green(),
+        utils::convert_size(logical as f64 / 1024.0).green()
+    );
+    if logical > 0 {
+        println!(
+            "Deduplication saved {} ({:.1}%)",
+            utils::convert_size(saved as f64 / 1024.0).green(),
+            saved as f64 * 100.0 / logical as f64
+        );
     }
+}
 
-    tokio::fs::write(target, contents).await?;
-
-    let pb = pb.lock().await;
-    pb.inc(1);
+/// Checks the snapshots of the entry against the hashes their files were stored with,
+/// describing the first one that is broken
+fn broken_snapshot(conn: &rusqlite::Connection, e: &Entry) -> Option<String> {
+    let snapshots = db::get_snapshots(conn, &e.path).expect("Could not get snapshots");
+    snapshots.iter().find_map(|s| {
+        let files = db::get_snapshot_files(conn, s.id).expect("Could not get snapshot files");
+        let broken = snapshot::verify_snapshot(Path::new(&s.location), &files);
+        let first = broken.first()?;
+        let first = match first.is_empty() {
+            true => "its file".to_string(),
+            false => first.clone(),
+        };
+        Some(format!(
+            "Snapshot version {} is missing or changed {} of {} files, ex: {}",
+            s.version,
+            broken.len(),
+            files.len(),
+            first
+        ))
+    })
+}
 
-    Ok(())
+/// Whether the file of the entry is a directory, from the file type detected when it was added
+fn entry_kind(e: &Entry) -> Option<bool> {
+    e.file_type.as_deref().map(|t| t == "inode/directory")
 }
 
-pub async fn handle_list(args: ConstructedArgs, conn: &rusqlite::Connection) {
+pub async fn handle_verify(conn: &rusqlite::Connection, fix: bool) {
     let mut entries = db::get_all(conn).expect("Could not get entries from database");
 
     sort_entries(&mut entries);
 
     if entries.is_empty() {
-        println!("{}", "No entries in the store".red());
-        std::process::exit(1);
+        eprintln!("{}", "No entries in the store".red());
+        ExitCode::NotFound.exit();
     }
 
-    println!(
-        "{}  entries in the store",
-        entries.len().to_string().green()
-    );
-    let mut count = 0;
-
     #[derive(Tabled)]
-    struct DisplayFiles {
+    struct VerifyResult {
         id: usize,
         name: String,
         path: String,
-        count: usize,
-        size: String,
-        is_dir: bool,
-        last_accessed: String,
+        status: String,
+        reason: String,
+    }
+
+    let mut broken = 0;
+    let mut movable = 0;
+    let results = entries
+        .iter()
+        .map(|e| {
+            let path = PathBuf::from(&e.path);
+            if fix && e.file_id.is_none() {
+                // Entries added before the identity was recorded get it now, while they're intact
+                if let Some(id) = FileId::of(&path) {
+                    db::update_file_id(conn, &e.path, id).expect("Could not update the entry");
+                }
+            }
+
+            let (status, reason) = if !path.exists() {
+                // Searching for a moved file can walk a lot of directories, so only a fix does
+                let found = e
+                    .file_id
+                    .filter(|_| fix)
+                    .and_then(|id| identity::locate(&path, id, entry_kind(e)));
+                match found {
+                    Some(found) => {
+                        let found = found.to_string_lossy().to_string();
+                        db::relocate_entry(conn, &e.path, &found)
+                            .expect("Could not update the entry");
+                        tracing::info!(from = e.path, to = found, "relocated an entry");
+                        ("fixed", format!("Moved to {}", utils::display_path(&found)))
+                    }
+                    None => {
+                        if !fix && e.file_id.is_some() {
+                            movable += 1;
+                        }
+                        broken += 1;
+                        ("fail", "Path does not exist".to_string())
+                    }
+                }
+            } else if e.is_dir && !path.is_dir() {
+                broken += 1;
+                ("fail", "Marked as a dir but is not a directory".to_string())
+            } else if let Some(reason) = broken_snapshot(conn, e) {
+                broken += 1;
+                ("fail", reason)
+            } else {
+                ("pass", String::new())
+            };
+
+            VerifyResult {
+                id: e.id as usize,
+                name: e.name.clone(),
+                path: utils::display_path(&e.path),
+                status: status.to_string(),
+                reason,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let table = Table::new(results)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Store Integrity"))
+        .to_string();
+
+    status!("{}", table);
+
+    if broken > 0 {
+        eprintln!(
+            "{} of {} entries are broken",
+            broken.to_string().red(),
+            entries.len()
+        );
+        if movable > 0 {
+            eprintln!(
+                "{} of them may have been moved, run {} to look for them and point them at where they are now",
+                movable.to_string().yellow(),
+                "ynk verify --fix".cyan()
+            );
+        }
+        ExitCode::Failure.exit();
+    }
+
+    status!(
+        "All {} entries are intact",
+        entries.len().to_string().green()
+    );
+}
+
+/// Re-resolves the entries matching the queries, or all of them, against the filesystem
+///
+/// The paths are canonicalized again and the entries whose files were moved are found
+/// again by their identity, while whether they are a dir, their file type, size and
+/// number of files are recounted and their access time bumped
+///
+/// `is_dir` is only ever cleared, for a directory added with `--dir` that is now a file,
+/// as setting it would paste a directory added without `--dir` as a directory of its own
+pub async fn handle_refresh(args: ConstructedArgs, conn: &rusqlite::Connection) {
+    let all = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = match args.files.clone() {
+        Some(queries) => search(queries, &all, args.matching, args.explain),
+        None => all,
+    };
+    sort_entries(&mut entries);
+
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries to refresh".red());
+        ExitCode::NotFound.exit();
     }
 
     #[derive(Tabled)]
-    struct PartialDisplayFiles {
+    struct RefreshResult {
         id: usize,
         name: String,
         path: String,
-        is_dir: bool,
+        size: String,
+        changes: String,
     }
 
-    let mut paste_config = args;
-    paste_config.specific = None;
+    let mut unresolved = 0;
+    let results = entries
+        .iter()
+        .map(|e| {
+            let mut changes = Vec::new();
+            let mut e = e.clone();
+
+            let resolved = PathBuf::from(&e.path)
+                .canonicalize()
+                .ok()
+                .or_else(|| {
+                    let found = identity::locate(Path::new(&e.path), e.file_id?, entry_kind(&e))?;
+                    changes.push("moved".to_string());
+                    Some(found)
+                })
+                .map(|p| p.to_string_lossy().to_string());
+            let Some(resolved) = resolved else {
+                unresolved += 1;
+                return RefreshResult {
+                    id: e.id as usize,
+                    name: e.name.clone(),
+                    path: utils::display_path(&e.path),
+                    size: "-".to_string(),
+                    changes: "missing".red().to_string(),
+                };
+            };
+
+            if resolved != e.path {
+                // Another entry may already be what the path resolves to now
+                if let Ok(other) = db::does_exist(conn, &resolved) {
+                    unresolved += 1;
+                    return RefreshResult {
+                        id: e.id as usize,
+                        name: e.name.clone(),
+                        path: utils::display_path(&e.path),
+                        size: "-".to_string(),
+                        changes: format!("same file as entry {}", other.id)
+                            .yellow()
+                            .to_string(),
+                    };
+                }
+                if changes.is_empty() {
+                    changes.push("path".to_string());
+                }
+                db::relocate_entry(conn, &e.path, &resolved).expect("Could not update the entry");
+                tracing::info!(from = e.path, to = resolved, "relocated an entry");
+                e.path = resolved;
+            }
 
-    static LIST_DIR_CONFIG: OnceLock<ListDirConfig> = OnceLock::new();
-    LIST_DIR_CONFIG.get_or_init(|| ListDirConfig {
-        filter_file: !paste_config.dir,
-        full_path: false,
-        strict: paste_config.strict,
-        hidden: paste_config.all,
-        respect_ignore: paste_config.ignore,
-    });
+            let path = PathBuf::from(&e.path);
+            if e.is_dir && !path.is_dir() {
+                db::update_is_dir(conn, &e.path, false).expect("Could not update the entry");
+                changes.push("no longer a dir".to_string());
+            }
+            let file_type = utils::detect_file_type(&path);
+            if e.file_type.as_deref() != Some(file_type.as_str()) {
+                db::update_file_type(conn, &e.path, &file_type)
+                    .expect("Could not update the entry");
+                changes.push("type".to_string());
+            }
 
-    // TODO: Better way to handle the calculate size flag
-    #[allow(unused_assignments)]
-    let mut table = String::new();
+            if let Some(id) = FileId::of(&path).filter(|id| e.file_id != Some(*id)) {
+                db::update_file_id(conn, &e.path, id).expect("Could not update the entry");
+            }
 
-    let mut total_size = 0.0;
-    if paste_config.calculate_size {
-        let mut display_contents = Vec::new();
-        entries.iter().for_each(|x| {
-            let mut file_count = 1;
-            let mut size = 0.0;
+            let (count, size) = entry_usage(&args, conn, &e, true);
+            if (e.file_count, e.size) != (Some(count), Some(size)) {
+                changes.push("size".to_string());
+            }
 
-            utils::convert_size(size);
+            let snapshots = db::get_snapshots(conn, &e.path).expect("Could not get snapshots");
+            let mut rehashed = 0;
+            for s in snapshots {
+                let files =
+                    db::get_snapshot_files(conn, s.id).expect("Could not get snapshot files");
+                let changed = snapshot::rehash_snapshot(Path::new(&s.location), &files)
+                    .expect("Could not hash the snapshot files");
+                for (old, file) in &changed {
+                    db::rehash_snapshot_file(conn, s.id, old, file)
+                        .expect("Could not update the snapshot files");
+                }
+                rehashed += changed.len();
+            }
+            if rehashed > 0 {
+                changes.push(format!("{} snapshot hashes", rehashed));
+            }
+            db::update_accessed_at(conn, &e.path).expect("Could not update the entry");
+
+            RefreshResult {
+                id: e.id as usize,
+                name: e.name.clone(),
+                path: utils::display_path(&e.path),
+                size: format!(
+                    "{} in {} files",
+                    utils::convert_size(size as f64 / 1024.0),
+                    count
+                ),
+                changes: if changes.is_empty() {
+                    "-".to_string()
+                } else {
+                    changes.join(", ").green().to_string()
+                },
+            }
+        })
+        .collect::<Vec<_>>();
 
-            if PathBuf::from(x.path.clone()).is_dir() {
-                let (files, raw_size) = utils::list_dir(&x.path, LIST_DIR_CONFIG.get().unwrap());
+    let table = Table::new(results)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Refreshed Entries"))
+        .to_string();
+    status!("{}", table);
+
+    if unresolved > 0 {
+        eprintln!(
+            "{} of {} entries could not be resolved",
+            unresolved.to_string().red(),
+            entries.len()
+        );
+        ExitCode::PartialFailure.exit();
+    }
+}
 
-                file_count = files.len();
-                size = raw_size;
-            } else {
-                size = PathBuf::from(x.path.clone()).metadata().unwrap().len() as f64;
-            }
+pub async fn handle_clear(
+    args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    mut filter: db::ClearFilter,
+) {
+    if let Some(queries) = args.files.clone() {
+        let entries = db::get_all(conn).expect("Could not get entries from database");
+        let ids = search(queries, &entries, args.matching, args.explain)
+            .iter()
+            .map(|e| e.id)
+            .collect::<Vec<_>>();
 
-            total_size += size;
+        if ids.is_empty() {
+            eprintln!("{}", "No matching entries to clear".red());
+            ExitCode::NotFound.exit();
+        }
+        filter.ids = Some(ids);
+    }
+    if filter.tag.is_some() {
+        let entries = db::get_all(conn).expect("Could not get entries from database");
+        filter.narrow_by_tag(&entries);
+    }
+
+    if args.confirm_clear {
+        let prompt = if filter.is_all() {
+            "Are you sure you want to clear all the copied files?"
+        } else {
+            "Are you sure you want to clear the selected entries?"
+        };
+        let choice = inquire::Confirm::new(prompt)
+            .with_default(false)
+            .prompt()
+            .unwrap();
+
+        if !choice {
+            status!("Ok! Quitting");
+            return;
+        }
+    }
+
+    let cleared = db::clear_entries(conn, &filter).expect("Unable to delete the indexes");
+    let _ = db::reid(conn).expect("Failed to reid");
+    tracing::info!(cleared, ?filter, "cleared the store");
+
+    if filter.is_all() {
+        status!("Emptied the store");
+    } else {
+        status!("Cleared {} entries", cleared.to_string().green());
+    }
+    status!("Use ynk restore --deleted to bring the entries back");
+}
+
+pub async fn handle_pop(
+    args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    output: Option<String>,
+    keep: bool,
+    stdout: bool,
+) -> ExitCode {
+    let entry = match db::pop_one(conn) {
+        Ok(entry) => entry,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            eprintln!("{}", "No entries in the store".red());
+            return ExitCode::NotFound;
+        }
+        Err(e) => {
+            eprintln!("Could not pop entry from database: {:?}", e);
+            ExitCode::Failure.exit();
+        }
+    };
+
+    if stdout {
+        pop_to_stdout(conn, &entry, keep);
+        return ExitCode::Success;
+    }
+
+    let mut paste_config = args;
+    paste_config.range = None;
+    paste_config.specific = Some(entry.path);
+    paste_config.delete = !keep;
+
+    handle_paste(paste_config, conn, output).await
+}
+
+pub async fn handle_open(args: ConstructedArgs, conn: &rusqlite::Connection, reveal: bool) {
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = search(
+        args.files.unwrap_or_default(),
+        &entries,
+        args.matching,
+        args.explain,
+    );
+    sort_entries(&mut entries);
+
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries".red());
+        ExitCode::NotFound.exit();
+    }
+
+    // Queries are fuzzy, so ask which of the matches was meant
+    // rather than opening a window for each of them
+    if entries.len() > 1 && !args.all_matches && !(args.prompt && atty::is(atty::Stream::Stdin)) {
+        eprintln!(
+            "{} entries match, pass --all-matches to open all of them:",
+            entries.len().to_string().red()
+        );
+        entries
+            .iter()
+            .for_each(|e| eprintln!("{} {}", e.id, utils::display_path(&e.path)));
+        ExitCode::Usage.exit();
+    }
+    if entries.len() > 1 && !args.all_matches {
+        let choices = entries
+            .iter()
+            .map(|e| format!("{} {}", e.id, utils::display_path(&e.path)))
+            .collect::<Vec<_>>();
+        let choice = inquire::Select::new("Open", choices)
+            .raw_prompt()
+            .unwrap_or_else(|_| {
+                status!("Ok! Quitting");
+                ExitCode::Cancelled.exit();
+            });
+        entries = vec![entries.swap_remove(choice.index)];
+    }
+
+    entries.iter().for_each(|e| {
+        let path = PathBuf::from(&e.path);
+        if !path.exists() {
+            eprintln!(
+                "{} does not exist anymore",
+                utils::display_path(&e.path).red()
+            );
+            return;
+        }
+
+        match utils::open_path(&path, reveal) {
+            Ok(()) => tracing::info!(path = e.path, reveal, "opened entry"),
+            Err(err) => eprintln!("{}", err.red()),
+        }
+    });
+}
+
+/// Gets the entries matching the queries, exiting if there are none
+fn matching_entries(args: &ConstructedArgs, conn: &rusqlite::Connection) -> Vec<Entry> {
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = search(
+        args.files.clone().unwrap_or_default(),
+        &entries,
+        args.matching,
+        args.explain,
+    );
+    sort_entries(&mut entries);
+
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries".red());
+        ExitCode::NotFound.exit();
+    }
+    entries
+}
+
+/// Pins the entries matching the queries, or unpins them with `unpin`,
+/// so that `clear --keep-pinned` leaves them in the store
+pub async fn handle_pin(args: ConstructedArgs, conn: &rusqlite::Connection, unpin: bool) {
+    let entries = matching_entries(&args, conn);
+
+    entries.iter().for_each(|e| {
+        db::update_pinned(conn, &e.path, !unpin).expect("Could not update the entry");
+        tracing::info!(path = e.path, pinned = !unpin, "pinned an entry");
+    });
+
+    let action = if unpin { "Unpinned" } else { "Pinned" };
+    status!("{} {} entries", action, entries.len().to_string().green());
+}
+
+/// Gives the entries matching the queries the tag, or takes it away with `remove`
+pub async fn handle_tag(
+    args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    tag: &str,
+    remove: bool,
+) {
+    if tag.is_empty() || tag.contains(|c: char| c == ',' || c.is_whitespace()) {
+        eprintln!(
+            "{} \"{}\", it can't be empty or have commas or spaces in it",
+            "Invalid tag".red(),
+            tag
+        );
+        ExitCode::Usage.exit();
+    }
+    let entries = matching_entries(&args, conn);
+
+    entries.iter().for_each(|e| {
+        let mut tags = e.tags.clone();
+        tags.retain(|t| t != tag);
+        if !remove {
+            tags.push(tag.to_string());
+        }
+        db::update_tags(conn, &e.path, &tags).expect("Could not update the entry");
+        tracing::info!(path = e.path, tag, remove, "tagged an entry");
+    });
+
+    let action = if remove { "Untagged" } else { "Tagged" };
+    status!("{} {} entries", action, entries.len().to_string().green());
+}
+
+/// Rotates the top `n` entries, so that the `n`th one is popped next
+pub async fn handle_roll(conn: &rusqlite::Connection, n: usize) {
+    let rolled = db::roll(conn, n).expect("Could not reorder the entries");
+    if rolled < 2 {
+        eprintln!("{}", "Not enough entries to reorder".red());
+        ExitCode::NotFound.exit();
+    }
+    tracing::info!(rolled, "rolled the entries");
+
+    let top = db::pop_one(conn).expect("Could not get the top entry");
+    status!(
+        "Rolled {} entries, {} will be popped next",
+        rolled.to_string().green(),
+        utils::display_path(&top.path).blue()
+    );
+}
+
+/// Writes the contents of a file entry to stdout,
+/// with any messages on stderr so that they don't end up in a pipe
+fn pop_to_stdout(conn: &rusqlite::Connection, entry: &Entry, keep: bool) {
+    let path = PathBuf::from(&entry.path);
+    if path.is_dir() {
+        eprintln!("{} is a directory", utils::display_path(&entry.path).red());
+        ExitCode::Usage.exit();
+    }
+
+    let mut file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!(
+                "{} {}: {}",
+                "Could not read".red(),
+                utils::display_path(&entry.path),
+                e
+            );
+            ExitCode::Failure.exit();
+        }
+    };
+
+    // Streamed, so that a large file isn't read into memory whole
+    let mut out = std::io::stdout().lock();
+    if let Err(e) = std::io::copy(&mut file, &mut out).and_then(|_| out.flush()) {
+        // A closed pipe just means the reader is done
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            eprintln!(
+                "{} {}: {}",
+                "Could not copy to stdout".red(),
+                utils::display_path(&entry.path),
+                e
+            );
+            ExitCode::Failure.exit();
+        }
+    }
+
+    db::update_accessed_at(conn, &entry.path).expect("Could not update accessed time");
+    if !keep {
+        db::delete_entries_as(conn, &[entry.path.as_str()], db::AuditAction::Paste)
+            .expect("Unable to delete entry");
+        let _ = db::reid(conn).expect("Failed to reid");
+    }
+    tracing::info!(path = entry.path, keep, "popped to stdout");
+}
+
+pub async fn handle_add(mut args: ConstructedArgs, conn: &rusqlite::Connection) {
+    let mut files: HashMap<String, PathBuf> = HashMap::new();
+    let req = args.files.take().unwrap_or_else(|| {
+        status!("{}", "No files or directories specified".yellow());
+        status!("Copying the current directory");
+
+        if args.confirm_cwd_add {
+            let choice = inquire::Confirm::new("Do you want to continue?")
+                .with_default(true)
+                .prompt()
+                .unwrap();
+
+            if !choice {
+                ExitCode::Success.exit();
+            }
+        }
+
+        vec![".".to_string()]
+    });
+    req.iter().for_each(|x| {
+        if !does_file_exist(x) {
+            eprintln!(
+                "{} \"{}\" {}",
+                "File or directory with path".red(),
+                x.red(),
+                "does not exist.".red(),
+            );
+            ExitCode::NotFound.exit();
+        }
+    });
+
+    let paths = req
+        .iter()
+        .map(|x| PathBuf::from(x).canonicalize().unwrap())
+        .collect::<Vec<_>>();
+    let names = if args.preserve_structure {
+        let cwd = std::env::current_dir()
+            .and_then(|cwd| cwd.canonicalize())
+            .unwrap_or_default();
+        utils::preserved_names(&paths, &cwd)
+    } else {
+        req.iter().map(|x| utils::parse_file_name(x)).collect()
+    };
+    files.extend(names.into_iter().zip(paths));
+
+    if args.dry_run {
+        print_dry_run(&files, args.all, args.ignore);
+        return;
+    }
+
+    if let Some(warn_size) = &args.warn_entry_size {
+        check_entry_sizes(&files, warn_size, &args);
+    }
+
+    let entries = engine::add(conn, &files, &args).expect("Could not insert into database");
+
+    if args.snapshot {
+        snapshot_entries(&entries, &args, conn);
+    }
+
+    tracing::info!(entries = entries.len(), "added entries");
+    status!("Copied {} files", entries.len());
+}
+
+/// Takes a new snapshot of each of the entries
+/// and removes the versions over the `snapshot_versions` limit
+fn snapshot_entries(entries: &[Entry], args: &ConstructedArgs, conn: &rusqlite::Connection) {
+    let config = ListDirConfig {
+        filter_file: true,
+        full_path: false,
+        strict: args.strict,
+        hidden: args.all,
+        respect_ignore: args.ignore,
+        symlinks: SymlinkPolicy::Skip,
+    };
+
+    entries.iter().for_each(|e| {
+        let (location, stored) =
+            match snapshot::take_snapshot(&PathBuf::from(&e.path), &e.name, &config) {
+                Ok(res) => res,
+                Err(err) => {
+                    eprintln!(
+                        "{} {}: {}",
+                        "Could not snapshot".red(),
+                        utils::display_path(&e.path),
+                        err
+                    );
+                    ExitCode::Failure.exit();
+                }
+            };
+
+        let snap = db::insert_snapshot(conn, &e.path, location.to_str().unwrap())
+            .expect("Could not insert snapshot into database");
+        db::insert_snapshot_files(conn, snap.id, &stored)
+            .expect("Could not insert snapshot files into database");
+        status!(
+            "Snapshotted {} as version {}",
+            e.name.blue(),
+            snap.version.to_string().green()
+        );
+
+        db::get_snapshots(conn, &e.path)
+            .expect("Could not get snapshots from database")
+            .iter()
+            .skip(args.snapshot_versions.max(1))
+            .for_each(|old| {
+                snapshot::remove_snapshot(&PathBuf::from(&old.location))
+                    .expect("Could not remove old snapshot");
+                db::release_snapshot_files(conn, old.id).expect("Could not release snapshot files");
+                db::delete_snapshot(conn, old.id).expect("Could not delete snapshot");
+            });
+    });
+}
+
+/// Warns about the entries that are larger than the `warn_entry_size`
+/// set in the config, refusing to add them in strict mode
+fn check_entry_sizes(files: &HashMap<String, PathBuf>, warn_size: &str, args: &ConstructedArgs) {
+    let threshold = match utils::parse_size(warn_size) {
+        Some(threshold) => threshold,
+        None => {
+            status!(
+                "{} \"{}\"",
+                "Invalid warn_entry_size in config:".yellow(),
+                warn_size
+            );
+            return;
+        }
+    };
+
+    let config = ListDirConfig {
+        filter_file: true,
+        full_path: false,
+        strict: false,
+        hidden: args.all,
+        respect_ignore: args.ignore,
+        symlinks: SymlinkPolicy::Skip,
+    };
+
+    let mut too_large = false;
+    files
+        .values()
+        .filter(|path| path.is_dir())
+        .for_each(|path| {
+            let (_, size) = list_dir(path.to_str().unwrap(), &config);
+            let size = (size * 1024.0) as u64;
+
+            if size > threshold {
+                too_large = true;
+                status!(
+                    "{} {} {} {}",
+                    "Entry".yellow(),
+                    utils::display_path(path).blue(),
+                    "is larger than".yellow(),
+                    warn_size.yellow()
+                );
+            }
+        });
+
+    if too_large && args.strict {
+        eprintln!(
+            "{}",
+            "Refusing to add entries over the size limit in strict mode".red()
+        );
+        ExitCode::Conflict.exit();
+    }
+}
+
+/// Prints the entries that would be added to the store
+/// along with the files discovered in them, without touching the database
+fn print_dry_run(files: &HashMap<String, PathBuf>, hidden: bool, respect_ignore: bool) {
+    #[derive(Tabled)]
+    struct DryRunEntry {
+        name: String,
+        path: String,
+        count: usize,
+        size: String,
+    }
+
+    let config = ListDirConfig {
+        filter_file: true,
+        full_path: false,
+        strict: false,
+        hidden,
+        respect_ignore,
+        symlinks: SymlinkPolicy::Skip,
+    };
+
+    let mut total_size = 0.0;
+    let mut display_contents = files
+        .iter()
+        .map(|(name, path)| {
+            let (count, size) = if path.is_dir() {
+                let (files, size) = list_dir(path.to_str().unwrap(), &config);
+                (files.len(), size)
+            } else {
+                (
+                    1,
+                    path.metadata().map(|m| m.len()).unwrap_or(0) as f64 / 1024.0,
+                )
+            };
+            total_size += size;
+
+            DryRunEntry {
+                name: name.clone(),
+                path: utils::display_path(path),
+                count,
+                size: utils::convert_size(size),
+            }
+        })
+        .collect::<Vec<_>>();
+    display_contents.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let table = Table::new(display_contents)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Entries that would be added"))
+        .to_string();
+
+    println!("{}", table);
+    println!(
+        "{} entries of about {} would be added",
+        files.len().to_string().green(),
+        utils::convert_size(total_size).green()
+    );
+    println!("{}", "Dry run, nothing was written to the store".yellow());
+}
+
+/// Number of ignored files printed as a sample
+const IGNORED_SAMPLE_SIZE: usize = 10;
+
+pub fn handle_show_ignored(args: ConstructedArgs) {
+    let dirs = args.files.unwrap_or_else(|| vec![".".to_string()]);
+
+    let config = ListDirConfig {
+        filter_file: true,
+        full_path: false,
+        strict: args.strict,
+        hidden: args.all,
+        respect_ignore: args.ignore,
+        symlinks: SymlinkPolicy::Skip,
+    };
+
+    for dir in dirs {
+        if !PathBuf::from(&dir).is_dir() {
+            eprintln!(
+                "{} \"{}\" {}",
+                "Path".red(),
+                dir.red(),
+                "is not a directory.".red()
+            );
+            continue;
+        }
+
+        let ignored = utils::list_ignored(&dir, &config);
+        println!(
+            "{} files would be excluded from {}",
+            ignored.len().to_string().yellow(),
+            dir.blue()
+        );
+
+        ignored
+            .iter()
+            .take(IGNORED_SAMPLE_SIZE)
+            .for_each(|p| println!("  {}", p.to_string_lossy()));
+
+        if ignored.len() > IGNORED_SAMPLE_SIZE {
+            println!("  ... and {} more", ignored.len() - IGNORED_SAMPLE_SIZE);
+        }
+    }
+}
+
+/// Finds the entries matching the queries, explaining why each matched with `--explain`
+fn search(
+    queries: Vec<String>,
+    entries: &[Entry],
+    matching: Matching,
+    explain: bool,
+) -> Vec<Entry> {
+    if explain && !queries.is_empty() {
+        explain_matches(&queries, entries, matching);
+    }
+    deep_search(queries, entries, matching)
+}
+
+/// Finds the entries matching the queries, asking which were meant
+/// for each query that matches more than one entry
+fn pick_matches(
+    queries: &[String],
+    entries: &[Entry],
+    matching: Matching,
+    action: &str,
+) -> Vec<Entry> {
+    let matches = utils::match_queries(queries, entries, matching);
+
+    let mut picked = HashSet::new();
+    let mut asked = HashSet::new();
+    for query in matches.iter().map(|m| m.query.as_str()) {
+        if !asked.insert(query) {
+            continue;
+        }
+
+        let mut candidates = entries
+            .iter()
+            .filter(|e| matches.iter().any(|m| m.query == query && m.id == e.id))
+            .collect::<Vec<_>>();
+        if candidates.len() == 1 {
+            picked.insert(candidates[0].id);
+            continue;
+        }
+
+        candidates.sort_by_key(|e| std::cmp::Reverse(e.id));
+        let choices = candidates
+            .iter()
+            .map(|e| format!("{} {} {}", e.id, e.name, utils::display_path(&e.path)))
+            .collect::<Vec<_>>();
+        let message = format!(
+            "\"{}\" matches {} entries, which should be {}d?",
+            query,
+            candidates.len(),
+            action
+        );
+        let chosen = inquire::MultiSelect::new(&message, choices)
+            .with_help_message("space to select, enter to confirm, --all-matches to skip this")
+            .raw_prompt()
+            .unwrap_or_else(|_| {
+                status!("Ok! Quitting");
+                ExitCode::Cancelled.exit();
+            });
+        picked.extend(chosen.iter().map(|c| candidates[c.index].id));
+    }
+
+    entries
+        .iter()
+        .filter(|e| picked.contains(&e.id))
+        .cloned()
+        .collect()
+}
+
+/// Prints why each entry matched a query, and the closest name to each query that matched nothing,
+/// on stderr so that the output of the command itself stays as it is
+fn explain_matches(queries: &[String], entries: &[Entry], matching: Matching) {
+    #[derive(Tabled)]
+    struct DisplayMatch {
+        query: String,
+        id: i32,
+        name: String,
+        reason: String,
+    }
+
+    let matches = utils::match_queries(queries, entries, matching);
+    let rows = matches
+        .iter()
+        .filter_map(|m| {
+            let entry = entries.iter().find(|e| e.id == m.id)?;
+            Some(DisplayMatch {
+                query: m.query.clone(),
+                id: m.id,
+                name: entry.name.clone(),
+                reason: m.reason.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if !rows.is_empty() {
+        let table = Table::new(rows)
+            .with(Style::modern_rounded())
+            .with(Panel::header("Why The Entries Matched"))
+            .to_string();
+        eprintln!("{}", table);
+    }
+
+    // Queries that are paths are matched by their canonical path
+    for query in queries {
+        let canonical = std::fs::canonicalize(query)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| query.clone());
+        if matches.iter().any(|m| m.query == canonical) {
+            continue;
+        }
+        let closest = entries
+            .iter()
+            .map(|e| (e, utils::similarity(&canonical, &e.name)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        match (closest, matching) {
+            (Some((e, score)), Matching::Fuzzy(threshold)) => eprintln!(
+                "{} \"{}\", the closest name is {} at {:.2}, under the threshold of {}",
+                "Nothing matched".yellow(),
+                query,
+                e.name,
+                score,
+                threshold
+            ),
+            _ => eprintln!("{} \"{}\"", "Nothing matched".yellow(), query),
+        }
+    }
+}
+
+/// How long after `list` a range is still taken against the ids it showed
+/// once the entries have been renumbered, in minutes
+const LISTING_TTL_MINUTES: i64 = 60;
+
+/// Resolves a range of ids, ex: `2..5`, `3..` or `1,4`, to the entries in it
+///
+/// Either end can be the uuid of an entry instead of its id, and the short uuid shown by `list`
+/// is the token that stays the same. The ids are taken as they were when the entries were
+/// last listed, so a range read off `list` means the same entries even if deleting others
+/// has renumbered them since. The range is refused if an entry in it as listed is gone,
+/// or if the entries were renumbered and the listing is older than [`LISTING_TTL_MINUTES`]
+fn parse_range(conn: &rusqlite::Connection, range: String, s_files: &[db::Entry]) -> Vec<Entry> {
+    let invalid = || -> ! {
+        eprintln!("{} \"{}\"", "Invalid range".red(), range);
+        ExitCode::Usage.exit();
+    };
+
+    let listing = db::get_listing(conn).expect("Could not get the listed ids");
+    let all = db::get_all(conn).expect("Could not get entries from database");
+    let stored = all.iter().map(|e| e.uuid.as_str()).collect::<HashSet<_>>();
+    // An entry listed before it was renumbered is found by its id as listed
+    let id_of = |x: &str| -> usize {
+        let x = x.trim();
+        if let Ok(id) = x.parse::<usize>() {
+            return id;
+        }
+        let entry = s_files
+            .iter()
+            .find(|e| utils::matches_uuid(x, e))
+            .unwrap_or_else(|| invalid());
+        listing
+            .as_ref()
+            .and_then(|l| l.uuids.iter().find(|(_, uuid)| **uuid == entry.uuid))
+            .map(|(id, _)| *id as usize)
+            .unwrap_or(entry.id as usize)
+    };
+
+    let ids = if let Some((start, end)) = range.split_once("..") {
+        let start = id_of(start);
+        let end = if end.is_empty() {
+            let listed = listing.as_ref().and_then(|l| l.uuids.keys().max().copied());
+            let current = s_files.iter().map(|x| x.id).max();
+            listed.max(current).map(|id| id as usize).unwrap_or(start)
+        } else {
+            id_of(end)
+        };
+        (start..=end).collect::<Vec<usize>>()
+    } else {
+        range.split(',').map(id_of).collect::<Vec<usize>>()
+    };
+
+    let mut renumbered = false;
+    let mut gone = Vec::new();
+    let entries: Vec<Entry> = ids
+        .iter()
+        .filter_map(|i| {
+            let current = s_files.iter().find(|x| x.id as usize == *i);
+            let listed = listing
+                .as_ref()
+                .and_then(|l| Some((l.uuids.get(&(*i as i32))?, l.at)));
+            match (current, listed) {
+                // Added after the listing, so not what the id meant then
+                (Some(e), Some((_, at))) if e.created_at > at => Some(e),
+                (_, Some((uuid, _))) => {
+                    let e = s_files.iter().find(|e| &e.uuid == uuid);
+                    match e {
+                        Some(e) if e.id as usize != *i => renumbered = true,
+                        Some(_) => {}
+                        None if !stored.contains(uuid.as_str()) => gone.push(*i),
+                        None => {}
+                    }
+                    e
+                }
+                (current, None) => current,
+            }
+        })
+        .cloned()
+        .collect();
+
+    if !gone.is_empty() {
+        let gone = gone.iter().map(usize::to_string).collect::<Vec<_>>();
+        eprintln!(
+            "{} {} {}",
+            "Entries".red(),
+            gone.join(", "),
+            "as last listed are no longer in the store, run `ynk list` again".red()
+        );
+        ExitCode::Conflict.exit();
+    }
+    if renumbered {
+        let now = chrono::Local::now();
+        let listed_at = listing.as_ref().map_or(now, |l| l.at);
+        if now - listed_at > chrono::Duration::minutes(LISTING_TTL_MINUTES) {
+            eprintln!(
+                "{} {}{}",
+                "The entries were renumbered since they were listed on".red(),
+                listed_at.to_rfc2822(),
+                ", run `ynk list` again or use their uuids".red()
+            );
+            ExitCode::Conflict.exit();
+        }
+        eprintln!(
+            "{}",
+            "The entries were renumbered since they were listed, so the range is taken as listed"
+                .yellow()
+        );
+    }
+
+    entries
+}
+
+/// Number of skipped paths that are printed
+const SKIPPED_SAMPLE_SIZE: usize = 10;
+
+/// Pastes the plan with the `copy_backend` set in the config,
+/// falling back to tokio when io_uring can't be used
+async fn paste_with_backend(
+    plan: &engine::PastePlan,
+    args: &ConstructedArgs,
+    pb: &ProgressBar,
+) -> engine::PasteResult {
+    if args.copy_backend == CopyBackend::IoUring {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            let chunk_size = args
+                .copy_buffer_size
+                .unwrap_or(crate::uring::DEFAULT_CHUNK_SIZE);
+            match crate::uring::UringCopy::new(chunk_size) {
+                Ok(copier) => {
+                    tracing::debug!(buffer_size = chunk_size, "copying with io_uring");
+                    return paste_on(copier, plan, args, pb).await;
+                }
+                Err(e) => tracing::warn!(error = %e, "io_uring is unavailable, copying with tokio"),
+            }
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        tracing::warn!("ynk was built without io_uring, copying with tokio");
+    }
+
+    match args.copy_buffer_size {
+        Some(buffer_size) => {
+            tracing::debug!(buffer_size, "streaming copies through a buffer");
+            paste_on(StreamCopy { buffer_size }, plan, args, pb).await
+        }
+        None => {
+            tracing::debug!("copying in the kernel where possible");
+            paste_on(KernelCopy, plan, args, pb).await
+        }
+    }
+}
+
+/// Pastes the plan with the copier, mapping the files
+/// over the `mmap_threshold` set in the config into memory
+async fn paste_on<C: Copier>(
+    copier: C,
+    plan: &engine::PastePlan,
+    args: &ConstructedArgs,
+    pb: &ProgressBar,
+) -> engine::PasteResult {
+    match args.mmap_threshold.as_deref().and_then(utils::parse_size) {
+        Some(threshold) => {
+            let fs = CopyingFs(MmapCopy::new(copier, threshold));
+            engine::paste(&fs, plan, args.overwrite, pb).await
+        }
+        None => engine::paste(&CopyingFs(copier), plan, args.overwrite, pb).await,
+    }
+}
+
+/// Warns about the files that are larger than the `warn_file_size` set in the config
+/// and asks whether to paste them anyway, refusing to in strict mode
+///
+/// Returns the code to exit with when the paste shouldn't go ahead
+async fn check_file_sizes(
+    plan: &engine::PastePlan,
+    warn_size: &str,
+    args: &ConstructedArgs,
+) -> Option<ExitCode> {
+    let Some(threshold) = utils::parse_size(warn_size) else {
+        status!(
+            "{} \"{}\"",
+            "Invalid warn_file_size in config:".yellow(),
+            warn_size
+        );
+        return None;
+    };
+
+    let large = engine::large_files(&RealFs, plan, threshold).await;
+    if large.is_empty() {
+        return None;
+    }
+
+    status!(
+        "{} {} {}",
+        large.len().to_string().yellow(),
+        "files are larger than".yellow(),
+        warn_size.yellow()
+    );
+    large.iter().for_each(|(path, size)| {
+        status!(
+            "  {} {}",
+            utils::display_path(path).blue(),
+            utils::convert_size(*size as f64 / 1024.0)
+        )
+    });
+
+    if args.strict {
+        eprintln!(
+            "{}",
+            "Refusing to paste files over the size limit in strict mode".red()
+        );
+        return Some(ExitCode::Failure);
+    }
+
+    if args.prompt && atty::is(atty::Stream::Stdin) {
+        let choice = inquire::Confirm::new("Paste them anyway?")
+            .with_default(true)
+            .prompt()
+            .unwrap_or(false);
+        if !choice {
+            status!("Ok! Quitting");
+            return Some(ExitCode::Cancelled);
+        }
+    }
+
+    None
+}
+
+/// Reports a target that couldn't be created or written to,
+/// explaining the usual reasons in words
+fn report_unwritable(action: &str, target: &str, e: &std::io::Error) {
+    let reason = match e.kind() {
+        std::io::ErrorKind::ReadOnlyFilesystem => "the target is read-only".to_string(),
+        std::io::ErrorKind::PermissionDenied => "you lack permission to write to it".to_string(),
+        _ => e.to_string(),
+    };
+    tracing::debug!(path = target, error = %e, "the target isn't writable");
+    eprintln!(
+        "{} {}: {}",
+        action.red(),
+        utils::display_path(target),
+        reason.red()
+    );
+}
+
+/// Pastes the entries, returning [`ExitCode::PartialFailure`] if some files couldn't be
+/// pasted and [`ExitCode::Conflict`] if some already exist without `--overwrite`
+#[tracing::instrument(skip_all)]
+pub async fn handle_paste(
+    mut paste_config: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    output: Option<String>,
+) -> ExitCode {
+    let all = db::get_all(conn).expect("Could not get entries from database");
+    let queries = paste_config.files.clone().unwrap_or_default();
+    let s_files = search(
+        queries.clone(),
+        &all,
+        paste_config.matching,
+        paste_config.explain,
+    );
+    let range = paste_config.range.clone();
+    let files = if let Some(range) = range {
+        parse_range(conn, range, &s_files)
+    } else if let Some(latest) = paste_config.latest {
+        let mut s_files = s_files;
+        // By when they were added, as a swap or roll reorders the ids,
+        // with the ids breaking the ties
+        sort_entries(&mut s_files);
+        utils::sort_entries_by(&mut s_files, "created");
+        s_files.truncate(latest);
+        s_files
+    } else if let Some(specific_path) = &paste_config.specific {
+        s_files
+            .iter()
+            .filter(|e| &e.path == specific_path)
+            .cloned()
+            .collect()
+    } else if !queries.is_empty()
+        && !paste_config.all_matches
+        && paste_config.prompt
+        && atty::is(atty::Stream::Stdin)
+    {
+        pick_matches(&queries, &all, paste_config.matching, "paste")
+    } else {
+        s_files
+    };
+
+    if files.is_empty() {
+        eprintln!("{}", "No matching entries to paste".red());
+        return ExitCode::NotFound;
+    }
+
+    let files = if paste_config.select {
+        let mut files = files;
+        sort_entries(&mut files);
+        let choices = files
+            .iter()
+            .map(|e| format!("{} {} {}", e.id, e.name, utils::display_path(&e.path)))
+            .collect::<Vec<_>>();
+        let chosen = inquire::MultiSelect::new("Paste which entries?", choices)
+            .with_all_selected_by_default()
+            .with_help_message("space to drop or keep an entry, enter to paste")
+            .raw_prompt()
+            .unwrap_or_else(|_| {
+                status!("Ok! Quitting");
+                ExitCode::Cancelled.exit();
+            });
+        if chosen.is_empty() {
+            status!("No entries chosen, so nothing is pasted");
+            return ExitCode::Cancelled;
+        }
+        chosen.iter().map(|c| files[c.index].clone()).collect()
+    } else {
+        files
+    };
+
+    let user_target = output
+        .clone()
+        .or_else(|| {
+            paste_config
+                .default_output
+                .as_deref()
+                .map(utils::expand_home)
+        })
+        .unwrap_or_else(|| ".".to_string());
+
+    let suggested = if output.is_none()
+        && paste_config.suggest_targets
+        && paste_config.prompt
+        && atty::is(atty::Stream::Stdin)
+    {
+        suggest_targets(conn, &paste_config, &files, &user_target)
+    } else {
+        HashMap::new()
+    };
+
+    // An explicit output wins over the target recorded with the entry
+    let target_of = |e: &Entry| {
+        let target = match (&output, &e.target) {
+            (None, Some(target)) => target,
+            (None, None) => suggested.get(&e.path).unwrap_or(&user_target),
+            _ => &user_target,
+        };
+        // Targets are made absolute so that nested ones that don't exist yet can be created
+        utils::absolute_path(target).to_string_lossy().to_string()
+    };
+
+    if paste_config.symlinks == SymlinkPolicy::Copy && !symlinks::can_create_symlinks() {
+        eprintln!(
+            "{}",
+            "Creating symlinks needs SeCreateSymbolicLinkPrivilege or Developer Mode, so they are followed instead"
+                .yellow()
+        );
+        paste_config.symlinks = SymlinkPolicy::Follow;
+    }
+
+    let discovery = tracing::info_span!("discovery", entries = files.len()).entered();
+    let plan = engine::plan(&RealFs, conn, &files, &paste_config, target_of).unwrap_or_else(|e| {
+        eprintln!("{}", e.to_string().red());
+        match e {
+            engine::PlanError::MissingVersion(_) => ExitCode::NotFound.exit(),
+            engine::PlanError::Clash(..) => ExitCode::Conflict.exit(),
+        }
+    });
+    tracing::debug!(files = plan.files.len(), "discovered files to paste");
+    discovery.exit();
+
+    if let Some(warn_size) = &paste_config.warn_file_size {
+        if let Some(code) = check_file_sizes(&plan, warn_size, &paste_config).await {
+            return code;
+        }
+    }
+
+    let targets = plan
+        .entry_targets
+        .iter()
+        .map(|(_, target)| target)
+        .collect::<HashSet<_>>();
+    for target in targets {
+        if !PathBuf::from(target).exists() {
+            status!(
+                "{} {}",
+                "Target directory does not exist, creating".yellow(),
+                utils::display_path(target)
+            );
+            if let Err(e) = std::fs::create_dir_all(target) {
+                report_unwritable("Could not create", target, &e);
+                return ExitCode::Failure;
+            }
+        }
+        // Checked up front, so that an unwritable target is reported once
+        // instead of by every copy into it
+        if let Err(e) = utils::probe_writable(Path::new(target)) {
+            report_unwritable("Could not write to", target, &e);
+            return ExitCode::Failure;
+        }
+    }
+
+    let pb = progress_bar(
+        (plan.files.len() + plan.links.len()) as u64,
+        paste_config.progress.as_ref(),
+    );
+    let result = paste_with_backend(&plan, &paste_config, &pb).await;
+
+    // The errors themselves are logged by the copy tasks
+    let count = result.pasted.len();
+    let failed = result.failed.len();
+    tracing::info!(
+        output = %user_target,
+        pasted = count,
+        failed,
+        conflicts = result.conflicts.len(),
+        skipped = plan.skipped.len() + result.skipped.len(),
+        "pasted files"
+    );
+
+    let summary = format!(
+        "{} {} files in {} seconds",
+        if paste_config.move_files {
+            "Moved"
+        } else {
+            "Pasted"
+        },
+        count,
+        pb.elapsed().as_secs_f32()
+    );
+    if pb.is_hidden() {
+        status!("{}", summary);
+    } else {
+        pb.finish_with_message(format!("\n{}", summary));
+    }
+
+    status!(
+        "Total size of files: {}",
+        utils::convert_size(plan.size).to_string().green()
+    );
+
+    let skipped = plan
+        .skipped
+        .iter()
+        .chain(result.skipped.iter())
+        .collect::<Vec<_>>();
+    if !skipped.is_empty() {
+        eprintln!(
+            "{} {}",
+            skipped.len().to_string().yellow(),
+            "files skipped as they couldn't be read or aren't regular files:".yellow()
+        );
+        skipped
+            .iter()
+            .take(SKIPPED_SAMPLE_SIZE)
+            .for_each(|p| eprintln!("  {}", utils::display_path(p)));
+        if skipped.len() > SKIPPED_SAMPLE_SIZE {
+            eprintln!("  ... and {} more", skipped.len() - SKIPPED_SAMPLE_SIZE);
+        }
+    }
+
+    if failed > 0 {
+        eprintln!(
+            "{} {} {}",
+            "Failed to paste".red(),
+            failed.to_string().red(),
+            "files, use the -v flag to see the errors".red()
+        );
+    }
+
+    if !result.conflicts.is_empty() {
+        result
+            .conflicts
+            .iter()
+            .for_each(|target| eprintln!("File {} already exists", target.display()));
+        eprintln!("Use the --overwrite flag to overwrite the any and all files");
+        return ExitCode::Conflict;
+    }
+
+    // Entries with files that failed to paste are kept, as the rpc paste does
+    engine::finish(conn, &files, &plan, paste_config.delete && failed == 0)
+        .expect("Could not update the pasted entries");
+
+    if failed > 0 {
+        ExitCode::PartialFailure
+    } else {
+        ExitCode::Success
+    }
+}
+
+/// Number of previous targets of an entry that are suggested
+const SUGGESTED_TARGETS: usize = 5;
+
+/// Suggests targets for the entries that have no target of their own,
+/// from the targets they were pasted to before and the `target_rules`
+/// in the config, and asks which one each entry is pasted to
+///
+/// Returns the chosen targets by the path of the entry,
+/// leaving out the entries that stay with the default target
+fn suggest_targets(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    files: &[Entry],
+    default_target: &str,
+) -> HashMap<String, String> {
+    let rules = args
+        .target_rules
+        .iter()
+        .filter_map(|r| match utils::glob_matcher(&r.glob) {
+            Some(matcher) => Some((matcher, utils::expand_home(&r.target))),
+            None => {
+                tracing::warn!(glob = r.glob, "Invalid glob in target_rules");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    files
+        .iter()
+        .filter(|e| e.target.is_none())
+        .filter_map(|e| {
+            let mut candidates = db::get_recent_targets(conn, Some(&e.path), SUGGESTED_TARGETS)
+                .expect("Could not get targets");
+
+            let name = PathBuf::from(&e.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            rules
+                .iter()
+                .filter(|(matcher, _)| matcher.is_match(&name))
+                .for_each(|(_, target)| {
+                    if !candidates.contains(target) {
+                        candidates.push(target.clone());
+                    }
+                });
+
+            if candidates.is_empty() {
+                return None;
+            }
+            candidates.push(default_target.to_string());
+
+            let choices = candidates
+                .iter()
+                .map(utils::display_path)
+                .collect::<Vec<_>>();
+            let choice = inquire::Select::new(&format!("Paste {} to", e.name), choices)
+                .raw_prompt()
+                .unwrap_or_else(|_| {
+                    status!("Ok! Quitting");
+                    ExitCode::Cancelled.exit();
+                });
+            let choice = candidates.swap_remove(choice.index);
+            (choice != default_target).then(|| (e.path.clone(), choice))
+        })
+        .collect()
+}
+
+/// Number of recent targets offered by `paste --to-recent` and `targets`
+const RECENT_TARGETS: usize = 20;
+
+/// Asks which of the recent paste targets to paste to
+pub fn pick_recent_target(conn: &rusqlite::Connection) -> String {
+    let targets =
+        db::get_recent_targets(conn, None, RECENT_TARGETS).expect("Could not get targets");
+    if targets.is_empty() {
+        eprintln!("{}", "Nothing has been pasted yet".red());
+        ExitCode::NotFound.exit();
+    }
+
+    let choices = targets.iter().map(utils::display_path).collect::<Vec<_>>();
+    let choice = inquire::Select::new("Paste to", choices)
+        .raw_prompt()
+        .unwrap_or_else(|_| {
+            status!("Ok! Quitting");
+            ExitCode::Cancelled.exit();
+        });
+    targets[choice.index].clone()
+}
+
+/// Picks the output with the external picker from the config,
+/// offering the recent paste targets and the directories in the home directory
+pub fn pick_target(conn: &rusqlite::Connection, picker: &str) -> String {
+    let mut candidates =
+        db::get_recent_targets(conn, None, RECENT_TARGETS).expect("Could not get targets");
+
+    if let Some(home) = dirs::home_dir() {
+        let mut dirs = std::fs::read_dir(&home)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().is_dir())
+                    .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                    .map(|e| e.path().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        dirs.sort();
+        candidates.push(home.to_string_lossy().to_string());
+        candidates.extend(dirs);
+    }
+    let mut seen = HashSet::new();
+    candidates.retain(|c| seen.insert(c.clone()));
+
+    match utils::run_picker(picker, &candidates) {
+        Ok(target) => target,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            ExitCode::Failure.exit();
+        }
+    }
+}
+
+/// Prints the number of entries matching the queries, or all of them,
+/// or with `files` the total number of files in them
+///
+/// The number of files in an entry is counted once and cached,
+/// unless `--all` or `--noignore` change what is counted
+pub async fn handle_count(args: ConstructedArgs, conn: &rusqlite::Connection, files: bool) {
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let entries = search(
+        args.files.clone().unwrap_or_default(),
+        &entries,
+        args.matching,
+        args.explain,
+    );
+
+    if !files {
+        println!("{}", entries.len());
+        return;
+    }
+
+    let total = entries
+        .iter()
+        .map(|e| entry_usage(&args, conn, e, false).0)
+        .sum::<i64>();
+
+    println!("{}", total);
+}
+
+/// The number of files in an entry and their size in bytes
+///
+/// These are cached in the store and only counted when missing or on `refresh`,
+/// and not cached when `--all` or `--noignore` change what is counted
+fn entry_usage(
+    args: &ConstructedArgs,
+    conn: &rusqlite::Connection,
+    e: &Entry,
+    refresh: bool,
+) -> (i64, i64) {
+    let cache = !args.all_explicit && !args.ignore_explicit;
+    if let (Some(count), Some(size), true, false) = (e.file_count, e.size, cache, refresh) {
+        return (count, size);
+    }
+
+    let path = PathBuf::from(&e.path);
+    let (count, size) = if path.is_dir() {
+        let (files, size) = utils::list_dir(&e.path, &engine::entry_list_dir_config(args, e));
+        (files.len() as i64, (size * 1024.0) as i64)
+    } else {
+        (
+            1,
+            path.metadata().map(|m| m.len() as i64).unwrap_or_default(),
+        )
+    };
+
+    if cache {
+        db::update_usage(conn, &e.path, count, size).expect("Could not update entry in database");
+    }
+    (count, size)
+}
+
+/// Breaks down the size of the entry matching the queries,
+/// or the top entry, by the files and directories directly in it
+pub async fn handle_du(args: ConstructedArgs, conn: &rusqlite::Connection) {
+    let all = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = match &args.files {
+        Some(queries) => search(queries.clone(), &all, args.matching, args.explain),
+        None => all.into_iter().max_by_key(|e| e.id).into_iter().collect(),
+    };
+    sort_entries(&mut entries);
+
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries".red());
+        ExitCode::NotFound.exit();
+    }
+
+    if entries.len() > 1 && args.prompt {
+        let choices = entries
+            .iter()
+            .map(|e| format!("{} {}", e.id, utils::display_path(&e.path)))
+            .collect::<Vec<_>>();
+        let choice = inquire::Select::new("Show the size of", choices)
+            .raw_prompt()
+            .unwrap_or_else(|_| {
+                status!("Ok! Quitting");
+                ExitCode::Cancelled.exit();
+            });
+        entries = vec![entries.swap_remove(choice.index)];
+    }
+
+    #[derive(Tabled)]
+    struct DisplayUsage {
+        name: String,
+        files: usize,
+        size: String,
+        share: String,
+    }
+
+    for e in &entries {
+        let root = PathBuf::from(&e.path);
+        if !root.is_dir() {
+            let size = root.metadata().map(|m| m.len()).unwrap_or_default();
+            println!(
+                "{} is a file of {}",
+                utils::display_path(&e.path).blue(),
+                utils::convert_size(size as f64 / 1024.0).green()
+            );
+            continue;
+        }
+
+        let (files, _) = utils::list_dir(&e.path, &engine::entry_list_dir_config(&args, e));
+
+        // Everything below a top level directory counts towards it
+        let mut usage: HashMap<String, (usize, u64)> = HashMap::new();
+        files.iter().for_each(|f| {
+            let Some(top) = f.strip_prefix(&root).ok().and_then(|p| p.iter().next()) else {
+                return;
+            };
+            let mut name = top.to_string_lossy().to_string();
+            if root.join(top).is_dir() {
+                name.push(std::path::MAIN_SEPARATOR);
+            }
+
+            let size = f.metadata().map(|m| m.len()).unwrap_or_default();
+            let usage = usage.entry(name).or_default();
+            usage.0 += 1;
+            usage.1 += size;
+        });
+
+        let total = usage.values().map(|(_, size)| size).sum::<u64>();
+        let mut usage = usage.into_iter().collect::<Vec<_>>();
+        usage.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then_with(|| a.0.cmp(&b.0)));
+
+        let display_contents = usage
+            .into_iter()
+            .map(|(name, (files, size))| DisplayUsage {
+                name,
+                files,
+                size: utils::convert_size(size as f64 / 1024.0),
+                share: format!("{:.1}%", size as f64 * 100.0 / total.max(1) as f64),
+            })
+            .collect::<Vec<_>>();
+
+        let table = Table::new(display_contents)
+            .with(Style::modern_rounded())
+            .with(Panel::header(utils::display_path(&e.path)))
+            .to_string();
+        println!("{}", table);
+        println!(
+            "{} files, {} in total",
+            files.len().to_string().green(),
+            utils::convert_size(total as f64 / 1024.0).green()
+        );
+    }
+}
+
+/// Checks the config file, printing each problem with its line
+pub async fn handle_config_validate() -> ExitCode {
+    let path = get_config_path();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!(
+                "{} {}: {}",
+                "Could not read".red(),
+                utils::display_path(&path),
+                e
+            );
+            return ExitCode::NotFound;
+        }
+    };
+
+    let problems = config::validate_config(&content);
+    if problems.is_empty() {
+        println!("{} is valid", utils::display_path(&path).green());
+        return ExitCode::Success;
+    }
+
+    problems.iter().for_each(|p| match p.line {
+        Some(line) => eprintln!(
+            "{}:{}: {}",
+            utils::display_path(&path),
+            line,
+            p.message.red()
+        ),
+        None => eprintln!("{}: {}", utils::display_path(&path), p.message.red()),
+    });
+    eprintln!(
+        "Found {} problems in the config",
+        problems.len().to_string().red()
+    );
+    ExitCode::Failure
+}
+
+/// Prints the effective value of every config key, with where it came from
+pub async fn handle_config_show(config: &config::Config, args: &ConstructedArgs) {
+    #[derive(Tabled)]
+    struct ConfigRow {
+        key: String,
+        value: String,
+        source: String,
+    }
+
+    let rows = config::effective_config(config, args)
+        .into_iter()
+        .map(|v| ConfigRow {
+            key: v.key,
+            value: v.value,
+            source: v.source.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let table = Table::new(rows)
+        .with(Style::modern_rounded())
+        .with(Panel::header(utils::display_path(get_config_path())))
+        .to_string();
+
+    println!("{}", table);
+}
+
+/// Serves the store over a local socket until interrupted
+pub async fn handle_daemon(
+    args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    socket: Option<String>,
+) -> ExitCode {
+    let address = socket.unwrap_or_else(daemon::default_address);
+    status!("Listening on {}", address.green());
+
+    match daemon::run(conn, &args, &address).await {
+        Ok(()) => ExitCode::Success,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            eprintln!("{}", e.to_string().red());
+            ExitCode::Conflict
+        }
+        Err(e) => {
+            eprintln!("{} {}: {}", "Could not listen on".red(), address, e);
+            ExitCode::Failure
+        }
+    }
+}
+
+/// Serves the entries matching the queries, or the top entry, over HTTP until interrupted
+pub async fn handle_serve(
+    args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    bind: &str,
+    port: u16,
+    token: Option<String>,
+    qr: bool,
+) -> ExitCode {
+    let all = db::get_all(conn).expect("Could not get entries from database");
+    let mut entries = match &args.files {
+        Some(queries) => search(queries.clone(), &all, args.matching, args.explain),
+        None => all.into_iter().max_by_key(|e| e.id).into_iter().collect(),
+    };
+    sort_entries(&mut entries);
+
+    if entries.is_empty() {
+        eprintln!("{}", "No matching entries to serve".red());
+        return ExitCode::NotFound;
+    }
+
+    let listener = match tokio::net::TcpListener::bind((bind, port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{} {}:{}: {}", "Could not listen on".red(), bind, port, e);
+            return ExitCode::Failure;
+        }
+    };
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or(port);
+
+    let server = serve::Server {
+        entries: entries
+            .iter()
+            .map(|e| {
+                serve::Shared::new(
+                    e.name.clone(),
+                    PathBuf::from(&e.path),
+                    Path::new(&e.path).is_dir(),
+                    engine::entry_list_dir_config(&args, e),
+                )
+            })
+            .collect(),
+        token,
+    };
+
+    // Binding every interface is meant for the other machines on the network
+    let host = match bind {
+        "0.0.0.0" | "::" => serve::lan_address()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| bind.to_string()),
+        _ => bind.to_string(),
+    };
+    let query = server
+        .token
+        .as_ref()
+        .map(|t| format!("?token={}", serve::percent_encode(t)))
+        .unwrap_or_default();
+    let index_url = format!("http://{}:{}/{}", host, port, query);
+    let file_url = |i: usize, e: &Entry| {
+        format!(
+            "http://{}:{}/{}/{}{}",
+            host,
+            port,
+            i,
+            serve::percent_encode(&e.name),
+            query
+        )
+    };
+    println!(
+        "Serving {} entries at {}, press Ctrl-C to stop",
+        entries.len().to_string().green(),
+        index_url.blue()
+    );
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| !Path::new(&e.path).is_dir())
+        .for_each(|(i, e)| println!("  curl -O {}", utils::shell_quote(&file_url(i, e))));
+
+    if qr {
+        // A single file is fetched straight away, anything else through the index
+        let url = match entries.as_slice() {
+            [e] if !Path::new(&e.path).is_dir() => file_url(0, e),
+            _ => index_url,
+        };
+        match qr::render(&url) {
+            Some(code) => println!("\n{}", code),
+            None => eprintln!("{}", "The link is too long for a QR code".yellow()),
+        }
+    }
+    tracing::info!(entries = entries.len(), port, "serving entries");
+
+    match server.run(listener).await {
+        Ok(()) => ExitCode::Success,
+        Err(e) => {
+            eprintln!("{} {}", "Could not serve the entries:".red(), e);
+            ExitCode::Failure
+        }
+    }
+}
+
+/// Serves JSON-RPC requests from stdin until it is closed, responding on stdout
+pub async fn handle_rpc(args: ConstructedArgs, conn: &rusqlite::Connection) -> ExitCode {
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    let busy = tokio::sync::Mutex::new(());
+    match rpc::serve_lines(conn, &args, &busy, stdin, tokio::io::stdout()).await {
+        Ok(()) => ExitCode::Success,
+        Err(e) => {
+            eprintln!("{} {}", "Could not serve requests:".red(), e);
+            ExitCode::Failure
+        }
+    }
+}
+
+/// Number of paths of a change shown by `audit` without `--full`
+const AUDIT_PATHS_SHOWN: usize = 3;
+
+/// Shows the latest `limit` changes in the audit log
+pub async fn handle_audit(conn: &rusqlite::Connection, limit: usize, full: bool) {
+    let records = db::get_audit_log(conn, Some(limit)).expect("Could not get the audit log");
+    if records.is_empty() {
+        println!("No changes have been made to the store yet");
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct AuditRow {
+        id: i32,
+        at: String,
+        user: String,
+        action: String,
+        paths: String,
+    }
+
+    let rows = records
+        .iter()
+        .map(|r| {
+            let shown = if full {
+                r.paths.len()
+            } else {
+                AUDIT_PATHS_SHOWN
+            };
+            let mut paths = r
+                .paths
+                .iter()
+                .take(shown)
+                .map(utils::display_path)
+                .collect::<Vec<_>>();
+            if r.paths.len() > shown {
+                paths.push(format!("and {} more", r.paths.len() - shown));
+            }
+
+            AuditRow {
+                id: r.id,
+                at: r.at.to_rfc2822(),
+                user: r.user.clone(),
+                action: r.action.clone(),
+                paths: paths.join("\n"),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let table = Table::new(rows)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Audit Log"))
+        .to_string();
+
+    println!("{}", table);
+}
+
+/// Prints the recent paste targets, one per line,
+/// which the shell completions use for `--output`
+///
+/// A target with a newline in it would be taken for two, so it is left out
+pub async fn handle_targets(conn: &rusqlite::Connection) {
+    db::get_recent_targets(conn, None, RECENT_TARGETS)
+        .expect("Could not get targets")
+        .iter()
+        .filter(|t| !t.contains(['\n', '\r']))
+        .for_each(|t| println!("{}", t));
+}
+
+/// Suggests the entries to paste into the current directory from the paste history,
+/// pasting the ones chosen from a checklist
+///
+/// The chosen entries are never deleted, even with `delete` set in the config,
+/// as the entries worth suggesting are the ones pasted again and again
+pub async fn handle_suggest(
+    mut args: ConstructedArgs,
+    conn: &rusqlite::Connection,
+    limit: usize,
+) -> ExitCode {
+    let cwd = std::env::current_dir()
+        .and_then(|d| d.canonicalize())
+        .expect("Could not get the current directory");
+    let entries = db::get_all(conn).expect("Could not get entries from database");
+    let history = db::get_paste_history(conn).expect("Could not get the paste history");
+
+    let mut suggestions = suggest::suggest(&entries, &history, &cwd);
+    suggestions.truncate(limit);
+    if suggestions.is_empty() {
+        println!("Nothing to suggest here, the entries you paste will be suggested once you have");
+        return ExitCode::NotFound;
+    }
+
+    #[derive(Tabled)]
+    struct SuggestionRow {
+        #[tabled(rename = "#")]
+        rank: usize,
+        id: i32,
+        name: String,
+        why: String,
+    }
+
+    let rows = suggestions
+        .iter()
+        .enumerate()
+        .map(|(i, s)| SuggestionRow {
+            rank: i + 1,
+            id: s.entry.id,
+            name: s.entry.name.clone(),
+            why: s.reason.clone(),
+        })
+        .collect::<Vec<_>>();
+    let table = Table::new(rows)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Suggested For Here"))
+        .to_string();
+    println!("{}", table);
+
+    if !args.prompt || !atty::is(atty::Stream::Stdin) {
+        println!(
+            "Paste one with {}",
+            format!("ynk paste {}", suggestions[0].entry.id).cyan()
+        );
+        return ExitCode::Success;
+    }
+
+    let choices = suggestions
+        .iter()
+        .map(|s| format!("{} {}", s.entry.id, s.entry.name))
+        .collect::<Vec<_>>();
+    let chosen = inquire::MultiSelect::new("Paste which entries here?", choices)
+        .with_help_message("space to select, enter to paste")
+        .raw_prompt()
+        .unwrap_or_else(|_| {
+            status!("Ok! Quitting");
+            ExitCode::Cancelled.exit();
+        });
+    if chosen.is_empty() {
+        println!("No entries chosen, so nothing is pasted");
+        return ExitCode::Cancelled;
+    }
+
+    // The entries are pasted by their uuid, which no other entry can match
+    args.files = Some(
+        chosen
+            .iter()
+            .map(|c| suggestions[c.index].entry.uuid.clone())
+            .collect(),
+    );
+    args.matching = Matching::Exact;
+    args.all_matches = true;
+    args.select = false;
+    args.range = None;
+    args.latest = None;
+    args.specific = None;
+    args.delete = false;
+    args.move_files = false;
+
+    handle_paste(args, conn, Some(cwd.to_string_lossy().to_string())).await
+}
+
+/// Summarizes the store from what is cached, so it is fast enough for a prompt
+///
+/// Sizes that were never counted are left out rather than counted here,
+/// which `list --size` or `refresh` fill in
+pub fn handle_status(conn: &rusqlite::Connection, short: bool, json: bool) {
+    let summary = db::summary(conn).expect("Could not summarize the store");
+    let top_name = summary.top.as_ref().map(|(name, _)| name.as_str());
+
+    if json {
+        let mut status = serde_json::json!({
+            "count": summary.count,
+            "top_name": top_name,
+            "total_size": summary.total_size,
+        });
+        if !short {
+            status["top_path"] = serde_json::json!(summary.top.as_ref().map(|(_, path)| path));
+            status["uncounted"] = serde_json::json!(summary.uncounted);
+        }
+        println!("{}", status);
+        return;
+    }
+
+    let size = utils::convert_size(summary.total_size as f64 / 1024.0);
+    if short {
+        match top_name {
+            Some(name) => println!("{} {} {}", summary.count, name, size),
+            None => println!("0"),
+        }
+        return;
+    }
+
+    let Some((name, path)) = &summary.top else {
+        println!("No entries in the store");
+        return;
+    };
+    println!(
+        "{} entries, {} in total",
+        summary.count.to_string().green(),
+        size.green()
+    );
+    if summary.uncounted > 0 {
+        println!(
+            "{} entries haven't been counted, run {} to count them",
+            summary.uncounted.to_string().yellow(),
+            "ynk list --size".cyan()
+        );
+    }
+    println!(
+        "{} is on top, from {}",
+        name.blue(),
+        utils::display_path(path)
+    );
+}
+
+/// Prints the number of entries in the format for a shell prompt,
+/// or nothing when the store is empty so the prompt stays as it is
+pub fn handle_prompt_status(conn: &rusqlite::Connection, format: &str) {
+    let count = db::count_entries(conn).expect("Could not count the entries");
+    if count > 0 {
+        println!("{}", format.replace("{count}", &count.to_string()));
+    }
+}
+
+/// Prints what the queries of an entry can be completed with, one per line,
+/// the id of each entry and then the names, latest first, each with a tab and a description
+///
+/// The names with a tab or a newline in them can't be told apart from the description
+/// or the next line, so they are left to their ids, while the descriptions have them replaced
+pub fn handle_complete_queries(conn: &rusqlite::Connection) {
+    let mut entries = db::get_all(conn).expect("Could not get entries from database");
+    sort_entries(&mut entries);
+
+    let one_line = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+    entries
+        .iter()
+        .for_each(|e| println!("{}\t{}", e.id, one_line(&e.name)));
+    let mut seen = HashSet::new();
+    entries
+        .iter()
+        .filter(|e| !e.name.contains(['\t', '\n', '\r']))
+        .filter(|e| seen.insert(e.name.as_str()))
+        .for_each(|e| println!("{}\t{}", e.name, one_line(&utils::display_path(&e.path))));
+}
+
+/// Benchmarks the copy engine on a generated tree of `files` files of `size` bytes each,
+/// pasting it like `paste` does, once copying in the kernel and once for every buffer size
+/// with each backend, along with the `mmap_threshold` set in the config
+pub async fn handle_bench(
+    args: ConstructedArgs,
+    files: usize,
+    size: u64,
+    buffer_sizes: Vec<usize>,
+) {
+    let root = std::env::temp_dir().join(format!("ynk-bench-{}", std::process::id()));
+    let source = root.join("source");
+
+    println!(
+        "Generating {} files of {} in {}",
+        files.to_string().green(),
+        utils::convert_size(size as f64 / 1024.0).green(),
+        root.to_string_lossy()
+    );
+
+    let contents = (0..size).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    let sources = (0..files)
+        .map(|i| {
+            // Spread the files over a few directories, like a real tree
+            let path = source
+                .join(format!("{}", i % 16))
+                .join(format!("{}.bin", i));
+            std::fs::create_dir_all(path.parent().unwrap()).expect("Could not create bench dir");
+            std::fs::write(&path, &contents).expect("Could not write bench file");
+            path
+        })
+        .collect::<Vec<_>>();
+
+    #[derive(Tabled)]
+    struct BenchResult {
+        backend: &'static str,
+        buffer: String,
+        seconds: String,
+        files_per_sec: String,
+        throughput: String,
+    }
+
+    let mut runs = vec![(CopyBackend::Tokio, None)];
+    runs.extend(buffer_sizes.iter().map(|b| (CopyBackend::Tokio, Some(*b))));
+    if cfg!(all(target_os = "linux", feature = "io-uring")) {
+        runs.extend(
+            buffer_sizes
+                .iter()
+                .map(|b| (CopyBackend::IoUring, Some(*b))),
+        );
+    }
+
+    let mut results = Vec::new();
+    for (i, (backend, buffer_size)) in runs.into_iter().enumerate() {
+        let target = root.join(format!("target-{}", i));
+        let plan = engine::PastePlan {
+            files: sources
+                .iter()
+                .map(|path| engine::PasteFile {
+                    source: path.clone(),
+                    target: target.join(utils::wrap_from_path(&source, path).0),
+                    move_source: false,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut args = args.clone();
+        args.copy_backend = backend;
+        args.copy_buffer_size = buffer_size;
+
+        let start = std::time::Instant::now();
+        let result = paste_with_backend(&plan, &args, &ProgressBar::hidden()).await;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        if let Some((target, e)) = result.failed.first() {
+            eprintln!("{} {}: {}", "Bench copy failed:".red(), target.display(), e);
+        }
+
+        let total = (files as u64 * size) as f64;
+        results.push(BenchResult {
+            backend: match backend {
+                CopyBackend::Tokio => "tokio",
+                CopyBackend::IoUring => "io_uring",
+            },
+            buffer: match buffer_size {
+                Some(b) => utils::convert_size(b as f64 / 1024.0),
+                None => "kernel".to_string(),
+            },
+            seconds: format!("{:.3}", elapsed),
+            files_per_sec: format!("{:.0}", files as f64 / elapsed),
+            throughput: format!("{}/s", utils::convert_size(total / 1024.0 / elapsed)),
+        });
+
+        let _ = std::fs::remove_dir_all(&target);
+    }
+
+    let _ = std::fs::remove_dir_all(&root);
+
+    let table = Table::new(results)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Copy Engine Benchmark"))
+        .to_string();
+    println!("{}", table);
+}
+
+pub async fn handle_list(args: ConstructedArgs, conn: &rusqlite::Connection) {
+    let mut entries = db::get_all(conn).expect("Could not get entries from database");
+
+    let plain = args.names_only || args.paths_only;
+    // Ranges are resolved against the ids as they are shown here
+    if !plain {
+        db::record_listing(conn, &entries).expect("Could not record the listed ids");
+    }
+
+    if entries.is_empty() && !plain {
+        eprintln!("{}", "No entries in the store".red());
+        ExitCode::NotFound.exit();
+    }
+
+    // Entries added before types were tracked are detected once and cached
+    for entry in entries.iter_mut().filter(|e| e.file_type.is_none()) {
+        let file_type = utils::detect_file_type(Path::new(&entry.path));
+        db::update_file_type(conn, &entry.path, &file_type)
+            .expect("Could not update entry in database");
+        entry.file_type = Some(file_type);
+    }
+
+    if let Some(filter) = &args.file_type {
+        entries.retain(|e| utils::matches_file_type(e.file_type.as_deref().unwrap(), filter));
+    }
+    if let Some(is_dir) = args.is_dir {
+        entries.retain(|e| Path::new(&e.path).is_dir() == is_dir);
+    }
+
+    if entries.is_empty() && !plain {
+        eprintln!("{}", "No entries match the filters".red());
+        ExitCode::NotFound.exit();
+    }
+
+    sort_entries(&mut entries);
+    if let Some(key) = &args.sort {
+        if key == "size" {
+            for e in entries.iter_mut() {
+                let (count, size) = entry_usage(&args, conn, e, false);
+                e.file_count = Some(count);
+                e.size = Some(size);
+            }
+        }
+        utils::sort_entries_by(&mut entries, key);
+    }
+
+    // The footprint of all the entries, before only the top ones are kept
+    let footprint = entries.iter().filter_map(|e| e.size).sum::<i64>();
+    let listed = entries.len();
+    let poppable = entries.iter().max_by_key(|e| e.id).cloned();
+    if let Some(top) = args.top {
+        entries.truncate(top);
+    }
+
+    // Plain output for scripts, where no entries is just no lines
+    if plain {
+        let terminator = if args.print0 { '\0' } else { '\n' };
+        let mut stdout = std::io::stdout().lock();
+        for e in &entries {
+            let line = if args.paths_only { &e.path } else { &e.name };
+            let _ = write!(stdout, "{}{}", line, terminator);
+        }
+        return;
+    }
+
+    // Anything else in the config falls back to the detailed table
+    if matches!(args.list_style.as_str(), "compact" | "icons") {
+        print_compact(&entries, args.list_style == "icons");
+        return;
+    }
+
+    println!("{}  entries in the store", listed.to_string().green());
+    let mut count = 0;
+
+    #[derive(Tabled)]
+    struct DisplayFiles {
+        id: usize,
+        uuid: String,
+        name: String,
+        path: String,
+        count: usize,
+        size: String,
+        is_dir: bool,
+        #[tabled(rename = "type")]
+        file_type: String,
+        created: String,
+        last_accessed: String,
+    }
+
+    #[derive(Tabled)]
+    struct PartialDisplayFiles {
+        id: usize,
+        uuid: String,
+        name: String,
+        path: String,
+        is_dir: bool,
+        #[tabled(rename = "type")]
+        file_type: String,
+        created: String,
+    }
+
+    let mut paste_config = args;
+    paste_config.specific = None;
+
+    let longest_path = entries
+        .iter()
+        .map(|e| utils::display_path(&e.path).width())
+        .max()
+        .unwrap_or_default();
+
+    // TODO: Better way to handle the calculate size flag
+    #[allow(unused_assignments)]
+    let mut table = String::new();
+
+    let mut total_size = 0;
+    if paste_config.calculate_size {
+        let mut display_contents = Vec::new();
+        entries.iter().for_each(|x| {
+            // Sizes are shown fresh, which also keeps the cache up to date
+            let (file_count, size) = match (x.file_count, x.size) {
+                (Some(count), Some(size)) if paste_config.sort.as_deref() == Some("size") => {
+                    (count, size)
+                }
+                _ => entry_usage(&paste_config, conn, x, true),
+            };
+
+            total_size += size;
 
             display_contents.push(DisplayFiles {
                 id: x.id as usize,
+                uuid: x.short_uuid().to_string(),
                 name: x.name.clone(),
-                path: x.path.clone(),
-                count: file_count,
+                path: utils::display_path(&x.path),
+                count: file_count as usize,
                 is_dir: x.is_dir,
-                size: utils::convert_size(size),
+                size: utils::convert_size(size as f64 / 1024.0),
+                file_type: x.file_type.clone().unwrap_or_default(),
+                created: x.created_at.to_rfc2822(),
                 last_accessed: x.accessed_at.to_rfc2822(),
             });
             count += 1;
         });
 
-        table = Table::new(display_contents)
-            .with(Style::modern_rounded())
-            .with(Panel::header("Entries in The Store"))
-            .to_string();
+        table = list_table(Table::new(display_contents), &paste_config, longest_path);
     } else {
         let mut display_contents = Vec::new();
         entries.iter().for_each(|x| {
             display_contents.push(PartialDisplayFiles {
                 id: x.id as usize,
+                uuid: x.short_uuid().to_string(),
                 name: x.name.clone(),
-                path: x.path.clone(),
+                path: utils::display_path(&x.path),
                 is_dir: x.is_dir,
+                file_type: x.file_type.clone().unwrap_or_default(),
+                created: x.created_at.to_rfc2822(),
             });
             count += 1;
         });
 
-        table = Table::new(display_contents)
-            .with(Style::modern_rounded())
-            .with(Panel::header("Entries in The Store"))
-            .to_string();
+        table = list_table(Table::new(display_contents), &paste_config, longest_path);
     }
 
     println!("{}", table);
@@ -466,12 +2938,162 @@ pub async fn handle_list(args: ConstructedArgs, conn: &rusqlite::Connection) {
     if paste_config.calculate_size {
         println!(
             "Total size of {} kept track",
-            utils::convert_size(total_size).green()
+            utils::convert_size(total_size as f64 / 1024.0).green()
+        );
+    }
+    if paste_config.top.is_some() && paste_config.sort.as_deref() == Some("size") {
+        println!(
+            "The {} largest of the {} entries take {} of the {} in the store",
+            entries.len().to_string().green(),
+            listed.to_string().green(),
+            utils::convert_size(entries.iter().filter_map(|e| e.size).sum::<i64>() as f64 / 1024.0)
+                .green(),
+            utils::convert_size(footprint as f64 / 1024.0).green()
+        );
+        println!("Delete the ones no longer needed with ynk delete, and reclaim snapshot space with ynk gc");
+    }
+    let poppable = poppable.unwrap();
+    println!(
+        "The entry {} can be popped",
+        utils::display_path(&poppable.path).blue()
+    );
+
+    println!(
+        "Use ynk paste to paste the files, or {} for just that one",
+        format!("ynk paste {}", utils::shell_quote(&poppable.name)).blue()
+    );
+}
+
+pub async fn handle_list_deleted(conn: &rusqlite::Connection) {
+    let trash = db::get_trash(conn).expect("Could not get deleted entries from database");
+
+    if trash.is_empty() {
+        eprintln!("{}", "No deleted entries in the trash".red());
+        ExitCode::NotFound.exit();
+    }
+
+    #[derive(Tabled)]
+    struct DisplayDeleted {
+        id: usize,
+        uuid: String,
+        name: String,
+        path: String,
+        deleted: String,
+    }
+
+    let display_contents = trash
+        .iter()
+        .map(|d| DisplayDeleted {
+            id: d.entry.id as usize,
+            uuid: d.entry.short_uuid().to_string(),
+            name: d.entry.name.clone(),
+            path: utils::display_path(&d.entry.path),
+            deleted: d.deleted_at.to_rfc2822(),
+        })
+        .collect::<Vec<_>>();
+
+    let table = Table::new(display_contents)
+        .with(Style::modern_rounded())
+        .with(Panel::header("Deleted Entries"))
+        .to_string();
+
+    println!("{}", table);
+    println!("Use ynk restore --deleted to bring them back");
+}
+
+/// Prints the entries one per line without the table,
+/// prefixed by a glyph for their type with `icons`
+fn print_compact(entries: &[db::Entry], icons: bool) {
+    for entry in entries {
+        let icon = if icons {
+            format!(
+                "{} ",
+                utils::file_type_icon(entry.file_type.as_deref().unwrap_or_default())
+            )
+        } else {
+            String::new()
+        };
+        let name = if Path::new(&entry.path).is_dir() {
+            entry.name.blue().bold()
+        } else {
+            entry.name.normal()
+        };
+
+        println!(
+            "{:>3} {} {}{} {}",
+            entry.id.to_string().green(),
+            entry.short_uuid().dimmed(),
+            icon,
+            name,
+            utils::display_path(&entry.path).dimmed()
+        );
+    }
+}
+
+/// Builds the progress bar as set in the config, or a hidden one with `--no-progress`
+///
+/// An invalid template falls back to the default one
+fn progress_bar(len: u64, config: Option<&ProgressConfig>) -> ProgressBar {
+    let Some(config) = config else {
+        return ProgressBar::hidden();
+    };
+
+    let template = ProgressStyle::with_template(&config.template).unwrap_or_else(|e| {
+        println!(
+            "{} {}",
+            "Invalid progress_template in the config:".yellow(),
+            e
         );
+        ProgressStyle::with_template(&default_progress_template()).unwrap()
+    });
+    // indicatif needs at least two characters for both
+    let mut style = template;
+    if config.progress_chars.chars().count() >= 2 {
+        style = style.progress_chars(&config.progress_chars);
+    }
+    if let Some(spinner) = config
+        .spinner_chars
+        .as_ref()
+        .filter(|s| s.chars().count() >= 2)
+    {
+        style = style.tick_chars(spinner);
+    }
+
+    ProgressBar::with_draw_target(
+        Some(len),
+        ProgressDrawTarget::stderr_with_hz(config.refresh_rate.max(1)),
+    )
+    .with_style(style)
+}
+
+/// Styles the table of entries, leaving out the created and type columns unless asked for
+///
+/// Unless `--full-path` is passed, the paths are shortened
+/// in the middle so that the table fits the terminal
+fn list_table(mut table: Table, args: &ConstructedArgs, longest_path: usize) -> String {
+    if !args.show_created {
+        table.with(Remove::column(ByColumnName::new("created")));
+    }
+    if !args.show_types {
+        table.with(Remove::column(ByColumnName::new("type")));
+    }
+    table.with(Style::modern_rounded());
+
+    if let (false, Some(width)) = (args.full_path, utils::terminal_width()) {
+        let overflow = table.total_width().saturating_sub(width);
+        if overflow > 0 {
+            // Don't shorten the paths past the point of being useful
+            let max = longest_path.saturating_sub(overflow).max(24);
+            table.with(
+                Modify::new(ByColumnName::new("path"))
+                    .with(Format::content(|p| utils::truncate_middle(p, max))),
+            );
+        }
     }
-    println!("The entry {} can be popped", entries[0].path.blue(),);
 
-    println!("Use ynk paste to paste the files");
+    table
+        .with(Panel::header("Entries in The Store"))
+        .to_string()
 }
 
 fn map_to_shell(shell: &str) -> Shell {
@@ -485,10 +3107,266 @@ fn map_to_shell(shell: &str) -> Shell {
 }
 
 pub fn handle_completions(command: &mut Command, shell: String) {
-    let sh = map_to_shell(&shell);
+    println!("{}", completions_script(command, map_to_shell(&shell)));
+}
+
+/// Replaces the running ynk with the binary of the latest release,
+/// once its checksum matches the one released with it
+pub fn handle_update(args: &ConstructedArgs, check: bool, force: bool) {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = match update::latest_release() {
+        Ok(release) => release,
+        Err(e) => {
+            eprintln!("{} {}", "Could not get the latest release:".red(), e);
+            ExitCode::Failure.exit();
+        }
+    };
+
+    if !update::is_newer(&release.tag_name, current) && !force {
+        status!("ynk is up to date, at {}", current.green());
+        return;
+    }
+    if check {
+        status!(
+            "A new version of ynk is available: {}, at {}",
+            release.tag_name.yellow(),
+            current
+        );
+        return;
+    }
+
+    if update::installed_by_cargo() && !force {
+        eprintln!(
+            "{} run {} instead, or pass {} to replace it anyway",
+            "ynk was installed with cargo,".yellow(),
+            "cargo install ynk --locked".cyan(),
+            "--force".cyan()
+        );
+        ExitCode::Conflict.exit();
+    }
+
+    if update::PUBLIC_KEY.is_none() {
+        eprintln!(
+            "{} install it with {}",
+            "This build of ynk can't verify the signatures of releases,".red(),
+            "cargo install ynk --locked".cyan()
+        );
+        ExitCode::Failure.exit();
+    }
+
+    let Some((binary, checksum, signature)) = release.binary() else {
+        eprintln!(
+            "{} {} has no {}, install it with {}",
+            "There is no release binary for this platform,".red(),
+            release.tag_name,
+            update::asset_name(),
+            "cargo install ynk --locked".cyan()
+        );
+        ExitCode::NotFound.exit();
+    };
+
+    if args.prompt {
+        let choice = inquire::Confirm::new(&format!(
+            "Update ynk from {} to {}?",
+            current, release.tag_name
+        ))
+        .with_default(true)
+        .prompt()
+        .unwrap_or(false);
+
+        if !choice {
+            status!("Ok! Quitting");
+            ExitCode::Cancelled.exit();
+        }
+    }
+
+    status!("Downloading {}", binary.name.blue());
+    let downloaded = update::download(binary).and_then(|bytes| {
+        let checksum = update::download(checksum)?;
+        update::verify(&bytes, &String::from_utf8_lossy(&checksum))
+            .map_err(|e| format!("the checksum doesn't match, {}", e))?;
+        let signature = update::download(signature)?;
+        update::verify_signature(&bytes, &String::from_utf8_lossy(&signature))
+            .map_err(|e| format!("the signature isn't valid, {}", e))?;
+        Ok(bytes)
+    });
+    let bytes = match downloaded {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{} {}", "Could not download the release:".red(), e);
+            ExitCode::Failure.exit();
+        }
+    };
+
+    match update::install(&bytes) {
+        Ok(exe) => {
+            tracing::info!(from = current, to = release.tag_name, "updated ynk");
+            status!(
+                "Updated {} from {} to {}",
+                utils::display_path(&exe),
+                current,
+                release.tag_name.green()
+            );
+        }
+        Err(e) => {
+            eprintln!("{} {}", "Could not replace ynk:".red(), e);
+            ExitCode::Failure.exit();
+        }
+    }
+}
+
+/// Prints the completions and the line editor widgets for the shell,
+/// for its rc file to evaluate, ex: `eval "$(ynk init zsh)"`
+pub fn handle_init(command: &mut Command, shell: &str, bind: bool) {
+    let sh = map_to_shell(shell);
+    println!("{}", completions_script(command, sh));
+    if let Some(widgets) = widgets::script(sh, bind) {
+        print!("{}", widgets);
+    }
+}
+
+/// The completions for the shell, with the queries and outputs
+/// completed from the store on top of what clap generates
+fn completions_script(command: &mut Command, sh: Shell) -> String {
     let mut res: Vec<u8> = Vec::new();
     generate(sh, command, command.get_name().to_string(), &mut res);
 
-    let completions = String::from_utf8_lossy(&res).to_string();
-    println!("{}", completions);
+    let mut completions = String::from_utf8_lossy(&res).to_string();
+
+    // The subcommands taking queries, which are completed with the entries in the store
+    let query_cmds = command
+        .get_subcommands()
+        .filter(|c| c.get_arguments().any(|a| a.get_id() == "queries"))
+        .map(|c| c.get_name().to_string())
+        .collect::<Vec<_>>();
+
+    // Completes --output with the recent paste targets, on top of directories
+    // The functions are swapped into what clap generates, which is checked
+    // so that a change in its output doesn't silently drop them
+    if sh == Shell::Zsh {
+        let output_arg = "[The output dir or file]: :_files -/'";
+        assert!(
+            completions.contains(output_arg),
+            "clap no longer completes --output with _files"
+        );
+        completions = completions.replace(output_arg, "[The output dir or file]: :_ynk_output'");
+        let output_fn = r#"(( $+functions[_ynk_output] )) ||
+_ynk_output() {
+    local -a targets
+    targets=(${(f)"$(ynk targets 2>/dev/null)"})
+    _alternative 'targets:recent target:compadd -a targets' 'directories:directory:_files -/'
+}
+
+"#;
+        let mut replaced = 0;
+        completions = completions
+            .lines()
+            .map(
+                |line| match line.contains(":QUERIES -- ") && line.contains(":_default'") {
+                    true => {
+                        replaced += 1;
+                        line.replace(":_default'", ":_ynk_queries'")
+                    }
+                    false => line.to_string(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(
+            replaced >= query_cmds.len(),
+            "clap no longer completes the queries with _default"
+        );
+        let queries_fn = r#"(( $+functions[_ynk_queries] )) ||
+_ynk_queries() {
+    local -a queries
+    local line
+    for line in ${(f)"$(ynk complete-queries 2>/dev/null)"}; do
+        # _describe takes what is before the first unescaped colon as the value,
+        # so the backslashes and colons in it are escaped
+        queries+=("${${${line%%$'\t'*}//\\/\\\\}//:/\\:}:${line#*$'\t'}")
+    done
+    _describe -t queries 'entry' queries
+}
+
+"#;
+        let pos = completions
+            .rfind("if [ \"$funcstack[1]\" = \"_ynk\" ]")
+            .expect("clap no longer ends the completions by calling _ynk");
+        completions.insert_str(pos, output_fn);
+        completions.insert_str(pos, queries_fn);
+    }
+    match sh {
+        Shell::Fish => {
+            for cmd in ["paste", "cut", "pop"] {
+                completions.push_str(&format!(
+                    "\ncomplete -c ynk -n \"__fish_ynk_using_subcommand {}\" -s o -l output -xa '(ynk targets 2>/dev/null; __fish_complete_directories)'",
+                    cmd
+                ));
+            }
+            for cmd in &query_cmds {
+                completions.push_str(&format!(
+                    "\ncomplete -c ynk -n \"__fish_ynk_using_subcommand {}\" -f -a '(ynk complete-queries 2>/dev/null)'",
+                    cmd
+                ));
+            }
+        }
+        Shell::Bash => {
+            completions.push_str(&format!(
+                r#"
+_ynk_dynamic() {{
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    if [[ "$prev" == "-o" || "$prev" == "--output" ]]; then
+        # The targets are matched as they are, rather than through compgen -W,
+        # which would expand any quotes and $ in them
+        local cur="${{COMP_WORDS[COMP_CWORD]}}" target IFS=$'\n'
+        COMPREPLY=()
+        while IFS= read -r target; do
+            [[ "$target" == "$cur"* ]] && COMPREPLY+=("$target")
+        done < <(ynk targets 2>/dev/null)
+        COMPREPLY+=($(compgen -d -- "$cur"))
+        # Escapes the spaces and quotes in the completed paths
+        compopt -o filenames
+        return 0
+    fi
+    # The subcommand is the first word that isn't a flag
+    local word cmd cur="${{COMP_WORDS[COMP_CWORD]}}"
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        [[ "$word" != -* ]] && {{ cmd="$word"; break; }}
+    done
+    if [[ " {} " == *" $cmd "* && "$cur" != -* && "$prev" != -* ]]; then
+        local query IFS=$'\n'
+        COMPREPLY=()
+        while IFS=$'\t' read -r query _; do
+            [[ "$query" == "$cur"* ]] && COMPREPLY+=("$query")
+        done < <(ynk complete-queries 2>/dev/null)
+        compopt -o filenames
+        return 0
+    fi
+    _ynk "$@"
+}}
+complete -F _ynk_dynamic -o bashdefault -o default ynk"#,
+                query_cmds.join(" ")
+            ));
+        }
+        _ => {}
+    }
+
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_swap_in_the_store_functions() {
+        for sh in [Shell::Zsh, Shell::Bash, Shell::Fish] {
+            completions_script(&mut utils::setup_cli(), sh);
+        }
+
+        let zsh = completions_script(&mut utils::setup_cli(), Shell::Zsh);
+        assert!(zsh.contains(":_ynk_output'"));
+        assert!(zsh.contains(":_ynk_queries'"));
+        assert!(zsh.contains("_ynk_queries() {"));
+    }
 }
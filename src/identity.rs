@@ -0,0 +1,179 @@
+//! The identity of the file an entry was added from, so an entry whose file
+//! was renamed or moved within the same filesystem can be found again, with `verify --fix`
+//!
+//! On Unix it is the device and inode of the file, and on Windows
+//! the volume serial number and file index, both of which a rename keeps.
+//! The time the file was created is kept with them where the filesystem records it,
+//! as the inode of a deleted file can be reused by a new one
+
+use std::path::{Path, PathBuf};
+
+/// How many directories above the nearest one that still exists are searched
+const SEARCH_LEVELS: usize = 2;
+/// How deep the directories are searched for a moved file
+const SEARCH_DEPTH: usize = 6;
+
+/// Identifies a file on a machine for as long as it isn't copied or deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    /// The device, or the volume serial number on Windows
+    pub dev: u64,
+    /// The inode, or the file index on Windows
+    pub ino: u64,
+    /// When the file was created, in nanoseconds since the epoch,
+    /// `None` where the filesystem doesn't record it
+    pub born: Option<i64>,
+}
+
+impl FileId {
+    /// The identity of the file or directory at the path, without following a symlink
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> Option<FileId> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::symlink_metadata(path).ok()?;
+        Some(FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            born: birth_time(&metadata),
+        })
+    }
+
+    /// The identity of the file or directory at the path, without following a symlink
+    #[cfg(windows)]
+    pub fn of(path: &Path) -> Option<FileId> {
+        use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+        use windows_sys::Win32::Storage::FileSystem::{
+            GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+            FILE_FLAG_OPEN_REPARSE_POINT,
+        };
+
+        // Directories can only be opened with backup semantics
+        let file = std::fs::OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+            .open(path)
+            .ok()?;
+        let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+        if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+            return None;
+        }
+
+        Some(FileId {
+            dev: info.dwVolumeSerialNumber as u64,
+            ino: ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64,
+            born: file.metadata().ok().as_ref().and_then(birth_time),
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn of(_path: &Path) -> Option<FileId> {
+        None
+    }
+
+    /// The identity as it is stored, as SQLite integers are signed
+    pub fn to_stored(self) -> (i64, i64, Option<i64>) {
+        (self.dev as i64, self.ino as i64, self.born)
+    }
+
+    /// The identity from the stored columns, if the device and inode were recorded
+    pub fn from_stored(dev: Option<i64>, ino: Option<i64>, born: Option<i64>) -> Option<FileId> {
+        Some(FileId {
+            dev: dev? as u64,
+            ino: ino? as u64,
+            born,
+        })
+    }
+
+    /// Whether this is the same file as `other`, which a reused inode isn't
+    /// as it was created at another time, unless either time wasn't recorded
+    pub fn same_file(&self, other: &FileId) -> bool {
+        self.dev == other.dev
+            && self.ino == other.ino
+            && match (self.born, other.born) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+    }
+}
+
+/// When the file was created, in nanoseconds since the epoch
+fn birth_time(metadata: &std::fs::Metadata) -> Option<i64> {
+    let created = metadata.created().ok()?;
+    let since = created.duration_since(std::time::UNIX_EPOCH).ok()?;
+    i64::try_from(since.as_nanos()).ok()
+}
+
+/// Looks for the file with the identity, near where it was last seen
+///
+/// The nearest directory above `old` that still exists is searched first,
+/// and then the ones above it, up to [`SEARCH_LEVELS`] of them.
+/// Only the filesystem it was on is searched, as a move to another one makes a new file.
+/// A file is only taken if it is the same kind as the entry, a directory or not, when known
+pub fn locate(old: &Path, id: FileId, is_dir: Option<bool>) -> Option<PathBuf> {
+    let nearest = old.ancestors().skip(1).find(|dir| dir.is_dir())?;
+
+    let mut searched: Option<PathBuf> = None;
+    for dir in nearest.ancestors().take(SEARCH_LEVELS + 1) {
+        if !matches!(FileId::of(dir), Some(d) if d.dev == id.dev) {
+            break;
+        }
+
+        let skip = searched.take();
+        let found = ignore::WalkBuilder::new(dir)
+            .standard_filters(false)
+            .same_file_system(true)
+            .max_depth(Some(SEARCH_DEPTH))
+            // The directory searched before this one was already gone through
+            .filter_entry(move |e| skip.as_deref() != Some(e.path()))
+            .build()
+            .filter_map(Result::ok)
+            .skip(1)
+            .find(|e| {
+                let kind = e.file_type().map(|t| t.is_dir());
+                let same_kind = match (is_dir, kind) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => true,
+                };
+                same_kind && FileId::of(e.path()).is_some_and(|found| found.same_file(&id))
+            });
+        if let Some(found) = found {
+            return Some(found.into_path());
+        }
+        searched = Some(dir.to_path_buf());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db,
+        testing::{add, store, TempDir},
+    };
+
+    #[test]
+    fn moved_files_are_found_again() {
+        let dir = TempDir::new();
+        let file = dir.write("drafts/notes.txt", "hello");
+        let conn = store();
+
+        let entry = add(&conn, &[file.to_str().unwrap()]).remove(0);
+        let id = entry.file_id.expect("the identity should be recorded");
+
+        let moved = dir.0.join("archive/2024/notes-final.txt");
+        std::fs::create_dir_all(moved.parent().unwrap()).unwrap();
+        std::fs::rename(&file, &moved).unwrap();
+
+        assert_eq!(locate(&file, id, Some(true)), None);
+        assert_eq!(locate(&file, id, Some(false)), Some(moved.clone()));
+
+        let moved = moved.to_string_lossy().to_string();
+        db::relocate_entry(&conn, &entry.path, &moved).unwrap();
+        let entries = db::get_all(&conn).unwrap();
+        assert_eq!(entries[0].path, moved);
+        assert_eq!(entries[0].uuid, entry.uuid);
+    }
+}
@@ -0,0 +1,38 @@
+//! ynk, effortlessly yank and paste files in the terminal
+//!
+//! The `ynk` binary is a thin command line over these modules,
+//! and the [`engine`] can be used to add and paste entries
+//! without it, as the integration tests do
+
+pub mod clipboard;
+pub mod config;
+pub mod daemon;
+pub mod db;
+pub mod engine;
+pub mod exit;
+pub mod files;
+pub mod fs;
+pub mod handler;
+pub mod identity;
+pub mod lock;
+mod migrations;
+pub mod mmap;
+pub mod owner;
+pub mod portable;
+pub mod preserve;
+pub mod qr;
+pub mod rpc;
+pub mod selection;
+pub mod serve;
+pub mod snapshot;
+pub mod suggest;
+pub mod symlinks;
+#[cfg(test)]
+mod testing;
+pub mod update;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+pub mod utils;
+pub mod widgets;
+
+pub use config::ConstructedArgs;
@@ -0,0 +1,52 @@
+//! An advisory lock on the store, held by the invocations that change it
+//!
+//! Without it, two `ynk paste --delete` run at the same time could both paste
+//! and delete the same entry, or one could renumber the entries while the other
+//! is deleting them. Invocations that only read the store don't take the lock,
+//! and rely on SQLite to see the changes made by others as a whole
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+};
+
+use colored::Colorize;
+
+use crate::files::get_path;
+
+/// The name of the lock file in the store directory
+pub const LOCK_NAME: &str = "ynk.lock";
+
+/// The lock on the store, released once dropped or when ynk exits
+#[derive(Debug)]
+pub struct StoreLock {
+    _file: File,
+}
+
+/// Takes the lock on the store, waiting for any other invocation holding it to finish
+///
+/// The lock is taken on a file next to the database, rather than the database itself,
+/// so it can be held across the separate transactions a command makes
+pub fn lock_store() -> io::Result<StoreLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(get_path(LOCK_NAME))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(std::fs::TryLockError::WouldBlock) => {
+            eprintln!(
+                "{}",
+                "Waiting for another ynk to finish with the store...".yellow()
+            );
+            tracing::info!("waiting for the store lock");
+            file.lock()?;
+        }
+        Err(std::fs::TryLockError::Error(e)) => return Err(e),
+    }
+    tracing::debug!("took the store lock");
+
+    Ok(StoreLock { _file: file })
+}
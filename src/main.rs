@@ -1,54 +1,237 @@
-use config::{get_config_from_file, write_default_config, ConstructedArgs};
-use files::get_config_path;
-use utils::{check_version, print_splash_screen, setup_cli};
-
-mod config;
-mod db;
-mod files;
-mod handler;
-mod utils;
+use colored::Colorize;
+use ynk::{
+    clipboard,
+    config::{get_config_from_file, write_default_config, ConstructedArgs},
+    db,
+    exit::ExitCode,
+    files::{self, get_config_path},
+    fs, handler, lock, portable, selection,
+    update::UpdateCheck,
+    utils::{self, print_splash_screen, setup_cli, setup_tracing},
+};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
     let mut cmd = setup_cli();
     let matches = cmd.clone().get_matches();
 
+    // The prompt runs prompt-status every time it is drawn, so it never warns
+    utils::set_silent(
+        matches.get_flag("silent") || matches.subcommand_name() == Some("prompt-status"),
+    );
+
     if !get_config_path().exists() {
         write_default_config();
     }
 
     let config = get_config_from_file();
 
-    if config.show_splash && atty::is(atty::Stream::Stdout) {
+    // The logs are kept in the store, so it is set before tracing
+    let store = matches.get_one::<String>("store").cloned();
+    files::set_store_path(store.as_deref(), config.store_path.as_deref());
+    let _memory_store = files::MemoryStoreGuard;
+    portable::set_root(config.path_root.as_deref());
+
+    // The output of these is read by the shell, on every tab or prompt or when it starts,
+    // so nothing else is printed and nothing slow is done
+    let for_shell = matches!(
+        matches.subcommand_name(),
+        Some("targets" | "complete-queries" | "prompt-status" | "status" | "init")
+    );
+    // Anything else on stdout would get mixed up with the responses
+    let rpc = matches.get_flag("rpc");
+    let porcelain = rpc || for_shell;
+
+    // Logging every prompt and tab would bury the commands that were actually run
+    setup_tracing(matches.get_count("verbose"), !for_shell);
+    tracing::info!(
+        command = matches.subcommand_name().unwrap_or_default(),
+        args = ?std::env::args().skip(1).collect::<Vec<_>>(),
+        "invoked"
+    );
+
+    if config.show_splash && !porcelain && atty::is(atty::Stream::Stdout) {
         print_splash_screen();
     }
 
     files::check_paths_exist();
 
-    let conn = db::connect_to_db().expect("Could not connect to database");
+    // Only the commands that change the store wait for each other, so a long paste
+    // doesn't hold up a list, while two that delete entries can't delete the same ones
+    let changes_store = match matches.subcommand() {
+        Some(("paste", m)) => config.delete || m.get_flag("delete"),
+        Some(("verify", m)) => m.get_flag("fix"),
+        Some((name, _)) => matches!(
+            name,
+            "add"
+                | "pop"
+                | "cut"
+                | "delete"
+                | "clear"
+                | "swap"
+                | "roll"
+                | "pin"
+                | "tag"
+                | "restore"
+                | "gc"
+                | "refresh"
+        ),
+        None => false,
+    };
+    let read_only = config.read_only || matches.get_flag("read-only");
+    // A paste that would only delete the entries as `delete` is set in the config
+    // goes ahead without deleting them
+    let deletes_by_config = matches!(
+        matches.subcommand(),
+        Some(("paste", m)) if !m.get_flag("delete")
+    );
+    if read_only && changes_store && !deletes_by_config {
+        eprintln!(
+            "{} {} would change it",
+            "The store is read only,".red(),
+            format!("ynk {}", matches.subcommand_name().unwrap_or_default()).cyan()
+        );
+        return ExitCode::Usage.into();
+    }
+    db::set_read_only(read_only);
+    let _lock = match (changes_store && !read_only)
+        .then(lock::lock_store)
+        .transpose()
+    {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("{} {}", "Could not lock the store:".red(), e);
+            return ExitCode::Failure.into();
+        }
+    };
 
-    db::prep_db(&conn).expect("Could not prepare database");
+    let conn = match db::connect_to_db() {
+        Err(e) if read_only => {
+            eprintln!("{} {}", "Could not open the store read only:".red(), e);
+            return ExitCode::Failure.into();
+        }
+        conn => conn.expect("Could not connect to database"),
+    };
 
-    check_version();
+    if read_only && !files::is_memory_store() {
+        // A read only store can't be migrated, so it has to be from this version of ynk
+        let pending = db::pending_migrations(&conn).expect("Could not read the store version");
+        if pending > 0 {
+            eprintln!(
+                "{} run ynk once without --read-only to update it",
+                "The store is from an older version of ynk,".red()
+            );
+            return ExitCode::Failure.into();
+        }
+    } else {
+        db::prep_db(&conn).expect("Could not prepare database");
+    }
 
-    let mut args = ConstructedArgs::new(config);
+    // Only the commands that change the store, and hold its lock, purge the trash
+    if changes_store && !read_only {
+        let purged = db::purge_trash(
+            &conn,
+            chrono::Local::now() - chrono::Duration::days(config.trash_days as i64),
+        )
+        .expect("Could not purge the trash");
+        if purged > 0 {
+            tracing::info!(purged, "purged deleted entries");
+        }
+    }
+
+    // Checked in the background while the command runs, and only reported once it is done
+    let update = if porcelain || matches.subcommand_name() == Some("update") {
+        None
+    } else {
+        UpdateCheck::start(&config)
+    };
+
+    let mut args = ConstructedArgs::new(config.clone());
+    args.store = store;
 
     if matches.get_flag("all") {
         args.all = true;
+        args.all_explicit = true;
     }
     if matches.get_flag("noignore") {
         args.ignore = false;
+        args.ignore_explicit = true;
+    }
+    if read_only {
+        args.read_only = true;
+        args.delete = false;
+    }
+    if matches.get_flag("exact") {
+        args.matching = utils::Matching::Exact;
+    }
+    if matches.get_flag("explain") {
+        args.explain = true;
+    }
+    if let Some(threshold) = matches.get_one::<f64>("threshold") {
+        if !(0.0..=1.0).contains(threshold) {
+            eprintln!(
+                "{} {} is not between 0 and 1",
+                "Invalid --threshold:".red(),
+                threshold
+            );
+            return ExitCode::Usage.into();
+        }
+        args.matching = utils::Matching::Fuzzy(*threshold);
     }
-    if matches.get_flag("yes") {
-        args.yes = false;
+    if matches.get_flag("no-progress") || matches.get_flag("silent") {
+        args.progress = None;
+    }
+    if matches.get_flag("yes") || matches.get_flag("silent") {
+        args.no_prompt();
     }
 
+    let mut code = ExitCode::Success;
     match matches.subcommand_name() {
         Some("list") => {
             let m = matches.subcommand_matches("list").unwrap();
             if m.get_flag("size") {
                 args.calculate_size = true;
             }
+            if m.get_flag("created") {
+                args.show_created = true;
+            }
+            if let Some(style) = m.get_one::<String>("style") {
+                args.list_style = style.clone();
+            }
+            if m.get_flag("names-only") {
+                args.names_only = true;
+            }
+            if m.get_flag("paths-only") {
+                args.paths_only = true;
+            }
+            if m.get_flag("print0") {
+                args.print0 = true;
+            }
+            if m.get_flag("full-path") {
+                args.full_path = true;
+            }
+            if let Some(top) = m.get_one::<usize>("top") {
+                args.top = Some(*top);
+            }
+            if m.get_flag("dirs") {
+                args.is_dir = Some(true);
+            }
+            if m.get_flag("files") {
+                args.is_dir = Some(false);
+            }
+            if m.get_flag("types") {
+                args.show_types = true;
+            }
+            if let Some(file_type) = m.get_one::<String>("type") {
+                args.file_type = Some(file_type.clone());
+            }
+            if let Some(sort) = m.get_one::<String>("sort") {
+                args.sort = Some(sort.clone());
+            }
+            if m.get_flag("deleted") {
+                handler::handle_list_deleted(&conn).await;
+                return ExitCode::Success.into();
+            }
             handler::handle_list(args, &conn).await;
         }
         Some("add") => {
@@ -59,9 +242,51 @@ async fn main() {
             if m.get_flag("preserve-structure") {
                 args.preserve_structure = true;
             }
+            if m.get_flag("dry-run") {
+                args.dry_run = true;
+            }
+            if m.get_flag("strict") {
+                args.strict = true;
+            }
+            if m.get_flag("snapshot") {
+                args.snapshot = true;
+            }
+            if let Some(target) = m.get_one::<String>("target") {
+                let target = utils::absolute_path(target);
+                args.target = Some(target.to_string_lossy().to_string());
+            }
             if let Some(files) = m.get_many::<String>("files") {
                 args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
             }
+            if m.get_flag("from-clipboard") {
+                match clipboard::read_file_paths() {
+                    Ok(paths) => args
+                        .files
+                        .get_or_insert_with(Vec::new)
+                        .extend(paths.iter().map(|p| p.to_string_lossy().to_string())),
+                    Err(e) => {
+                        eprintln!("{}", e.red());
+                        ExitCode::Failure.exit();
+                    }
+                }
+            }
+            if let Some(file) = m.get_one::<String>("from-selection") {
+                let file = Some(file.as_str()).filter(|f| !f.is_empty());
+                match selection::read_selection(file) {
+                    Ok(paths) => args
+                        .files
+                        .get_or_insert_with(Vec::new)
+                        .extend(paths.iter().map(|p| p.to_string_lossy().to_string())),
+                    Err(e) => {
+                        eprintln!("{}", e.red());
+                        ExitCode::Failure.exit();
+                    }
+                }
+            }
+            if m.get_flag("show-ignored") {
+                handler::handle_show_ignored(args);
+                return ExitCode::Success.into();
+            }
             handler::handle_add(args, &conn).await;
         }
         Some("pop") => {
@@ -72,20 +297,272 @@ async fn main() {
             if m.get_flag("strict") {
                 args.strict = true;
             }
+            if let Some(policy) = m.get_one::<String>("symlinks") {
+                args.symlinks = policy.parse().unwrap();
+            }
+            if let Some(attrs) = m.get_one::<String>("preserve") {
+                match attrs.parse() {
+                    Ok(preserve) => args.preserve = preserve,
+                    Err(e) => {
+                        eprintln!("{} {}", "Invalid --preserve:".red(), e);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
+            if m.get_flag("no-preserve-timestamps") {
+                args.preserve.times = false;
+            }
+            for (flag, mode) in [
+                ("chmod", &mut args.file_mode),
+                ("dir-mode", &mut args.dir_mode),
+            ] {
+                if let Some(value) = m.get_one::<String>(flag) {
+                    match utils::parse_mode(value) {
+                        Some(parsed) => *mode = Some(parsed),
+                        None => {
+                            eprintln!("{} \"{}\"", "Invalid mode".red(), value);
+                            ExitCode::Usage.exit();
+                        }
+                    }
+                }
+            }
+            if let Some(owner) = m.get_one::<String>("chown") {
+                match owner.parse() {
+                    Ok(owner) => {
+                        args.owner = Some(owner);
+                        args.owner_explicit = true;
+                    }
+                    Err(e) => {
+                        eprintln!("{} {}", "Invalid --chown:".red(), e);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
             let mut output = None;
             if let Some(out) = m.get_one::<String>("output") {
                 output = Some(out.clone());
             }
-            handler::handle_pop(args, &conn, output).await;
+            code = handler::handle_pop(
+                args,
+                &conn,
+                output,
+                m.get_flag("keep"),
+                m.get_flag("stdout"),
+            )
+            .await;
+        }
+        Some("open") => {
+            let m = matches.subcommand_matches("open").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            if m.get_flag("all-matches") {
+                args.all_matches = true;
+            }
+
+            handler::handle_open(args, &conn, m.get_flag("reveal")).await;
+        }
+        Some("count") => {
+            let m = matches.subcommand_matches("count").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            handler::handle_count(args, &conn, m.get_flag("files")).await;
+        }
+        Some("du") => {
+            let m = matches.subcommand_matches("du").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            handler::handle_du(args, &conn).await;
+        }
+        Some("config") => {
+            let m = matches.subcommand_matches("config").unwrap();
+            match m.subcommand_name() {
+                Some("validate") => code = handler::handle_config_validate().await,
+                Some("show") => handler::handle_config_show(&config, &args).await,
+                _ => {}
+            }
+        }
+        Some("targets") => {
+            handler::handle_targets(&conn).await;
+        }
+        Some("suggest") => {
+            let m = matches.subcommand_matches("suggest").unwrap();
+            let limit = *m.get_one::<usize>("limit").unwrap();
+            code = handler::handle_suggest(args, &conn, limit).await;
+        }
+        Some("status") => {
+            let m = matches.subcommand_matches("status").unwrap();
+            handler::handle_status(&conn, m.get_flag("short"), m.get_flag("json"));
+        }
+        Some("prompt-status") => {
+            let m = matches.subcommand_matches("prompt-status").unwrap();
+            handler::handle_prompt_status(&conn, m.get_one::<String>("format").unwrap());
+        }
+        Some("complete-queries") => {
+            handler::handle_complete_queries(&conn);
+        }
+        Some("pin") => {
+            let m = matches.subcommand_matches("pin").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            handler::handle_pin(args, &conn, m.get_flag("remove")).await;
+        }
+        Some("tag") => {
+            let m = matches.subcommand_matches("tag").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+            let tag = m.get_one::<String>("tag").unwrap();
+
+            handler::handle_tag(args, &conn, tag, m.get_flag("remove")).await;
+        }
+        Some("swap") => {
+            handler::handle_roll(&conn, 2).await;
+        }
+        Some("roll") => {
+            let m = matches.subcommand_matches("roll").unwrap();
+            handler::handle_roll(&conn, *m.get_one::<usize>("n").unwrap()).await;
+        }
+        Some("show") => {
+            let m = matches.subcommand_matches("show").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            handler::handle_show(args, &conn, m.get_flag("versions")).await;
+        }
+        Some("restore") => {
+            let m = matches.subcommand_matches("restore").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+            if let Some(version) = m.get_one::<i32>("version") {
+                args.version = Some(*version);
+            }
+
+            handler::handle_restore(args, &conn, m.get_flag("deleted")).await;
+        }
+        Some("gc") => {
+            let m = matches.subcommand_matches("gc").unwrap();
+            handler::handle_gc(&conn, m.get_flag("dry-run")).await;
+        }
+        Some("stats") => {
+            handler::handle_stats(&conn).await;
+        }
+        Some("daemon") => {
+            let m = matches.subcommand_matches("daemon").unwrap();
+            let socket = m.get_one::<String>("socket").cloned();
+            code = handler::handle_daemon(args, &conn, socket).await;
+        }
+        Some("serve") => {
+            let m = matches.subcommand_matches("serve").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+            let port = m.get_one::<u16>("port").copied().unwrap_or(0);
+            let bind = m.get_one::<String>("bind").unwrap();
+            // Anything listening beyond this machine needs a token, unless it is turned off
+            let loopback = bind
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+                || bind == "localhost";
+            let token = match m.get_one::<String>("token").map(String::as_str) {
+                Some("") => Some(uuid::Uuid::new_v4().simple().to_string()),
+                Some(token) => Some(token.to_string()),
+                None if loopback || m.get_flag("no-token") => None,
+                None => Some(uuid::Uuid::new_v4().simple().to_string()),
+            };
+            let qr = m.get_flag("qr");
+            code = handler::handle_serve(args, &conn, bind, port, token, qr).await;
+        }
+        Some("audit") => {
+            let m = matches.subcommand_matches("audit").unwrap();
+            let limit = *m.get_one::<usize>("limit").unwrap();
+            handler::handle_audit(&conn, limit, m.get_flag("full")).await;
+        }
+        Some("bench") => {
+            let m = matches.subcommand_matches("bench").unwrap();
+            let files = *m.get_one::<usize>("files").unwrap();
+            let size = m.get_one::<String>("size").unwrap();
+            let size = match utils::parse_size(size) {
+                Some(size) => size,
+                None => {
+                    eprintln!("{} \"{}\"", "Invalid size".red(), size);
+                    ExitCode::Usage.exit();
+                }
+            };
+            let buffer_sizes = m
+                .get_many::<String>("buffer-sizes")
+                .unwrap()
+                .map(|size| {
+                    fs::parse_buffer_size(size).unwrap_or_else(|e| {
+                        eprintln!("{} {}", "Invalid --buffer-sizes:".red(), e);
+                        ExitCode::Usage.exit();
+                    })
+                })
+                .collect::<Vec<usize>>();
+
+            handler::handle_bench(args, files, size, buffer_sizes).await;
+        }
+        Some("refresh") => {
+            let m = matches.subcommand_matches("refresh").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+
+            handler::handle_refresh(args, &conn).await;
+        }
+        Some("verify") => {
+            let m = matches.subcommand_matches("verify").unwrap();
+            handler::handle_verify(&conn, m.get_flag("fix")).await;
         }
         Some("clear") => {
-            handler::handle_clear(args, &conn).await;
+            let m = matches.subcommand_matches("clear").unwrap();
+            if let Some(files) = m.get_many::<String>("queries") {
+                args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
+            }
+            let mut filter = db::ClearFilter {
+                keep_newest: m.get_one::<usize>("keep").copied(),
+                tag: m.get_one::<String>("tag").cloned(),
+                keep_pinned: m.get_flag("keep-pinned"),
+                ..Default::default()
+            };
+            if let Some(age) = m.get_one::<String>("older-than") {
+                match utils::parse_duration(age) {
+                    Some(age) => filter.created_before = Some(chrono::Local::now() - age),
+                    None => {
+                        eprintln!("{} \"{}\"", "Invalid duration".red(), age);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
+            if m.get_flag("dirs-only") {
+                filter.is_dir = Some(true);
+            }
+            if m.get_flag("files-only") {
+                filter.is_dir = Some(false);
+            }
+
+            handler::handle_clear(args, &conn, filter).await;
         }
-        Some("paste") => {
-            let m = matches.subcommand_matches("paste").unwrap();
+        Some(name @ ("paste" | "cut")) => {
+            let m = matches.subcommand_matches(name).unwrap();
             if m.get_flag("overwrite") {
                 args.overwrite = true;
             }
+            if m.get_flag("all-matches") {
+                args.all_matches = true;
+            }
+            if m.get_flag("select") {
+                args.select = true;
+            }
             if m.get_flag("strict") {
                 args.strict = true;
             }
@@ -93,9 +570,70 @@ async fn main() {
             if m.get_flag("delete") {
                 args.delete = true;
             }
+            // Once moved, the sources the entries refer to are gone
+            if name == "cut" {
+                args.move_files = true;
+                args.delete = true;
+            }
+            if let Some(policy) = m.get_one::<String>("symlinks") {
+                args.symlinks = policy.parse().unwrap();
+            }
+            if let Some(attrs) = m.get_one::<String>("preserve") {
+                match attrs.parse() {
+                    Ok(preserve) => args.preserve = preserve,
+                    Err(e) => {
+                        eprintln!("{} {}", "Invalid --preserve:".red(), e);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
+            if m.get_flag("no-preserve-timestamps") {
+                args.preserve.times = false;
+            }
+            for (flag, mode) in [
+                ("chmod", &mut args.file_mode),
+                ("dir-mode", &mut args.dir_mode),
+            ] {
+                if let Some(value) = m.get_one::<String>(flag) {
+                    match utils::parse_mode(value) {
+                        Some(parsed) => *mode = Some(parsed),
+                        None => {
+                            eprintln!("{} \"{}\"", "Invalid mode".red(), value);
+                            ExitCode::Usage.exit();
+                        }
+                    }
+                }
+            }
+            if let Some(owner) = m.get_one::<String>("chown") {
+                match owner.parse() {
+                    Ok(owner) => {
+                        args.owner = Some(owner);
+                        args.owner_explicit = true;
+                    }
+                    Err(e) => {
+                        eprintln!("{} {}", "Invalid --chown:".red(), e);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
+            if let Some(size) = m.get_one::<String>("buffer-size") {
+                match fs::parse_buffer_size(size) {
+                    Ok(size) => args.copy_buffer_size = Some(size),
+                    Err(e) => {
+                        eprintln!("{} {}", "Invalid --buffer-size:".red(), e);
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
             if let Some(range) = m.get_one::<String>("range") {
                 args.range = Some(range.clone());
             }
+            if let Some(latest) = m.get_one::<usize>("latest") {
+                args.latest = Some(*latest);
+            }
+            if let Some(version) = m.get_one::<i32>("version") {
+                args.version = Some(*version);
+            }
             if let Some(files) = m.get_many::<String>("queries") {
                 args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
             }
@@ -103,9 +641,46 @@ async fn main() {
             if let Some(out) = m.get_one::<String>("output") {
                 output = Some(out.clone());
             }
+            if let Some(query) = m.get_one::<String>("cd") {
+                match utils::zoxide_query(query) {
+                    Ok(dir) => output = Some(dir.to_string_lossy().to_string()),
+                    Err(e) => {
+                        eprintln!("{}", e.red());
+                        ExitCode::NotFound.exit();
+                    }
+                }
+            }
+            if m.get_flag("pick-target") {
+                output = Some(handler::pick_target(&conn, &args.picker));
+            }
+            if m.get_flag("to-recent") {
+                output = Some(handler::pick_recent_target(&conn));
+            }
+            if m.get_flag("at-root") {
+                match utils::find_git_root(".") {
+                    Some(root) => output = Some(root.to_string_lossy().to_string()),
+                    None => {
+                        eprintln!("{}", "Not inside a git repository".red());
+                        ExitCode::Usage.exit();
+                    }
+                }
+            }
             args.specific = None;
 
-            handler::handle_paste(args, &conn, output).await;
+            code = handler::handle_paste(args, &conn, output).await;
+        }
+        Some("init") => {
+            let m = matches.subcommand_matches("init").unwrap();
+            let mut c = cmd.clone();
+            handler::handle_init(
+                &mut c,
+                m.get_one::<String>("shell").unwrap(),
+                !m.get_flag("no-bindings"),
+            );
+        }
+        Some("update") => {
+            let m = matches.subcommand_matches("update").unwrap();
+            handler::handle_update(&args, m.get_flag("check"), m.get_flag("force"));
         }
         Some("completions") => {
             let m = matches.subcommand_matches("completions").unwrap();
@@ -119,12 +694,32 @@ async fn main() {
             if let Some(files) = m.get_many::<String>("queries") {
                 args.files = Some(files.map(|s| s.to_string()).collect::<Vec<String>>());
             }
+            if let Some(range) = m.get_one::<String>("range") {
+                args.range = Some(range.clone());
+            }
+            if let Some(glob) = m.get_one::<String>("glob") {
+                args.glob = Some(glob.clone());
+            }
+            if m.get_flag("dry-run") {
+                args.dry_run = true;
+            }
+            if m.get_flag("all-matches") {
+                args.all_matches = true;
+            }
 
             handler::handle_delete(args, &conn).await;
         }
         Some(_) => {}
+        None if rpc => {
+            code = handler::handle_rpc(args, &conn).await;
+        }
         None => {
             let _ = cmd.print_help();
         }
     }
+
+    if let Some(update) = update {
+        update.notify();
+    }
+    code.into()
 }
@@ -0,0 +1,558 @@
+//! This module contains the migrations of the database schema
+//!
+//! Every migration brings the schema up by one version and the version
+//! a store is at is kept in the `schema_version` table, so columns and tables
+//! can be added to existing stores without losing any entries
+//!
+//! Migrations must only ever be appended to `MIGRATIONS`, never edited or reordered
+
+use rusqlite::Connection;
+use sea_query::{ColumnDef, Expr, Func, Iden, Query, SqliteQueryBuilder, Table};
+
+use crate::db::{
+    format_timestamp, new_uuid, try_parse_timestamp, AuditLog, Blobs, ListedIds, PasteTargets,
+    SnapshotFiles, Snapshots, Store, Trash,
+};
+
+#[derive(Iden)]
+enum SchemaVersion {
+    Table,
+    Version,
+}
+
+type Migration = fn(&Connection) -> Result<(), rusqlite::Error>;
+
+/// All the migrations, in the order they are applied
+/// The schema version of a store is the number of migrations applied to it
+const MIGRATIONS: &[Migration] = &[
+    create_store,
+    add_discovery_columns,
+    create_snapshots,
+    convert_timestamps,
+    add_uuids,
+    create_trash,
+    add_targets,
+    create_paste_targets,
+    add_file_types,
+    add_file_counts,
+    add_sizes,
+    create_audit_log,
+    create_listed_ids,
+    add_file_ids,
+    add_birth_times,
+    add_pins_and_tags,
+];
+
+/// Applies the migrations that the store is missing
+/// Each migration runs in its own transaction along with the version bump
+///
+/// # Returns
+///
+/// The number of migrations that were applied
+pub fn run(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let query = Table::create()
+        .table(SchemaVersion::Table)
+        .if_not_exists()
+        .col(ColumnDef::new(SchemaVersion::Version).integer().not_null())
+        .build(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    let current = schema_version(conn)?;
+    if current > MIGRATIONS.len() {
+        tracing::warn!(
+            current,
+            known = MIGRATIONS.len(),
+            "The store was created by a newer version of ynk"
+        );
+        return Ok(0);
+    }
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.unchecked_transaction()?;
+        migration(&tx)?;
+        set_schema_version(&tx, version + 1)?;
+        tx.commit()?;
+
+        tracing::info!(version = version + 1, "migrated the store");
+    }
+
+    Ok(MIGRATIONS.len() - current)
+}
+
+/// Gets the schema version of the store,
+/// 0 for stores created before versions were tracked
+pub fn schema_version(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let query = Query::select()
+        .expr(Func::coalesce([
+            Func::max(Expr::col(SchemaVersion::Version)).into(),
+            Expr::val(0).into(),
+        ]))
+        .from(SchemaVersion::Table)
+        .to_string(SqliteQueryBuilder);
+
+    conn.query_row(&query, [], |row| row.get::<_, i64>(0))
+        .map(|v| v as usize)
+}
+
+/// How many migrations the store is missing, without applying them,
+/// for a store that is only read
+pub fn pending(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    let current = match schema_version(conn) {
+        Ok(current) => current,
+        // A store from before versions were tracked has no table for them
+        Err(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.starts_with("no such table") => 0,
+        Err(e) => return Err(e),
+    };
+
+    Ok(MIGRATIONS.len().saturating_sub(current))
+}
+
+fn set_schema_version(conn: &Connection, version: usize) -> Result<(), rusqlite::Error> {
+    let query = Query::delete()
+        .from_table(SchemaVersion::Table)
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    let query = Query::insert()
+        .into_table(SchemaVersion::Table)
+        .columns([SchemaVersion::Version])
+        .values_panic([(version as i64).into()])
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// Checks if a table already has a column
+/// Stores from before versions were tracked may already have some of the changes
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let columns = stmt
+        .query_map([table], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(columns.iter().any(|c| c == column))
+}
+
+/// Adds a nullable column to a table unless it is already there
+fn add_column(
+    conn: &Connection,
+    table: impl Iden + 'static,
+    col: ColumnDef,
+) -> Result<(), rusqlite::Error> {
+    let mut col = col;
+    if has_column(conn, &table.to_string(), &col.get_column_name())? {
+        return Ok(());
+    }
+
+    let query = Table::alter()
+        .table(table)
+        .add_column(&mut col)
+        .to_string(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// 1: The store table, as it was in the first versions of ynk
+fn create_store(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(Store::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Store::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Store::Name).string().not_null())
+        .col(ColumnDef::new(Store::Path).string().not_null())
+        .col(ColumnDef::new(Store::IsDir).boolean().not_null())
+        .col(ColumnDef::new(Store::AccessedAt).date_time().not_null())
+        .col(ColumnDef::new(Store::CreatedAt).date_time().not_null())
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// 2: The discovery options recorded with each entry
+fn add_discovery_columns(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Hidden).boolean().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::RespectIgnore).boolean().to_owned(),
+    )
+}
+
+/// 3: Snapshots and the deduplicated blobs of their files
+fn create_snapshots(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(Snapshots::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Snapshots::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Snapshots::Path).string().not_null())
+        .col(ColumnDef::new(Snapshots::Version).integer().not_null())
+        .col(ColumnDef::new(Snapshots::Location).string().not_null())
+        .col(ColumnDef::new(Snapshots::CreatedAt).date_time().not_null())
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    let query = Table::create()
+        .table(SnapshotFiles::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(SnapshotFiles::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(
+            ColumnDef::new(SnapshotFiles::SnapshotId)
+                .integer()
+                .not_null(),
+        )
+        .col(ColumnDef::new(SnapshotFiles::Path).string().not_null())
+        .col(ColumnDef::new(SnapshotFiles::Hash).string().not_null())
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    let query = Table::create()
+        .table(Blobs::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Blobs::Hash)
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Blobs::Size).big_integer().not_null())
+        .col(ColumnDef::new(Blobs::Refs).integer().not_null())
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// Rewrites a timestamp column as RFC3339 in UTC
+/// Values that can't be parsed are left as they are
+fn convert_timestamp_column<T>(
+    conn: &Connection,
+    table: T,
+    id: T,
+    column: T,
+) -> Result<(), rusqlite::Error>
+where
+    T: Iden + Clone + 'static,
+{
+    let query = Query::select()
+        .columns([id.clone(), column.clone()])
+        .from(table.clone())
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (row_id, time) in rows {
+        let Some(parsed) = try_parse_timestamp(&time) else {
+            tracing::warn!(row_id, time, "Could not convert a stored time");
+            continue;
+        };
+
+        let query = Query::update()
+            .table(table.clone())
+            .value(column.clone(), format_timestamp(&parsed))
+            .and_where(Expr::col(id.clone()).eq(row_id))
+            .to_string(SqliteQueryBuilder);
+        conn.execute(&query, [])?;
+    }
+
+    Ok(())
+}
+
+/// 4: Times stored as RFC3339 in UTC instead of chrono's default local format
+fn convert_timestamps(conn: &Connection) -> Result<(), rusqlite::Error> {
+    convert_timestamp_column(conn, Store::Table, Store::Id, Store::AccessedAt)?;
+    convert_timestamp_column(conn, Store::Table, Store::Id, Store::CreatedAt)?;
+    convert_timestamp_column(conn, Snapshots::Table, Snapshots::Id, Snapshots::CreatedAt)
+}
+
+/// 5: A uuid for every entry, generated for the existing ones
+fn add_uuids(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Uuid).string().to_owned(),
+    )?;
+
+    let query = Query::select()
+        .column(Store::Id)
+        .from(Store::Table)
+        .and_where(Expr::col(Store::Uuid).is_null())
+        .to_string(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&query)?;
+    let ids = stmt
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for id in ids {
+        let query = Query::update()
+            .table(Store::Table)
+            .value(Store::Uuid, new_uuid())
+            .and_where(Expr::col(Store::Id).eq(id))
+            .to_string(SqliteQueryBuilder);
+        conn.execute(&query, [])?;
+    }
+
+    Ok(())
+}
+
+/// 6: The trash table, where deleted entries are kept until they are purged
+fn create_trash(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(Trash::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Trash::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(Store::Name).string().not_null())
+        .col(ColumnDef::new(Store::Path).string().not_null())
+        .col(ColumnDef::new(Store::IsDir).boolean().not_null())
+        .col(ColumnDef::new(Store::AccessedAt).date_time().not_null())
+        .col(ColumnDef::new(Store::CreatedAt).date_time().not_null())
+        .col(ColumnDef::new(Store::Hidden).boolean())
+        .col(ColumnDef::new(Store::RespectIgnore).boolean())
+        .col(ColumnDef::new(Store::Uuid).string().not_null())
+        .col(ColumnDef::new(Trash::DeletedAt).date_time().not_null())
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// 7: The target an entry is pasted to when no output is given
+fn add_targets(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Target).string().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::Target).string().to_owned(),
+    )
+}
+
+/// 8: The history of the targets entries were pasted to
+fn create_paste_targets(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(PasteTargets::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(PasteTargets::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(PasteTargets::Path).string().not_null())
+        .col(ColumnDef::new(PasteTargets::EntryPath).string().not_null())
+        .col(
+            ColumnDef::new(PasteTargets::PastedAt)
+                .date_time()
+                .not_null(),
+        )
+        .build(SqliteQueryBuilder);
+
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// 9: The MIME type of an entry, detected when it is first listed for existing ones
+fn add_file_types(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::FileType).string().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::FileType).string().to_owned(),
+    )
+}
+
+/// 10: The number of files in an entry, counted when it is first needed
+fn add_file_counts(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::FileCount).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::FileCount).integer().to_owned(),
+    )
+}
+
+/// 11: The size of an entry, cached along with its number of files
+fn add_sizes(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Size).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::Size).integer().to_owned(),
+    )
+}
+
+/// 12: The audit log of the changes made to the store,
+/// kept append-only by triggers that refuse to update or delete its rows
+fn create_audit_log(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(AuditLog::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(AuditLog::Id)
+                .integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(AuditLog::Action).string().not_null())
+        .col(ColumnDef::new(AuditLog::User).string().not_null())
+        .col(ColumnDef::new(AuditLog::Paths).string().not_null())
+        .col(ColumnDef::new(AuditLog::At).date_time().not_null())
+        .build(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    // sea-query has no triggers, so they are written out
+    let table = AuditLog::Table.to_string();
+    for event in ["UPDATE", "DELETE"] {
+        conn.execute(
+            &format!(
+                "CREATE TRIGGER IF NOT EXISTS {table}_no_{} BEFORE {event} ON {table} \
+                 BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END",
+                event.to_lowercase()
+            ),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// 13: The ids the entries had when they were last listed,
+/// which ranges are resolved against in case the entries were renumbered since
+fn create_listed_ids(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let query = Table::create()
+        .table(ListedIds::Table)
+        .if_not_exists()
+        .col(
+            ColumnDef::new(ListedIds::Id)
+                .integer()
+                .not_null()
+                .primary_key(),
+        )
+        .col(ColumnDef::new(ListedIds::Uuid).string().not_null())
+        .col(ColumnDef::new(ListedIds::ListedAt).date_time().not_null())
+        .build(SqliteQueryBuilder);
+    conn.execute(&query, [])?;
+
+    Ok(())
+}
+
+/// 14: The identity of the file an entry was added from,
+/// so it can be found again after being moved
+fn add_file_ids(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Device).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Inode).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::Device).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::Inode).integer().to_owned(),
+    )
+}
+
+/// 15: When the file an entry was added from was created,
+/// so a reused inode isn't taken for the file
+fn add_birth_times(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::BornAt).integer().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::BornAt).integer().to_owned(),
+    )
+}
+
+/// 16: Whether an entry is pinned, and the tags it was given,
+/// for `clear` to keep or select entries by
+fn add_pins_and_tags(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let pinned = ColumnDef::new(Store::Pinned)
+        .boolean()
+        .not_null()
+        .default(false)
+        .to_owned();
+    add_column(conn, Store::Table, pinned.clone())?;
+    add_column(conn, Trash::Table, pinned)?;
+    add_column(
+        conn,
+        Store::Table,
+        ColumnDef::new(Store::Tags).string().to_owned(),
+    )?;
+    add_column(
+        conn,
+        Trash::Table,
+        ColumnDef::new(Store::Tags).string().to_owned(),
+    )
+}
@@ -0,0 +1,121 @@
+//! A copy path for huge files that maps them into memory,
+//! copying between the page caches instead of through a buffer of our own
+//!
+//! [`MmapCopy`] wraps another backend, which copies the smaller files
+//! and the files on network filesystems, where a mapping can fault under us.
+//! A source that is truncated by another process while it is mapped
+//! kills ynk with `SIGBUS`, which is why mapping is only done when asked for
+
+use std::{
+    fs::OpenOptions,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::fs::Copier;
+
+/// How much is copied before it is flushed to the target, in bytes
+const FLUSH_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+/// The filesystems that are mapped over the network, as they are named in `/proc/mounts`
+const NETWORK_FILESYSTEMS: [&str; 10] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "fuse.sshfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+];
+
+/// Copies the files larger than the threshold by mapping them,
+/// leaving everything else to the backend it wraps
+#[derive(Debug, Clone)]
+pub struct MmapCopy<C> {
+    inner: C,
+    /// The size in bytes above which files are mapped
+    threshold: u64,
+}
+
+impl<C: Copier> MmapCopy<C> {
+    pub fn new(inner: C, threshold: u64) -> Self {
+        Self { inner, threshold }
+    }
+}
+
+/// Copies a file by mapping both it and the target,
+/// flushing the target every [`FLUSH_CHUNK_SIZE`] bytes
+fn mmap_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let source = std::fs::File::open(from)?;
+    let metadata = source.metadata()?;
+    let len = metadata.len() as usize;
+
+    let target = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(to)?;
+    target.set_len(len as u64)?;
+
+    // SAFETY: this is only sound as long as no other process truncates the source
+    // while it is mapped, which nothing here can prevent. Reading a page past its new end
+    // raises SIGBUS and kills ynk, unlike a read that would fail or come up short.
+    // Mapping is opt-in with `mmap_threshold` for that reason, and files on network
+    // filesystems, where pages can go away at any time, are never mapped.
+    // Another process writing to the source could still tear the copy,
+    // as with any other way of copying
+    let src = unsafe { memmap2::Mmap::map(&source)? };
+    // SAFETY: the target was just created and sized for the map,
+    // the same goes for it being truncated by another process while it is copied
+    let mut dst = unsafe { memmap2::MmapMut::map_mut(&target)? };
+
+    for start in (0..len).step_by(FLUSH_CHUNK_SIZE) {
+        let end = (start + FLUSH_CHUNK_SIZE).min(len);
+        dst[start..end].copy_from_slice(&src[start..end]);
+        dst.flush_async_range(start, end - start)?;
+    }
+    dst.flush()?;
+
+    target.set_permissions(metadata.permissions())
+}
+
+/// Whether the path is on a filesystem mounted over the network,
+/// by the longest mount point in `/proc/mounts` that it is under
+///
+/// Always false where there is no `/proc/mounts`
+fn is_network_fs(path: &Path) -> bool {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return false;
+    };
+
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some((fields.next()?, fields.next()?))
+        })
+        // Spaces in mount points are escaped as \040
+        .map(|(mount, fs_type)| (PathBuf::from(mount.replace("\\040", " ")), fs_type))
+        .filter(|(mount, _)| path.starts_with(mount))
+        .max_by_key(|(mount, _)| mount.components().count())
+        .is_some_and(|(_, fs_type)| NETWORK_FILESYSTEMS.contains(&fs_type))
+}
+
+impl<C: Copier> Copier for MmapCopy<C> {
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let len = tokio::fs::metadata(from).await?.len();
+        if len <= self.threshold || is_network_fs(from) || is_network_fs(to) {
+            return self.inner.copy(from, to).await;
+        }
+
+        tracing::debug!(source = %from.display(), len, "copying through a memory map");
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        tokio::task::spawn_blocking(move || mmap_copy(&from, &to))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
@@ -0,0 +1,131 @@
+//! Who owns the pasted files, with `paste --chown`
+//!
+//! With `chown_sudo_user`, when ynk runs as root through sudo, the files it creates
+//! are given to the user who invoked it, rather than leaving root owned files around
+
+use std::str::FromStr;
+
+/// The user and group the pasted files are given to,
+/// where either is left as it is when `None`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Parses an owner like `chown` takes, ex: `alice`, `alice:staff`, `:staff` or `1000:1000`
+///
+/// Names are looked up in the user and group databases, while numbers are taken as ids
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+
+        let owner = Owner {
+            uid: match user {
+                "" => None,
+                user => Some(lookup_user(user)?),
+            },
+            gid: match group {
+                None | Some("") => None,
+                Some(group) => Some(lookup_group(group)?),
+            },
+        };
+        if owner.uid.is_none() && owner.gid.is_none() {
+            return Err("give a user, a group or both, ex: alice:staff".to_string());
+        }
+
+        Ok(owner)
+    }
+}
+
+/// The user who ran ynk through sudo, when it is running as root
+///
+/// Taken from `SUDO_UID` and `SUDO_GID`, which sudo sets
+pub fn sudo_user() -> Option<Owner> {
+    if !is_root() {
+        return None;
+    }
+
+    let id = |var: &str| std::env::var(var).ok()?.parse::<u32>().ok();
+    let owner = Owner {
+        uid: id("SUDO_UID"),
+        gid: id("SUDO_GID"),
+    };
+    // Running sudo as root leaves nothing to give the files back to
+    owner.uid.is_some_and(|uid| uid != 0).then_some(owner)
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    match nix::unistd::User::from_name(user) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err(format!("there is no user \"{}\"", user)),
+        Err(e) => Err(format!("could not look up the user \"{}\": {}", user, e)),
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    match nix::unistd::Group::from_name(group) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err(format!("there is no group \"{}\"", group)),
+        Err(e) => Err(format!("could not look up the group \"{}\": {}", group, e)),
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_user: &str) -> Result<u32, String> {
+    Err("ownership can only be set on Unix".to_string())
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_group: &str) -> Result<u32, String> {
+    Err("ownership can only be set on Unix".to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners_are_parsed_like_chown() {
+        assert_eq!(
+            "1000:100".parse::<Owner>(),
+            Ok(Owner {
+                uid: Some(1000),
+                gid: Some(100)
+            })
+        );
+        assert_eq!(
+            ":100".parse::<Owner>(),
+            Ok(Owner {
+                uid: None,
+                gid: Some(100)
+            })
+        );
+        assert_eq!("root".parse::<Owner>().unwrap().uid, Some(0));
+        assert!(":".parse::<Owner>().is_err());
+        assert!("no-such-user-ynk".parse::<Owner>().is_err());
+    }
+}
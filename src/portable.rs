@@ -0,0 +1,132 @@
+//! Portable paths, for a store synced between machines where the same files
+//! live under different directories, ex: `/home/alice` and `/Users/alice.smith`
+//!
+//! With `path_root` set in the config, the paths under it are stored relative to it,
+//! as `@root/docs/notes.md`, and resolved against the root of the machine reading them.
+//! Paths outside the root are stored as they are
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use crate::utils;
+
+/// What the root is stored as in the paths under it
+pub const ROOT_PREFIX: &str = "@root";
+
+/// The root set by `set_root`
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set once it has been warned that there is no root to resolve a path against
+static NO_ROOT_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Sets the root paths are stored relative to, from `path_root` in the config,
+/// ex: `~` or `~/projects`
+///
+/// It has to be called before the store is used, later calls are ignored
+pub fn set_root(root: Option<&str>) {
+    if let Some(root) = root.filter(|r| !r.trim().is_empty()) {
+        let root = utils::absolute_path(root);
+        // The paths of the entries are canonical, so the root has to be too
+        let _ = ROOT.set(root.canonicalize().unwrap_or(root));
+    }
+}
+
+/// The form a path is stored in, relative to the root if it is under it
+///
+/// The separators are always `/`, so the store can be read on other platforms too
+pub fn to_stored(path: &str) -> String {
+    let Some(root) = ROOT.get() else {
+        return path.to_string();
+    };
+
+    match Path::new(path).strip_prefix(root) {
+        Ok(rest) => {
+            let rest = rest
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            match rest.is_empty() {
+                true => ROOT_PREFIX.to_string(),
+                false => format!("{}/{}", ROOT_PREFIX, rest.join("/")),
+            }
+        }
+        Err(_) => path.to_string(),
+    }
+}
+
+/// The forms a path can be stored in, to look it up by: the one `to_stored` gives,
+/// and the path as it is, which is how it was stored if added before the root was set
+pub fn stored_forms(path: &str) -> Vec<String> {
+    let stored = to_stored(path);
+    match stored == path {
+        true => vec![stored],
+        false => vec![stored, path.to_string()],
+    }
+}
+
+/// The path a stored one refers to on this machine
+///
+/// A path stored relative to a root is left as it is if no root is set, with a warning
+pub fn from_stored(stored: &str) -> String {
+    let rest = match stored.strip_prefix(ROOT_PREFIX) {
+        Some("") => "",
+        Some(rest) if rest.starts_with('/') => &rest[1..],
+        _ => return stored.to_string(),
+    };
+
+    let Some(root) = ROOT.get() else {
+        if !NO_ROOT_WARNED.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                path = stored,
+                "the store has portable paths, but path_root isn't set to resolve them"
+            );
+        }
+        return stored.to_string();
+    };
+
+    rest.split('/')
+        .filter(|c| !c.is_empty())
+        .fold(root.clone(), |path, c| path.join(c))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// `to_stored` for an optional path, such as the target of an entry
+pub fn to_stored_opt(path: &Option<String>) -> Option<String> {
+    path.as_deref().map(to_stored)
+}
+
+/// `from_stored` for an optional path, such as the target of an entry
+pub fn from_stored_opt(stored: Option<String>) -> Option<String> {
+    stored.as_deref().map(from_stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, EntryBuilder};
+
+    #[test]
+    fn entries_added_before_the_root_are_still_found() {
+        let root = std::env::temp_dir()
+            .canonicalize()
+            .unwrap()
+            .join(format!("ynk-root-{}", uuid::Uuid::new_v4()));
+        let path = root.join("notes.md").to_string_lossy().to_string();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::prep_db(&conn).unwrap();
+
+        db::insert_into_db(&conn, EntryBuilder::new("notes.md", &path, false)).unwrap();
+        set_root(Some(&root.to_string_lossy()));
+        assert_eq!(to_stored(&path), format!("{}/notes.md", ROOT_PREFIX));
+
+        assert_eq!(db::does_exist(&conn, &path).unwrap().path, path);
+        assert_eq!(db::delete_entries(&conn, &[&path]).unwrap(), 1);
+        assert!(db::get_all(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Keeping the attributes of the files being pasted, with `paste --preserve`
+
+use std::{
+    fs::{File, FileTimes},
+    io,
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The attributes kept on the pasted files, from `--preserve` or `preserve_attributes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Preserve {
+    /// The permissions, which a copy usually keeps but a move across filesystems doesn't
+    pub mode: bool,
+    /// The access and modification times
+    pub times: bool,
+    /// Extended attributes, such as SELinux labels or the macOS quarantine flag
+    pub xattr: bool,
+    /// POSIX ACLs, on Linux
+    pub acl: bool,
+}
+
+impl Preserve {
+    /// Every attribute there is
+    pub fn all() -> Self {
+        Self {
+            mode: true,
+            times: true,
+            xattr: true,
+            acl: true,
+        }
+    }
+
+    /// Whether any attribute is kept
+    pub fn any(&self) -> bool {
+        self.mode || self.times || self.xattr || self.acl
+    }
+}
+
+/// Parses a comma separated list of attributes like `cp --preserve`, ex: `mode,times,xattr` or `all`
+impl FromStr for Preserve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut preserve = Preserve::default();
+        for attr in s.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            match attr {
+                "mode" => preserve.mode = true,
+                "times" | "timestamps" => preserve.times = true,
+                "xattr" => preserve.xattr = true,
+                "acl" => preserve.acl = true,
+                "all" => preserve = Preserve::all(),
+                _ => {
+                    return Err(format!(
+                        "\"{}\" is not one of mode, times, xattr, acl or all",
+                        attr
+                    ))
+                }
+            }
+        }
+
+        Ok(preserve)
+    }
+}
+
+/// Copies the attributes asked for from one file to another
+///
+/// The times are copied last, so nothing else touches them afterwards
+pub fn copy_attributes(from: &Path, to: &Path, preserve: Preserve) -> io::Result<()> {
+    let metadata = std::fs::metadata(from)?;
+
+    if preserve.mode {
+        std::fs::set_permissions(to, metadata.permissions())?;
+    }
+    if preserve.xattr {
+        copy_xattrs(from, to)?;
+    }
+    if preserve.acl {
+        copy_acls(from, to)?;
+    }
+    if preserve.times {
+        let times = FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        open_for_times(to)?.set_times(times)?;
+    }
+
+    Ok(())
+}
+
+/// Opens a file only to set its times, which works on read only files too
+fn open_for_times(path: &Path) -> io::Result<File> {
+    #[cfg(windows)]
+    {
+        use std::{fs::OpenOptions, os::windows::fs::OpenOptionsExt};
+        /// `FILE_WRITE_ATTRIBUTES`, all that setting the times needs
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .open(path)
+    }
+    // Owning the file is enough on Unix, whatever it was opened for
+    #[cfg(not(windows))]
+    File::open(path)
+}
+
+/// The extended attributes POSIX ACLs are kept in on Linux,
+/// the access ACL and the default ACL that directories pass on
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// Set once it has been warned that ACLs can't be kept, so it is only warned about once
+static ACLS_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+fn warn_acls_unsupported(reason: &str) {
+    if !ACLS_UNSUPPORTED.swap(true, Ordering::Relaxed) {
+        tracing::warn!("ACLs aren't kept, {}", reason);
+    }
+}
+
+/// Copies the extended attributes of a file to another
+///
+/// On Linux, only the `user` namespace can always be written, so attributes
+/// in the others, such as `security.selinux`, are kept where permitted and left out otherwise
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) -> io::Result<()> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(());
+    }
+
+    for name in xattr::list(from)? {
+        // ACLs are only kept when asked for, with `acl`
+        if ACL_XATTRS.iter().any(|acl| name == *acl) {
+            continue;
+        }
+        let Some(value) = xattr::get(from, &name)? else {
+            continue;
+        };
+        if let Err(e) = xattr::set(to, &name, &value) {
+            if cfg!(target_os = "linux") && !name.to_string_lossy().starts_with("user.") {
+                tracing::debug!(
+                    name = %name.to_string_lossy(),
+                    error = %e,
+                    "Could not keep an extended attribute"
+                );
+                continue;
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Extended attributes are only kept on Unix
+#[cfg(not(unix))]
+fn copy_xattrs(_from: &Path, _to: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Copies the POSIX ACLs of a file to another, through the extended attributes they are kept in
+///
+/// Filesystems without ACLs are warned about once and otherwise left alone
+#[cfg(target_os = "linux")]
+fn copy_acls(from: &Path, to: &Path) -> io::Result<()> {
+    for name in ACL_XATTRS {
+        let copied = xattr::get(from, name).and_then(|value| match value {
+            Some(value) => xattr::set(to, name, &value),
+            None => Ok(()),
+        });
+        match copied {
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EOPNOTSUPP as i32) => {
+                warn_acls_unsupported("as the filesystem doesn't support them");
+                return Ok(());
+            }
+            copied => copied?,
+        }
+    }
+
+    Ok(())
+}
+
+/// POSIX ACLs are only kept on Linux
+#[cfg(not(target_os = "linux"))]
+fn copy_acls(_from: &Path, _to: &Path) -> io::Result<()> {
+    warn_acls_unsupported("as POSIX ACLs are only kept on Linux");
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! QR codes printed to the terminal, so a link can be opened on a phone with one scan
+
+use qrcode::{render::unicode::Dense1x2, QrCode};
+
+/// Renders the text as a QR code of half block characters, two modules to a line
+///
+/// The colors are swapped, so the light modules are drawn in the foreground color,
+/// which reads on the usual dark terminal. `None` if the text is too long to fit in one
+pub fn render(text: &str) -> Option<String> {
+    let code = QrCode::new(text.as_bytes()).ok()?;
+    Some(
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build(),
+    )
+}
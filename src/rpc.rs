@@ -0,0 +1,408 @@
+//! A small JSON-RPC 2.0 API over the store, for editor plugins and other tools
+//!
+//! Requests and responses are framed as one JSON object per line,
+//! whatever they are carried over, the socket of `ynk daemon` or stdio with `ynk --rpc`
+//!
+//! The methods are:
+//!
+//! * `list` - `{ "queries"?: [..] }`, the entries matching the queries, or all of them
+//! * `add` - `{ "paths": [..], "target"?: "dir" }`, adds the paths as entries
+//! * `paste_plan` - `{ "queries"?: [..], "output": "dir" }`, the files a paste would copy
+//! * `paste` - `{ "queries"?: [..], "output": "dir", "overwrite"?: bool, "delete"?: bool }`
+//! * `delete` - `{ "queries": [..] }`, deletes the matching entries, which can be restored
+//!
+//! The methods that change the store take its [lock](crate::lock) while they run,
+//! so they don't race a `ynk` run at the same time
+
+use std::path::PathBuf;
+
+use hashbrown::HashMap;
+use indicatif::ProgressBar;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    config::ConstructedArgs,
+    db::{self, Entry},
+    engine,
+    fs::RealFs,
+    lock,
+    utils::{self, deep_search, sort_entries},
+};
+
+/// The error codes of JSON-RPC 2.0
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Any error from the method itself, such as a path that doesn't exist
+const SERVER_ERROR: i64 = -32000;
+
+/// An error to respond with, as its code and message
+struct RpcError(i64, String);
+
+impl RpcError {
+    fn invalid_params(e: impl ToString) -> Self {
+        RpcError(INVALID_PARAMS, e.to_string())
+    }
+
+    fn server(e: impl ToString) -> Self {
+        RpcError(SERVER_ERROR, e.to_string())
+    }
+
+    /// For the methods that would change a store that is only read
+    fn read_only() -> Self {
+        RpcError::server("the store is read only")
+    }
+}
+
+/// Takes the lock on the store for a method that changes it, held until dropped
+fn lock_store() -> Result<lock::StoreLock, RpcError> {
+    lock::lock_store().map_err(|e| RpcError::server(format!("could not lock the store: {}", e)))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ListParams {
+    queries: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct AddParams {
+    paths: Vec<String>,
+    #[serde(default)]
+    target: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    queries: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PasteParams {
+    #[serde(default)]
+    queries: Vec<String>,
+    output: String,
+    #[serde(default)]
+    overwrite: bool,
+    #[serde(default)]
+    delete: bool,
+}
+
+/// Serves the requests read line by line from `reader`, writing a response line
+/// for each one to `writer`, until `reader` is closed
+///
+/// `busy` is held while a request is handled, so the requests of the clients
+/// sharing it are handled one at a time
+///
+/// Notifications, the requests without an id, get no response
+pub async fn serve_lines<R, W>(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    busy: &tokio::sync::Mutex<()>,
+    reader: R,
+    mut writer: W,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = {
+            let _busy = busy.lock().await;
+            handle_request(conn, args, &line).await
+        };
+        if let Some(response) = response {
+            writer.write_all(response.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a single JSON-RPC request, returning the response to it,
+/// or `None` for a notification
+pub async fn handle_request(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    request: &str,
+) -> Option<String> {
+    let request = match serde_json::from_str::<Value>(request) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(respond(
+                Value::Null,
+                Err(RpcError(PARSE_ERROR, e.to_string())),
+            ))
+        }
+    };
+
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Some(respond(
+            id.unwrap_or(Value::Null),
+            Err(RpcError(INVALID_REQUEST, "no method given".to_string())),
+        ));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    tracing::debug!(method, "handling a request");
+    let result = call(conn, args, method, params).await;
+    if let Err(RpcError(code, message)) = &result {
+        tracing::warn!(method, code, message, "request failed");
+    }
+
+    id.map(|id| respond(id, result))
+}
+
+fn respond(id: Value, result: Result<Value, RpcError>) -> String {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(RpcError(code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    };
+
+    response.to_string()
+}
+
+/// Parses the params of a method, with missing params taken as an empty object
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+async fn call(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    method: &str,
+    params_value: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "list" => {
+            let p: ListParams = params(params_value)?;
+            let entries = find_entries(conn, args, p.queries)?;
+            Ok(Value::Array(entries.iter().map(entry_json).collect()))
+        }
+        "add" => add(conn, args, params(params_value)?),
+        "paste_plan" => {
+            let p: PasteParams = params(params_value)?;
+            let (_, plan) = plan(conn, args, &p)?;
+            Ok(json!({
+                "files": plan.files.iter().map(|f| json!({
+                    "source": f.source,
+                    "target": f.target,
+                })).collect::<Vec<_>>(),
+                "links": plan.links.iter().map(|f| json!({
+                    "source": f.source,
+                    "target": f.target,
+                })).collect::<Vec<_>>(),
+                "size": plan.size,
+                "skipped": plan.skipped,
+            }))
+        }
+        "paste" => paste(conn, args, params(params_value)?).await,
+        "delete" => delete(conn, args, params(params_value)?),
+        _ => Err(RpcError(
+            METHOD_NOT_FOUND,
+            format!("there is no method \"{}\"", method),
+        )),
+    }
+}
+
+fn entry_json(e: &Entry) -> Value {
+    json!({
+        "id": e.id,
+        "name": e.name,
+        "path": e.path,
+        "is_dir": e.is_dir,
+        "uuid": e.uuid,
+        "target": e.target,
+        "file_type": e.file_type,
+        "created_at": e.created_at.to_rfc3339(),
+        "accessed_at": e.accessed_at.to_rfc3339(),
+    })
+}
+
+/// The entries matching the queries, latest first, or all of them without queries
+fn find_entries(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    queries: Vec<String>,
+) -> Result<Vec<Entry>, RpcError> {
+    let entries = db::get_all(conn).map_err(RpcError::server)?;
+    let mut entries = deep_search(queries, &entries, args.matching);
+    sort_entries(&mut entries);
+
+    Ok(entries)
+}
+
+fn add(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    p: AddParams,
+) -> Result<Value, RpcError> {
+    if args.read_only {
+        return Err(RpcError::read_only());
+    }
+    if p.paths.is_empty() {
+        return Err(RpcError::invalid_params("no paths given"));
+    }
+
+    let mut files = HashMap::new();
+    for path in &p.paths {
+        let canonical = PathBuf::from(utils::expand_home(path))
+            .canonicalize()
+            .map_err(|e| RpcError::server(format!("{}: {}", path, e)))?;
+        let name = utils::parse_file_name(path);
+        if let Some(other) = files.get(&name).filter(|&other| *other != canonical) {
+            return Err(RpcError::invalid_params(format!(
+                "{} and {} are both named {}",
+                other.display(),
+                canonical.display(),
+                name
+            )));
+        }
+        files.insert(name, canonical);
+    }
+
+    let _lock = lock_store()?;
+
+    let mut args = args.clone();
+    args.target = p
+        .target
+        .map(|t| utils::absolute_path(&t).to_string_lossy().to_string());
+    let entries = engine::add(conn, &files, &args).map_err(RpcError::server)?;
+    tracing::info!(entries = entries.len(), "added entries over rpc");
+
+    Ok(Value::Array(entries.iter().map(entry_json).collect()))
+}
+
+/// Plans a paste of the matching entries to the output
+fn plan(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    p: &PasteParams,
+) -> Result<(Vec<Entry>, engine::PastePlan), RpcError> {
+    let entries = find_entries(conn, args, p.queries.clone())?;
+    if entries.is_empty() {
+        return Err(RpcError::server("no matching entries"));
+    }
+
+    let output = utils::absolute_path(&p.output)
+        .to_string_lossy()
+        .to_string();
+    let plan = engine::plan(&RealFs, conn, &entries, args, |_| output.clone())
+        .map_err(RpcError::server)?;
+
+    Ok((entries, plan))
+}
+
+async fn paste(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    p: PasteParams,
+) -> Result<Value, RpcError> {
+    if p.delete && args.read_only {
+        return Err(RpcError::read_only());
+    }
+    let _lock = p.delete.then(lock_store).transpose()?;
+    let (entries, plan) = plan(conn, args, &p)?;
+
+    std::fs::create_dir_all(utils::absolute_path(&p.output)).map_err(RpcError::server)?;
+    let result = engine::paste(&RealFs, &plan, p.overwrite, &ProgressBar::hidden()).await;
+    // Like `paste --delete`, the entries are kept if any of their files didn't make it
+    engine::finish(conn, &entries, &plan, p.delete && result.is_success())
+        .map_err(RpcError::server)?;
+    tracing::info!(
+        pasted = result.pasted.len(),
+        failed = result.failed.len(),
+        "pasted entries over rpc"
+    );
+
+    Ok(json!({
+        "pasted": result.pasted,
+        "conflicts": result.conflicts,
+        "failed": result.failed.iter().map(|(path, e)| json!({
+            "path": path,
+            "error": e.to_string(),
+        })).collect::<Vec<_>>(),
+        "skipped": result.skipped,
+    }))
+}
+
+/// Deletes the matching entries, which needs queries so that a missing one
+/// doesn't delete every entry
+fn delete(
+    conn: &rusqlite::Connection,
+    args: &ConstructedArgs,
+    p: DeleteParams,
+) -> Result<Value, RpcError> {
+    if args.read_only {
+        return Err(RpcError::read_only());
+    }
+    if p.queries.is_empty() {
+        return Err(RpcError::invalid_params("no queries given"));
+    }
+
+    let _lock = lock_store()?;
+    let entries = find_entries(conn, args, p.queries)?;
+    let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    let deleted = db::delete_entries(conn, &paths).map_err(RpcError::server)?;
+    db::reid(conn).map_err(RpcError::server)?;
+    tracing::info!(entries = ?paths, "deleted entries over rpc");
+
+    Ok(json!({ "deleted": deleted, "paths": paths }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add, args, store, TempDir};
+
+    #[tokio::test]
+    async fn lists_and_pastes_entries() {
+        let dir = TempDir::new();
+        let file = dir.write("notes.txt", "hello");
+        let conn = store();
+        add(&conn, &[file.to_str().unwrap()]);
+
+        let call = |request: String| {
+            let conn = &conn;
+            async move {
+                let response = handle_request(conn, &args(), &request).await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&response).unwrap()
+            }
+        };
+
+        let listed = call(r#"{"jsonrpc":"2.0","id":1,"method":"list"}"#.to_string()).await;
+        assert_eq!(listed["id"], 1);
+        assert_eq!(listed["result"][0]["name"], "notes.txt");
+
+        let out = TempDir::new();
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "paste",
+            "params": { "output": out.0 },
+        });
+        let pasted = call(request.to_string()).await;
+        assert_eq!(pasted["result"]["pasted"].as_array().unwrap().len(), 1);
+        assert!(out.0.join("notes.txt").exists());
+
+        let unknown = call(r#"{"jsonrpc":"2.0","id":3,"method":"nope"}"#.to_string()).await;
+        assert_eq!(unknown["error"]["code"], -32601);
+        let notification = r#"{"jsonrpc":"2.0","method":"list"}"#;
+        assert!(handle_request(&conn, &args(), notification).await.is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! This module reads the files selected in a terminal file manager, with `add --from-selection`,
+//! so that selecting files and adding them to the store is a single keybinding
+//!
+//! lf, nnn and ranger all write their selections as a list of paths,
+//! separated by newlines, or by NUL characters as nnn does
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Names the selection file read when `--from-selection` is given without one
+pub const SELECTION_ENV: &str = "YNK_SELECTION";
+
+/// Reads the paths in a selection file, or in stdin for `-`,
+/// falling back to the file named by `YNK_SELECTION` when none is given
+///
+/// Returns an error message if the file can't be read or selects nothing
+pub fn read_selection(file: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let file = match file {
+        Some(file) => file.to_string(),
+        None => std::env::var(SELECTION_ENV).map_err(|_| {
+            format!(
+                "No selection file given, pass one or set {} to it",
+                SELECTION_ENV
+            )
+        })?,
+    };
+
+    let mut contents = Vec::new();
+    let read = if file == "-" {
+        std::io::stdin().read_to_end(&mut contents)
+    } else {
+        std::fs::File::open(Path::new(&file)).and_then(|mut f| f.read_to_end(&mut contents))
+    };
+    read.map_err(|e| format!("Could not read the selection {}: {}", file, e))?;
+
+    let paths = parse_selection(&contents);
+    if paths.is_empty() {
+        return Err(format!("Nothing is selected in {}", file));
+    }
+
+    Ok(paths)
+}
+
+/// Splits a selection into its paths, on NUL characters if it has any and on newlines otherwise,
+/// as a path can hold a newline but never a NUL
+pub fn parse_selection(contents: &[u8]) -> Vec<PathBuf> {
+    let separator = if contents.contains(&0) { 0 } else { b'\n' };
+
+    contents
+        .split(|b| *b == separator)
+        .map(|p| p.strip_suffix(b"\r").unwrap_or(p))
+        .filter(|p| !p.is_empty())
+        .map(|p| PathBuf::from(String::from_utf8_lossy(p).to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selections_are_split_like_file_managers_write_them() {
+        let lines = parse_selection(b"/home/me/a b.txt\n/home/me/dir\r\n\n");
+        assert_eq!(
+            lines,
+            vec![
+                PathBuf::from("/home/me/a b.txt"),
+                PathBuf::from("/home/me/dir")
+            ]
+        );
+
+        // nnn separates paths with NUL, which a path can't hold, unlike a newline
+        let nul = parse_selection(b"/home/me/line\nbreak\0/home/me/dir\0");
+        assert_eq!(
+            nul,
+            vec![
+                PathBuf::from("/home/me/line\nbreak"),
+                PathBuf::from("/home/me/dir")
+            ]
+        );
+    }
+}
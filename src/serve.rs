@@ -0,0 +1,433 @@
+//! `ynk serve`, a read-only HTTP server for fetching entries from another machine,
+//! ex: `curl -O http://192.168.1.4:40213/0/notes.md`
+//!
+//! Only GET and HEAD are answered. The entries are numbered in the order
+//! they are served in, with `/` listing them and `/N/` listing the files of a directory.
+//! Files in directories are only served if pasting the entry would paste them,
+//! so ignored and hidden files stay out unless the entry includes them
+
+use std::{
+    io,
+    net::{IpAddr, UdpSocket},
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::utils::{self, ListDirConfig};
+
+/// The most header lines read from a request, so a client can't keep one going forever
+const MAX_HEADERS: usize = 100;
+
+/// The most bytes read of the request line and headers together
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+
+/// How long a client has to send its request, before the connection is closed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many clients are answered at once, the others wait to be accepted
+const MAX_CLIENTS: usize = 32;
+
+/// An entry being served
+#[derive(Debug, Clone)]
+pub struct Shared {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// How the files of a directory are discovered, as they would be for a paste
+    pub config: ListDirConfig,
+    /// The files of a directory, sorted, listed once when they are first asked for
+    pub files: OnceLock<Vec<PathBuf>>,
+}
+
+impl Shared {
+    pub fn new(name: String, path: PathBuf, is_dir: bool, config: ListDirConfig) -> Self {
+        Self {
+            name,
+            path,
+            is_dir,
+            config,
+            files: OnceLock::new(),
+        }
+    }
+
+    /// The files a paste of the directory would paste, and so the ones that are served
+    ///
+    /// They are listed once, so a file added to the directory while it is
+    /// being served isn't served, and a download of the whole directory doesn't walk it again
+    /// for each of its files
+    fn files(&self) -> &[PathBuf] {
+        self.files.get_or_init(|| {
+            let mut files = utils::walk_dir(&self.path.to_string_lossy(), &self.config).paths;
+            files.sort();
+            files
+        })
+    }
+}
+
+/// The entries being served, and the token requests need if one is set
+#[derive(Debug)]
+pub struct Server {
+    pub entries: Vec<Shared>,
+    pub token: Option<String>,
+}
+
+/// The address of this machine on the local network, for the links printed on start
+///
+/// No packets are sent, connecting a UDP socket only picks the interface it would use
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+impl Server {
+    /// Answers requests until interrupted, at most [`MAX_CLIENTS`] at once
+    pub async fn run(&self, listener: TcpListener) -> io::Result<()> {
+        let mut clients = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept(), if clients.len() < MAX_CLIENTS => match accepted {
+                    Ok((stream, peer)) => {
+                        tracing::debug!(%peer, "a client connected");
+                        clients.push(async move {
+                            if let Err(e) = self.handle(stream).await {
+                                tracing::debug!(%peer, error = %e, "could not answer a request");
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!(error = %e, "could not accept a client"),
+                },
+                Some(()) = clients.next(), if !clients.is_empty() => {}
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers a single request, closing the connection afterwards
+    async fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let (read, mut write) = stream.split();
+        // The request is read before the token is checked, so it is kept short in both
+        // size and time, whoever sends it
+        let mut reader = BufReader::new(read).take(MAX_REQUEST_SIZE);
+
+        let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await;
+        let Ok(request) = request else {
+            return respond(
+                &mut write,
+                "408 Request Timeout",
+                "text/plain",
+                b"Request timeout",
+                false,
+            )
+            .await;
+        };
+        let (request_line, authorization) = request?;
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return respond(
+                &mut write,
+                "400 Bad Request",
+                "text/plain",
+                b"Bad request",
+                false,
+            )
+            .await;
+        };
+
+        let head = method == "HEAD";
+        if method != "GET" && !head {
+            return respond(
+                &mut write,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Only GET and HEAD are allowed",
+                head,
+            )
+            .await;
+        }
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if !self.authorized(query, authorization.as_deref()) {
+            return respond(
+                &mut write,
+                "401 Unauthorized",
+                "text/plain",
+                b"Wrong token",
+                head,
+            )
+            .await;
+        }
+
+        tracing::info!(method, path, "answering a request");
+        let Some(path) = percent_decode(path) else {
+            return respond(
+                &mut write,
+                "400 Bad Request",
+                "text/plain",
+                b"Bad path",
+                head,
+            )
+            .await;
+        };
+        self.route(&mut write, &path, head).await
+    }
+
+    /// Whether the request has the token, if one is needed,
+    /// as `?token=` or an `Authorization: Bearer` header
+    fn authorized(&self, query: &str, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        query
+            .split('&')
+            .filter_map(|pair| pair.strip_prefix("token="))
+            .any(|given| percent_decode(given).as_ref() == Some(token))
+            || authorization
+                .and_then(|a| a.strip_prefix("Bearer "))
+                .is_some_and(|given| given.trim() == token)
+    }
+
+    async fn route<W: AsyncWrite + Unpin>(
+        &self,
+        w: &mut W,
+        path: &str,
+        head: bool,
+    ) -> io::Result<()> {
+        let path = path.trim_start_matches('/');
+        if path.is_empty() {
+            return respond(
+                w,
+                "200 OK",
+                "text/html; charset=utf-8",
+                self.index().as_bytes(),
+                head,
+            )
+            .await;
+        }
+
+        let (index, rest) = path.split_once('/').unwrap_or((path, ""));
+        let Some(shared) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.entries.get(i))
+        else {
+            return not_found(w, head).await;
+        };
+
+        if !shared.is_dir {
+            // The name after the index is only there for the file to be saved as
+            return send_file(w, &shared.path, head).await;
+        }
+
+        if rest.is_empty() || rest.ends_with('/') {
+            return respond(
+                w,
+                "200 OK",
+                "text/html; charset=utf-8",
+                self.dir_index(index, shared).as_bytes(),
+                head,
+            )
+            .await;
+        }
+
+        let relative = Path::new(rest);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return not_found(w, head).await;
+        }
+        let file = shared.path.join(relative);
+        if shared.files().binary_search(&file).is_err() {
+            return not_found(w, head).await;
+        }
+
+        send_file(w, &file, head).await
+    }
+
+    fn index(&self) -> String {
+        let items = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, shared)| {
+                let href = if shared.is_dir {
+                    format!("/{}/", i)
+                } else {
+                    format!("/{}/{}", i, percent_encode(&shared.name))
+                };
+                let suffix = if shared.is_dir { "/" } else { "" };
+                format!(
+                    "<li><a href=\"{}\">{}{}</a></li>",
+                    self.with_token(&href),
+                    html_escape(&shared.name),
+                    suffix
+                )
+            })
+            .collect::<String>();
+
+        page("ynk", &items)
+    }
+
+    fn dir_index(&self, index: &str, shared: &Shared) -> String {
+        let mut files = shared
+            .files()
+            .iter()
+            .map(|p| utils::wrap_from_path(&shared.path, p).0)
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let items = files
+            .iter()
+            .map(|name| {
+                let href = format!("/{}/{}", index, percent_encode(&name.replace('\\', "/")));
+                format!(
+                    "<li><a href=\"{}\">{}</a></li>",
+                    self.with_token(&href),
+                    html_escape(name)
+                )
+            })
+            .collect::<String>();
+
+        page(&shared.name, &items)
+    }
+
+    /// Adds the token to a link, so the links of a page work when opened with it
+    fn with_token(&self, href: &str) -> String {
+        match &self.token {
+            Some(token) => format!("{}?token={}", href, percent_encode(token)),
+            None => href.to_string(),
+        }
+    }
+}
+
+/// Reads the request line and the `Authorization` header, if there is one,
+/// leaving the other headers out
+async fn read_request<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> io::Result<(String, Option<String>)> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    let mut authorization = None;
+    for _ in 0..MAX_HEADERS {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    Ok((request_line, authorization))
+}
+
+fn page(title: &str, items: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{0}</title></head><body><h1>{0}</h1><ul>{1}</ul></body></html>",
+        html_escape(title),
+        items
+    )
+}
+
+async fn respond<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    head: bool,
+) -> io::Result<()> {
+    let headers = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    w.write_all(headers.as_bytes()).await?;
+    if !head {
+        w.write_all(body).await?;
+    }
+    w.flush().await
+}
+
+async fn not_found<W: AsyncWrite + Unpin>(w: &mut W, head: bool) -> io::Result<()> {
+    respond(w, "404 Not Found", "text/plain", b"Not found", head).await
+}
+
+/// Streams a file as an attachment, named as it is on disk
+async fn send_file<W: AsyncWrite + Unpin>(w: &mut W, path: &Path, head: bool) -> io::Result<()> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(_) => return not_found(w, head).await,
+    };
+    let len = file.metadata().await?.len();
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().replace('"', ""))
+        .unwrap_or_default();
+
+    let headers = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Content-Disposition: attachment; filename=\"{}\"\r\nConnection: close\r\n\r\n",
+        content_type, len, name
+    );
+    w.write_all(headers.as_bytes()).await?;
+    if !head {
+        tokio::io::copy(&mut file, w).await?;
+    }
+    w.flush().await
+}
+
+/// Encodes everything in a path but the unreserved characters and `/`
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` escapes of a path or query value, failing on invalid ones or invalid UTF-8
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,346 @@
+//! This module contains the functions to take and remove snapshots
+//! A snapshot is a copy of an entry kept in the store directory,
+//! so that it can be pasted even after the source has changed
+
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use hashbrown::HashMap;
+
+use crate::{
+    files::{get_blobs_path, get_snapshots_path},
+    symlinks::SymlinkPolicy,
+    utils::{hash_file, hash_files, list_dir, wrap_from_path, ListDirConfig},
+};
+
+/// A file kept in a snapshot
+#[derive(Debug, Clone)]
+pub struct SnapshotFile {
+    /// The path of the file relative to the snapshot location
+    pub path: String,
+    /// The hash of the contents, which names the blob it is stored in
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Stores a file in the snapshot at the target path
+///
+/// The contents are kept once in the blobs directory under their hash,
+/// and the target is hard linked to the blob, so identical files
+/// across snapshots only take up space once.
+/// Falls back to a plain copy when hard links aren't supported
+fn store_file(
+    file: &Path,
+    hash: String,
+    target: &Path,
+    relative: String,
+) -> Result<SnapshotFile, std::io::Error> {
+    let blob = blob_path(&hash);
+
+    if !blob.exists() {
+        std::fs::create_dir_all(get_blobs_path())?;
+        std::fs::copy(file, &blob)?;
+    }
+
+    std::fs::create_dir_all(target.parent().unwrap())?;
+    if std::fs::hard_link(&blob, target).is_err() {
+        std::fs::copy(&blob, target)?;
+    }
+
+    Ok(SnapshotFile {
+        path: relative,
+        hash,
+        size: blob.metadata()?.len(),
+    })
+}
+
+/// Copies the source file or directory into a new directory
+/// in the snapshots directory
+///
+/// Directories are walked using the passed config,
+/// so the snapshot only has the files that would be pasted
+///
+/// # Returns
+///
+/// The location of the copy, which can be used in place of the source path,
+/// along with the files that were stored
+pub fn take_snapshot(
+    source: &Path,
+    name: &str,
+    config: &ListDirConfig,
+) -> Result<(PathBuf, Vec<SnapshotFile>), std::io::Error> {
+    let stamp = Local::now()
+        .timestamp_nanos_opt()
+        .unwrap_or_default()
+        .to_string();
+    let file_name = source
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or(name.to_string());
+    let location = get_snapshots_path().join(stamp).join(file_name);
+    let mut stored = Vec::new();
+
+    if source.is_dir() {
+        let (files, _) = list_dir(source.to_str().unwrap(), config);
+        std::fs::create_dir_all(&location)?;
+
+        // Files are stored as their hashes come in
+        for (file, hash) in hash_files(files) {
+            let (relative, _) = wrap_from_path(source, &file);
+            let target = location.join(&relative);
+            stored.push(store_file(&file, hash?, &target, relative)?);
+        }
+    } else {
+        stored.push(store_file(
+            source,
+            hash_file(source)?,
+            &location,
+            String::new(),
+        )?);
+    }
+
+    Ok((location, stored))
+}
+
+/// The differences between a snapshot and the current source
+#[derive(Debug, Default)]
+pub struct SnapshotDiff {
+    /// Files whose contents differ from the snapshot
+    pub changed: Vec<PathBuf>,
+    /// Files in the snapshot that no longer exist in the source
+    pub missing: Vec<PathBuf>,
+    pub unchanged: usize,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Lists the files kept in a snapshot as pairs of the file in the snapshot
+/// and where it belongs in the source
+fn snapshot_files(location: &Path, source: &Path) -> Vec<(PathBuf, PathBuf)> {
+    if !location.is_dir() {
+        return vec![(location.to_path_buf(), source.to_path_buf())];
+    }
+
+    // Everything in a snapshot was already filtered when it was taken
+    let config = ListDirConfig {
+        respect_ignore: false,
+        full_path: false,
+        strict: false,
+        hidden: true,
+        filter_file: true,
+        symlinks: SymlinkPolicy::Skip,
+    };
+    let (files, _) = list_dir(location.to_str().unwrap(), &config);
+
+    files
+        .into_iter()
+        .map(|file| {
+            let (relative, _) = wrap_from_path(location, &file);
+            (file, source.join(relative))
+        })
+        .collect()
+}
+
+/// Compares the files kept in a snapshot against the source
+/// Files that were added to the source after the snapshot are not reported
+pub fn diff_snapshot(location: &Path, source: &Path) -> Result<SnapshotDiff, std::io::Error> {
+    let mut diff = SnapshotDiff::default();
+
+    for (kept, current) in snapshot_files(location, source) {
+        if !current.exists() {
+            diff.missing.push(current);
+        } else if std::fs::read(&kept)? != std::fs::read(&current)? {
+            diff.changed.push(current);
+        } else {
+            diff.unchanged += 1;
+        }
+    }
+
+    diff.changed.sort();
+    diff.missing.sort();
+
+    Ok(diff)
+}
+
+/// Copies the files kept in a snapshot back over the source
+/// Only the files that differ from the snapshot are written
+///
+/// # Returns
+///
+/// The number of files that were written
+pub fn restore_snapshot(location: &Path, source: &Path) -> Result<usize, std::io::Error> {
+    let diff = diff_snapshot(location, source)?;
+    let mut count = 0;
+
+    for (kept, current) in snapshot_files(location, source) {
+        if !diff.changed.contains(&current) && !diff.missing.contains(&current) {
+            continue;
+        }
+
+        std::fs::create_dir_all(current.parent().unwrap())?;
+        std::fs::copy(&kept, &current)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Removes the copy kept for a snapshot
+/// along with the directory that was created for it
+pub fn remove_snapshot(location: &Path) -> Result<(), std::io::Error> {
+    let dir = location.parent().unwrap_or(location);
+    if dir.starts_with(get_snapshots_path()) && dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Lists the snapshot directories that are not in use by any of the given locations
+pub fn unreferenced_snapshots(locations: &[PathBuf]) -> Result<Vec<PathBuf>, std::io::Error> {
+    let snapshots_path = get_snapshots_path();
+    if !snapshots_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let in_use = locations
+        .iter()
+        .filter_map(|l| l.parent())
+        .collect::<Vec<_>>();
+
+    let mut unused = std::fs::read_dir(snapshots_path)?
+        .filter_map(|d| d.ok())
+        .map(|d| d.path())
+        .filter(|p| !in_use.contains(&p.as_path()))
+        .collect::<Vec<_>>();
+    unused.sort();
+
+    Ok(unused)
+}
+
+/// Calculates the size of everything under a path in kB
+pub fn disk_size(path: &Path) -> f64 {
+    if path.is_file() {
+        return path.metadata().map(|m| m.len()).unwrap_or(0) as f64 / 1024.0;
+    }
+
+    let config = ListDirConfig {
+        respect_ignore: false,
+        full_path: false,
+        strict: false,
+        hidden: true,
+        filter_file: true,
+        symlinks: SymlinkPolicy::Skip,
+    };
+    let (_, size) = list_dir(path.to_str().unwrap(), &config);
+
+    size
+}
+
+/// Checks the files of a snapshot at the location against the hashes they were stored with,
+/// as `(path, hash)` with the paths relative to the location
+///
+/// # Returns
+///
+/// The files that are missing, whose blob is gone or whose contents changed
+pub fn verify_snapshot(location: &Path, files: &[(String, String)]) -> Vec<String> {
+    let mut broken = Vec::new();
+    let mut expected = HashMap::new();
+    for (relative, hash) in files {
+        let path = match relative.is_empty() {
+            true => location.to_path_buf(),
+            false => location.join(relative),
+        };
+        if !path.is_file() || !blob_path(hash).is_file() {
+            broken.push(relative.clone());
+        } else {
+            expected.insert(path, (relative, hash));
+        }
+    }
+
+    for (path, hash) in hash_files(expected.keys().cloned().collect()) {
+        let (relative, expected) = expected[&path];
+        if !matches!(hash, Ok(hash) if hash == *expected) {
+            broken.push(relative.clone());
+        }
+    }
+    broken.sort();
+
+    broken
+}
+
+/// Hashes the files of a snapshot at the location again, as `(path, hash)` with the paths
+/// relative to the location, and moves the ones whose contents changed
+/// to the blob of their new hash. Missing files are left to `verify_snapshot`
+///
+/// # Returns
+///
+/// The files that changed, along with the hash they were stored with
+pub fn rehash_snapshot(
+    location: &Path,
+    files: &[(String, String)],
+) -> Result<Vec<(String, SnapshotFile)>, std::io::Error> {
+    let mut stored = HashMap::new();
+    for (relative, hash) in files {
+        let path = match relative.is_empty() {
+            true => location.to_path_buf(),
+            false => location.join(relative),
+        };
+        if path.is_file() {
+            stored.insert(path, (relative, hash));
+        }
+    }
+
+    let mut changed = Vec::new();
+    for (path, hash) in hash_files(stored.keys().cloned().collect()) {
+        let (relative, old) = stored[&path];
+        let hash = hash?;
+        if hash == *old {
+            continue;
+        }
+
+        // The file is still linked to the blob of the old hash,
+        // so it is linked to the one of the new hash instead
+        let blob = blob_path(&hash);
+        if !blob.exists() {
+            std::fs::create_dir_all(get_blobs_path())?;
+            std::fs::copy(&path, &blob)?;
+        }
+        std::fs::remove_file(&path)?;
+        changed.push((
+            old.clone(),
+            store_file(&blob, hash, &path, relative.clone())?,
+        ));
+    }
+
+    Ok(changed)
+}
+
+/// This function returns the path to the blob with the given hash
+pub fn blob_path(hash: &str) -> PathBuf {
+    get_blobs_path().join(hash)
+}
+
+/// Lists the hashes of all the blobs in the blobs directory
+pub fn stored_blobs() -> Result<Vec<String>, std::io::Error> {
+    let blobs_path = get_blobs_path();
+    if !blobs_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let blobs = std::fs::read_dir(blobs_path)?
+        .filter_map(|d| d.ok())
+        .map(|d| d.file_name().to_string_lossy().to_string())
+        .collect();
+
+    Ok(blobs)
+}
+
+/// Removes a blob from the blobs directory
+pub fn remove_blob(hash: &str) -> Result<(), std::io::Error> {
+    std::fs::remove_file(blob_path(hash))
+}
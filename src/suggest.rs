@@ -0,0 +1,194 @@
+//! `ynk suggest`, the entries likely to be pasted into the current directory,
+//! from where they were pasted before
+//!
+//! Every paste in the history counts towards its entry, the more the closer its target is
+//! to the current directory: the directory itself, a directory of the same kind, such as
+//! another Rust project or git repo, or one next to it. Older pastes count for less
+
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use hashbrown::{HashMap, HashSet};
+
+use crate::db::{Entry, PasteRecord};
+
+/// The files that tell what kind of directory a target is, most telling first
+const KINDS: [(&str, &str); 8] = [
+    ("Cargo.toml", "Rust projects"),
+    ("package.json", "Node projects"),
+    ("pyproject.toml", "Python projects"),
+    ("go.mod", "Go projects"),
+    ("pom.xml", "Java projects"),
+    ("Gemfile", "Ruby projects"),
+    ("Makefile", "projects with a Makefile"),
+    (".git", "git repos"),
+];
+
+/// How much a paste counts, by how close its target is to the current directory
+const HERE_WEIGHT: f64 = 1.0;
+const KIND_WEIGHT: f64 = 0.6;
+const SIBLING_WEIGHT: f64 = 0.4;
+const ELSEWHERE_WEIGHT: f64 = 0.1;
+
+/// A paste counts for half as much after this many days
+const HALF_LIFE_DAYS: f64 = 30.0;
+
+/// An entry suggested for the current directory, and why
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub entry: Entry,
+    pub score: f64,
+    pub reason: String,
+}
+
+/// How an entry was pasted before, relative to the current directory
+#[derive(Default)]
+struct Tally {
+    score: f64,
+    here: bool,
+    /// The targets of each kind the current directory is too
+    kinds: HashMap<&'static str, HashSet<String>>,
+    siblings: HashSet<String>,
+    targets: HashSet<String>,
+}
+
+/// The kinds of a directory, by the files in it
+fn kinds_of(dir: &Path) -> Vec<&'static str> {
+    KINDS
+        .iter()
+        .filter(|(marker, _)| dir.join(marker).exists())
+        .map(|(_, kind)| *kind)
+        .collect()
+}
+
+/// Suggests the entries to paste into `cwd`, best first
+///
+/// Only entries that were pasted before are suggested,
+/// and not the ones already in `cwd` under the name they would be pasted as
+pub fn suggest(entries: &[Entry], history: &[PasteRecord], cwd: &Path) -> Vec<Suggestion> {
+    let cwd_kinds = kinds_of(cwd);
+    let mut target_kinds: HashMap<&str, Vec<&'static str>> = HashMap::new();
+    let mut tallies: HashMap<&str, Tally> = HashMap::new();
+    let now = Local::now();
+
+    for record in history {
+        let target = PathBuf::from(&record.target);
+        let kinds = target_kinds
+            .entry(record.target.as_str())
+            .or_insert_with(|| kinds_of(&target));
+        // The most telling kind the target shares with the current directory
+        let shared = kinds.iter().find(|k| cwd_kinds.contains(k)).copied();
+
+        let tally = tallies.entry(record.entry_path.as_str()).or_default();
+        let weight = if target == cwd {
+            tally.here = true;
+            HERE_WEIGHT
+        } else if let Some(kind) = shared {
+            tally
+                .kinds
+                .entry(kind)
+                .or_default()
+                .insert(record.target.clone());
+            KIND_WEIGHT
+        } else if target.parent().is_some() && target.parent() == cwd.parent() {
+            tally.siblings.insert(record.target.clone());
+            SIBLING_WEIGHT
+        } else {
+            ELSEWHERE_WEIGHT
+        };
+
+        let age_days = (now - record.pasted_at).num_hours().max(0) as f64 / 24.0;
+        tally.score += weight * 0.5_f64.powf(age_days / HALF_LIFE_DAYS);
+        tally.targets.insert(record.target.clone());
+    }
+
+    let mut suggestions = entries
+        .iter()
+        .filter(|e| !cwd.join(&e.name).exists())
+        .filter_map(|e| {
+            let tally = tallies.get(e.path.as_str())?;
+            Some(Suggestion {
+                entry: e.clone(),
+                score: tally.score,
+                reason: reason(tally),
+            })
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    suggestions
+}
+
+fn reason(tally: &Tally) -> String {
+    let kind = tally
+        .kinds
+        .iter()
+        .max_by_key(|(kind, targets)| (targets.len(), std::cmp::Reverse(**kind)));
+
+    if let Some((kind, targets)) = kind {
+        return match targets.len() {
+            1 => format!("pasted into another of your {}", kind),
+            n => format!("usually pasted into {}, {} of them", kind, n),
+        };
+    }
+    if tally.here {
+        return "pasted here before".to_string();
+    }
+    match tally.siblings.len() {
+        0 => {}
+        1 => return "pasted into a directory next to this one".to_string(),
+        n => return format!("pasted into {} directories next to this one", n),
+    }
+    match tally.targets.len() {
+        1 => "pasted once before".to_string(),
+        n => format!("pasted into {} directories", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db,
+        testing::{add, store, TempDir},
+    };
+
+    #[test]
+    fn entries_are_suggested_where_they_are_usually_pasted() {
+        let dir = TempDir::new();
+        let editorconfig = dir.write("dotfiles/.editorconfig", "root = true");
+        let notes = dir.write("dotfiles/notes.txt", "hello");
+        for repo in ["api", "cli", "new"] {
+            dir.write(&format!("{}/Cargo.toml", repo), "[package]");
+        }
+        let conn = store();
+        let entries = add(
+            &conn,
+            &[editorconfig.to_str().unwrap(), notes.to_str().unwrap()],
+        );
+
+        for repo in ["api", "cli"] {
+            let target = dir.0.join(repo).to_string_lossy().to_string();
+            db::insert_paste_target(&conn, &target, &entries[0].path).unwrap();
+        }
+        let elsewhere = dir.0.join("dotfiles").to_string_lossy().to_string();
+        db::insert_paste_target(&conn, &elsewhere, &entries[1].path).unwrap();
+
+        let all = db::get_all(&conn).unwrap();
+        let history = db::get_paste_history(&conn).unwrap();
+        let suggestions = suggest(&all, &history, &dir.0.join("new"));
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(suggestions[0].entry.name, ".editorconfig");
+        assert_eq!(
+            suggestions[0].reason,
+            "usually pasted into Rust projects, 2 of them"
+        );
+        assert!(suggestions[0].score > suggestions[1].score);
+
+        // Nothing is suggested once it is already there
+        dir.write("new/.editorconfig", "root = true");
+        let suggestions = suggest(&all, &history, &dir.0.join("new"));
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].entry.name, "notes.txt");
+    }
+}
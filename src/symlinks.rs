@@ -0,0 +1,140 @@
+//! How symlinks in the directories being pasted are handled
+//!
+//! On Windows, junctions are treated as directory symlinks,
+//! and recreating a link needs `SeCreateSymbolicLinkPrivilege` or Developer Mode
+
+use std::str::FromStr;
+
+/// What happens to the symlinks found in a directory, from the `symlinks` config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Paste what the link points to, as if it were a regular file or directory
+    Follow,
+    /// Recreate the link itself, pointing to the same path
+    Copy,
+    /// Leave the link out
+    #[default]
+    Skip,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "copy" => Ok(SymlinkPolicy::Copy),
+            "skip" => Ok(SymlinkPolicy::Skip),
+            _ => Err(format!("\"{}\" is not one of follow, copy or skip", s)),
+        }
+    }
+}
+
+/// Whether the process is able to create symlinks, checked before any link is recreated
+///
+/// Always true on Unix. On Windows, it needs to hold `SeCreateSymbolicLinkPrivilege`,
+/// which elevated administrators do, or Developer Mode has to be on
+pub fn can_create_symlinks() -> bool {
+    #[cfg(windows)]
+    {
+        static CAN_CREATE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+        *CAN_CREATE.get_or_init(|| {
+            let can_create = windows::has_symlink_privilege() || windows::developer_mode();
+            tracing::debug!(can_create, "checked for symlink privileges");
+            can_create
+        })
+    }
+    #[cfg(not(windows))]
+    true
+}
+
+#[cfg(windows)]
+mod windows {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, ERROR_SUCCESS, HANDLE, LUID},
+        Security::{
+            GetTokenInformation, LookupPrivilegeValueW, TokenPrivileges, TOKEN_PRIVILEGES,
+            TOKEN_QUERY,
+        },
+        System::{
+            Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD},
+            Threading::{GetCurrentProcess, OpenProcessToken},
+        },
+    };
+
+    /// A NUL terminated UTF-16 string for the Windows APIs
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Whether the token of the process holds `SeCreateSymbolicLinkPrivilege`
+    pub fn has_symlink_privilege() -> bool {
+        let name = wide("SeCreateSymbolicLinkPrivilege");
+        let mut luid = LUID {
+            LowPart: 0,
+            HighPart: 0,
+        };
+        // SAFETY: the name is NUL terminated and the LUID outlives the call
+        if unsafe { LookupPrivilegeValueW(std::ptr::null(), name.as_ptr(), &mut luid) } == 0 {
+            return false;
+        }
+
+        let mut token: HANDLE = std::ptr::null_mut();
+        // SAFETY: the pseudo handle of the current process is always valid
+        if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+            return false;
+        }
+
+        // The first call only gets the size of the privileges
+        let mut len = 0;
+        // SAFETY: a null buffer with a length of 0 only writes the needed length
+        unsafe { GetTokenInformation(token, TokenPrivileges, std::ptr::null_mut(), 0, &mut len) };
+        // u64s keep the buffer aligned for TOKEN_PRIVILEGES
+        let mut buf = vec![0u64; (len as usize).div_ceil(8)];
+        // SAFETY: the buffer is at least `len` bytes long
+        let read = unsafe {
+            GetTokenInformation(
+                token,
+                TokenPrivileges,
+                buf.as_mut_ptr().cast(),
+                len,
+                &mut len,
+            )
+        } != 0;
+        // SAFETY: the token was opened above and isn't used after this
+        unsafe { CloseHandle(token) };
+        if !read {
+            return false;
+        }
+
+        // SAFETY: the buffer was filled with a TOKEN_PRIVILEGES followed by its privileges
+        let privileges = unsafe {
+            let header = &*(buf.as_ptr() as *const TOKEN_PRIVILEGES);
+            std::slice::from_raw_parts(header.Privileges.as_ptr(), header.PrivilegeCount as usize)
+        };
+        privileges
+            .iter()
+            .any(|p| p.Luid.LowPart == luid.LowPart && p.Luid.HighPart == luid.HighPart)
+    }
+
+    /// Whether Developer Mode is on, which lets anyone create symlinks
+    pub fn developer_mode() -> bool {
+        let key = wide(r"SOFTWARE\Microsoft\Windows\CurrentVersion\AppModelUnlock");
+        let value = wide("AllowDevelopmentWithoutDevLicense");
+        let mut data = 0u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        // SAFETY: the strings are NUL terminated and `data` is as big as `size` says
+        let status = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                key.as_ptr(),
+                value.as_ptr(),
+                RRF_RT_REG_DWORD,
+                std::ptr::null_mut(),
+                (&mut data as *mut u32).cast(),
+                &mut size,
+            )
+        };
+        status == ERROR_SUCCESS && data == 1
+    }
+}
@@ -0,0 +1,54 @@
+//! What the unit tests share, a temporary directory and an in-memory store
+
+use std::path::PathBuf;
+
+use rusqlite::Connection;
+
+use crate::{config, db, ConstructedArgs};
+
+/// A temporary directory, removed once dropped
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("ynk-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        // Entries are stored by their canonical path
+        Self(path.canonicalize().unwrap())
+    }
+
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn store() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    db::prep_db(&conn).unwrap();
+    conn
+}
+
+/// The arguments with every config option at its default
+pub fn args() -> ConstructedArgs {
+    ConstructedArgs::new(config::get_config(String::new()))
+}
+
+/// Adds the paths as entries named after their files
+pub fn add(conn: &Connection, paths: &[&str]) -> Vec<db::Entry> {
+    paths
+        .iter()
+        .map(|p| {
+            let name = crate::utils::parse_file_name(p);
+            db::insert_into_db(conn, db::EntryBuilder::new(&name, p, false)).unwrap()
+        })
+        .collect()
+}
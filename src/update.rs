@@ -0,0 +1,326 @@
+//! Checking for a new version of ynk on crates.io, which never holds up a command
+//!
+//! The check runs at most once a day, in the background while the command does its work,
+//! and what it found is cached in the store so the days in between don't need the network.
+//! The time of a check is cached as it starts, so one that fails or is abandoned
+//! when the command finishes first still waits a day to be tried again.
+//! It can be turned off with `check_updates = false` or by setting `YNK_NO_UPDATE_CHECK`
+//!
+//! `ynk update` replaces the running executable with the binary of the latest
+//! GitHub release for the platform, once its SHA-256 checksum matches the one released with it
+//! and its minisign signature is verified against the release key built into ynk.
+//! The key is taken from `YNK_RELEASE_PUBLIC_KEY` when ynk is built, as the release workflow does,
+//! and a build without it can't update itself
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use colored::Colorize;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use update_informer::{registry, Check};
+
+use crate::{
+    config::Config,
+    db::{format_timestamp, try_parse_timestamp},
+    files::get_path,
+};
+
+/// Setting this to anything turns the update check off, like `check_updates = false`
+pub const NO_UPDATE_CHECK_ENV: &str = "YNK_NO_UPDATE_CHECK";
+
+/// The file in the store the last check is cached in, as its time and the version found
+const CACHE_NAME: &str = "update_check";
+
+/// How often crates.io is asked for the latest version, in hours
+const CHECK_INTERVAL_HOURS: i64 = 24;
+
+/// How long the lookup may take, it is abandoned afterwards
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The GitHub repository the releases are downloaded from
+const REPOSITORY: &str = "newtoallofthis123/ynk";
+
+/// The minisign public key the releases are signed with, in base64
+pub const PUBLIC_KEY: Option<&str> = option_env!("YNK_RELEASE_PUBLIC_KEY");
+
+/// How long connecting to GitHub may take, and how long it may go without sending anything
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether updates are checked for, from the config and the environment
+pub fn enabled(config: &Config) -> bool {
+    config.check_updates
+        && !matches!(std::env::var_os(NO_UPDATE_CHECK_ENV), Some(v) if !v.is_empty())
+}
+
+/// An update check, either answered from the cache or running in the background
+pub enum UpdateCheck {
+    Cached(Option<String>),
+    Running(JoinHandle<Option<String>>),
+}
+
+impl UpdateCheck {
+    /// Starts a check unless they are turned off,
+    /// answering it from the cache if the last one was less than a day ago
+    pub fn start(config: &Config) -> Option<UpdateCheck> {
+        if !enabled(config) {
+            return None;
+        }
+
+        let previous = match read_cache() {
+            Some((checked_at, latest))
+                if Local::now() - checked_at < chrono::Duration::hours(CHECK_INTERVAL_HOURS) =>
+            {
+                return Some(UpdateCheck::Cached(latest));
+            }
+            Some((_, latest)) => latest,
+            None => None,
+        };
+        // Keeps the version found last time until this check finds one
+        write_cache(previous.as_deref());
+
+        Some(UpdateCheck::Running(thread::spawn(move || {
+            let latest = lookup().unwrap_or(previous);
+            write_cache(latest.as_deref());
+            latest
+        })))
+    }
+
+    /// Prints the newer version if one was found, without waiting for a check that
+    /// hasn't finished, which is abandoned and left for the next command to try again
+    pub fn notify(self) {
+        let latest = match self {
+            UpdateCheck::Cached(latest) => latest,
+            UpdateCheck::Running(handle) if handle.is_finished() => handle.join().ok().flatten(),
+            UpdateCheck::Running(_) => return,
+        };
+
+        if let Some(latest) = latest.filter(|v| is_newer(v, env!("CARGO_PKG_VERSION"))) {
+            eprintln!(
+                "A new version of ynk is available: {}, update with {}",
+                latest.yellow(),
+                "ynk update".cyan()
+            );
+        }
+    }
+}
+
+/// The latest version on crates.io, if it is newer than this one,
+/// or `None` if it couldn't be looked up
+fn lookup() -> Option<Option<String>> {
+    let informer = update_informer::new(
+        registry::Crates,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    )
+    // The interval is kept here, with the cache in the store
+    .interval(Duration::ZERO)
+    .timeout(TIMEOUT);
+
+    match informer.check_version() {
+        Ok(version) => Some(version.map(|v| v.to_string())),
+        Err(e) => {
+            tracing::debug!(error = %e, "could not check for updates");
+            None
+        }
+    }
+}
+
+fn read_cache() -> Option<(DateTime<Local>, Option<String>)> {
+    let cache = std::fs::read_to_string(get_path(CACHE_NAME)).ok()?;
+    let mut lines = cache.lines();
+    let checked_at = try_parse_timestamp(lines.next()?)?;
+    let latest = lines.next().filter(|v| !v.is_empty()).map(str::to_string);
+
+    Some((checked_at, latest))
+}
+
+fn write_cache(latest: Option<&str>) {
+    let cache = format!(
+        "{}\n{}\n",
+        format_timestamp(&Local::now()),
+        latest.unwrap_or_default()
+    );
+    if let Err(e) = std::fs::write(get_path(CACHE_NAME), cache) {
+        tracing::debug!(error = %e, "could not cache the update check");
+    }
+}
+
+/// Whether a version, ex: `v0.10.0`, is newer than another,
+/// so a cached one stops being shown once ynk is updated
+pub fn is_newer(version: &str, than: &str) -> bool {
+    let parse = |v: &str| {
+        v.trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .take(3)
+            .map(|part| part.parse::<u64>().unwrap_or_default())
+            .collect::<Vec<_>>()
+    };
+
+    parse(version) > parse(than)
+}
+
+/// A GitHub release, with the files released with it
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+
+    /// The binary for this platform, with the checksum and the signature released with it
+    pub fn binary(&self) -> Option<(&Asset, &Asset, &Asset)> {
+        let name = asset_name();
+        Some((
+            self.asset(&name)?,
+            self.asset(&format!("{}.sha256", name))?,
+            self.asset(&format!("{}.minisig", name))?,
+        ))
+    }
+}
+
+/// The name the binary for this platform is released as, ex: `ynk-x86_64-linux`
+/// or `ynk-aarch64-macos`, with `.exe` on Windows
+pub fn asset_name() -> String {
+    format!(
+        "ynk-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .user_agent(concat!("ynk/", env!("CARGO_PKG_VERSION")))
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build()
+}
+
+/// The latest release of ynk on GitHub
+pub fn latest_release() -> Result<Release, String> {
+    agent()
+        .get(&format!(
+            "https://api.github.com/repos/{}/releases/latest",
+            REPOSITORY
+        ))
+        .set("Accept", "application/vnd.github+json")
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_json::<Release>()
+        .map_err(|e| e.to_string())
+}
+
+/// Downloads a file released with a release
+pub fn download(asset: &Asset) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    agent()
+        .get(&asset.browser_download_url)
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+
+    Ok(bytes)
+}
+
+/// Checks the binary against a checksum file, which is the SHA-256 hash
+/// in hex, optionally followed by the name of the file as `sha256sum` writes it
+pub fn verify(binary: &[u8], checksum: &str) -> Result<(), String> {
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .ok_or("the checksum is empty")?
+        .to_lowercase();
+    let actual = Sha256::digest(binary)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    match actual == expected {
+        true => Ok(()),
+        false => Err(format!("expected {}, but got {}", expected, actual)),
+    }
+}
+
+/// Checks the binary against its minisign signature, with the release key built into ynk
+pub fn verify_signature(binary: &[u8], signature: &str) -> Result<(), String> {
+    let key = PUBLIC_KEY.ok_or("this build of ynk has no release key")?;
+    let key = minisign_verify::PublicKey::from_base64(key).map_err(|e| e.to_string())?;
+    let signature = minisign_verify::Signature::decode(signature).map_err(|e| e.to_string())?;
+
+    key.verify(binary, &signature, false)
+        .map_err(|e| e.to_string())
+}
+
+/// Whether the running executable was installed by `cargo install`,
+/// which is better updated by it, so it keeps track of the version
+pub fn installed_by_cargo() -> bool {
+    let Ok(exe) = std::env::current_exe() else {
+        return false;
+    };
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")));
+
+    cargo_home.is_some_and(|home| exe.starts_with(home.join("bin")))
+}
+
+/// Replaces the running executable with the binary, which is written next to it
+/// first so the replacement is a rename on the same filesystem
+pub fn install(binary: &[u8]) -> io::Result<PathBuf> {
+    let exe = std::env::current_exe()?.canonicalize()?;
+    let dir = exe.parent().unwrap_or(Path::new("."));
+    let staged = dir.join(format!(".ynk-update-{}", std::process::id()));
+
+    std::fs::write(&staged, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    let replaced = self_replace::self_replace(&staged);
+    let _ = std::fs::remove_file(&staged);
+    replaced.map(|_| exe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_compared_numerically() {
+        assert!(is_newer("v0.10.0", "0.9.6"));
+        assert!(is_newer("1.0.0", "0.9.6"));
+        assert!(!is_newer("v0.9.6", "0.9.6"));
+        assert!(!is_newer("v0.9.5", "0.9.6"));
+    }
+
+    #[test]
+    fn releases_are_verified_against_their_checksums() {
+        let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify(b"hello", hello).is_ok());
+        assert!(verify(b"hello", &format!("{}  ynk-x86_64-linux\n", hello)).is_ok());
+        assert!(verify(b"hello!", hello).is_err());
+        assert!(verify(b"hello", "").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! A copy backend built on io_uring, for Linux with the `io-uring` feature
+//!
+//! tokio-uring needs a runtime of its own, so it runs on a thread of its own
+//! and the copies are sent to it. Each file is read and then written a chunk at a time,
+//! with one read or write in flight per file, while the files are copied concurrently
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::fs::Copier;
+
+/// How much of a file is read before it is written, in bytes,
+/// unless `copy_buffer_size` is set
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// A file to copy on the io_uring runtime
+struct CopyJob {
+    from: PathBuf,
+    to: PathBuf,
+    done: oneshot::Sender<io::Result<()>>,
+}
+
+/// Copies files through io_uring, to be used with [`CopyingFs`](crate::fs::CopyingFs)
+#[derive(Debug, Clone)]
+pub struct UringCopy {
+    jobs: mpsc::UnboundedSender<CopyJob>,
+}
+
+impl UringCopy {
+    /// Starts the io_uring runtime, failing if the kernel doesn't support it
+    ///
+    /// Files are copied `chunk_size` bytes at a time
+    pub fn new(chunk_size: usize) -> io::Result<Self> {
+        let (jobs, mut rx) = mpsc::unbounded_channel::<CopyJob>();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("ynk-uring".to_string())
+            .spawn(move || {
+                let rt = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+
+                // Runs until every UringCopy is dropped, which closes the channel
+                rt.block_on(async move {
+                    while let Some(job) = rx.recv().await {
+                        tokio_uring::spawn(async move {
+                            let _ = job.done.send(copy(&job.from, &job.to, chunk_size).await);
+                        });
+                    }
+                });
+            })?;
+
+        ready_rx
+            .recv()
+            .map_err(|_| io::Error::other("the io_uring runtime failed to start"))??;
+        tracing::debug!("started the io_uring runtime");
+
+        Ok(Self { jobs })
+    }
+}
+
+/// Copies a file chunk by chunk, keeping its permissions like `std::fs::copy`
+async fn copy(from: &Path, to: &Path, chunk_size: usize) -> io::Result<()> {
+    let source = tokio_uring::fs::File::open(from).await?;
+    let target = tokio_uring::fs::File::create(to).await?;
+
+    let mut buf = Vec::with_capacity(chunk_size);
+    let mut pos = 0;
+    loop {
+        // A read fills the buffer from its start, setting its length
+        buf.clear();
+        let (read, b) = source.read_at(buf, pos).await;
+        let read = read?;
+        if read == 0 {
+            break;
+        }
+        let (written, b) = target.write_all_at(b, pos).await;
+        written?;
+
+        buf = b;
+        pos += read as u64;
+    }
+
+    source.close().await?;
+    target.close().await?;
+
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("the io_uring runtime has stopped")
+}
+
+impl Copier for UringCopy {
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        self.jobs
+            .send(CopyJob {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+                done,
+            })
+            .map_err(|_| stopped())?;
+
+        result.await.map_err(|_| stopped())?
+    }
+}
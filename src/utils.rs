@@ -2,19 +2,31 @@
 //! that are used throughout the codebase
 
 use std::{
-    path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    fs::{File, OpenOptions},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Arc, Mutex,
+    },
 };
 
-use clap::{command, Arg, ArgAction, Command};
+use clap::{command, Arg, ArgAction, Command, ValueHint};
 use colored::Colorize;
 use correct_word::levenshtein::levenshtein_distance;
+use globset::{GlobBuilder, GlobMatcher};
 use hashbrown::{HashMap, HashSet};
 use ignore::{WalkBuilder, WalkState};
 use path_abs::PathInfo;
-use update_informer::{registry, Check};
+use rayon::prelude::*;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::db::{Entry, EntryBuilder};
+use crate::{
+    db::{Entry, EntryBuilder},
+    files::get_logs_path,
+    symlinks::SymlinkPolicy,
+};
 
 pub fn does_file_exist(path: &str) -> bool {
     let path_buf = PathBuf::from(path);
@@ -32,6 +44,8 @@ pub struct ListDirConfig {
     pub strict: bool,
     pub hidden: bool,
     pub filter_file: bool,
+    /// What happens to the symlinks in the directory
+    pub symlinks: SymlinkPolicy,
 }
 
 /// Recursively lists all the files and directories in a directory
@@ -43,7 +57,7 @@ pub struct ListDirConfig {
 ///
 /// This function does not throw any errors
 /// when it encounters an error while listing the files
-/// so, if you want to handle errors, use `strict_list_dir`
+/// so, to find out which paths were skipped, use `walk_dir`
 ///
 /// # Arguments
 ///
@@ -61,36 +75,97 @@ pub struct ListDirConfig {
 ///
 /// A vector of `PathBuf`s
 pub fn list_dir(dir_path: &str, config: &ListDirConfig) -> (Vec<PathBuf>, f64) {
+    let listing = walk_dir(dir_path, config);
+    (listing.paths, listing.size)
+}
+
+/// The files found by `walk_dir`
+#[derive(Debug, Default)]
+pub struct DirListing {
+    pub paths: Vec<PathBuf>,
+    /// The total size of the files in KB
+    pub size: f64,
+    /// The paths that couldn't be read due to their permissions,
+    /// along with the sockets, FIFOs and devices, which can't be copied
+    pub skipped: Vec<PathBuf>,
+    /// The symlinks and junctions to recreate, with [`SymlinkPolicy::Copy`]
+    pub links: Vec<PathBuf>,
+}
+
+/// Walks a directory like `list_dir`, also returning the paths that were skipped
+/// because they couldn't be read due to their permissions or aren't regular files
+///
+/// Those are skipped even in strict mode, where any other error stops the walk
+#[tracing::instrument(level = "debug", skip(config))]
+pub fn walk_dir(dir_path: &str, config: &ListDirConfig) -> DirListing {
     let paths = Arc::new(Mutex::new(Vec::new()));
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+    let links = Arc::new(Mutex::new(Vec::new()));
     let size: Arc<Mutex<u64>> = Arc::new(Mutex::new(0));
 
     WalkBuilder::new(dir_path)
         .hidden(!config.hidden)
         .git_ignore(config.respect_ignore)
+        .follow_links(config.symlinks == SymlinkPolicy::Follow)
         .build_parallel()
         .run(|| {
             let paths = Arc::clone(&paths);
+            let skipped = Arc::clone(&skipped);
+            let links = Arc::clone(&links);
             let size = Arc::clone(&size);
 
             Box::new(move |entry| {
-                let entry = if config.strict {
-                    match entry {
-                        Ok(entry) => entry,
-                        Err(err) => {
-                            println!("{}: {:?}", "Error accessing entry:".red(), err);
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        let denied = err
+                            .io_error()
+                            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+                        if denied {
+                            tracing::warn!(error = %err, "Skipping an unreadable path");
+                            if let Some(path) = walk_error_path(&err) {
+                                skipped.lock().unwrap().push(path);
+                            }
+                            return WalkState::Continue;
+                        }
+
+                        if config.strict {
+                            tracing::error!(error = %err, "Error accessing entry");
                             return WalkState::Quit;
                         }
-                    }
-                } else {
-                    match entry {
-                        Ok(entry) => entry,
-                        Err(_) => return WalkState::Continue,
+                        return WalkState::Continue;
                     }
                 };
 
-                if config.filter_file && !entry.file_type().unwrap().is_file() {
+                // Followed links come with the file type of what they point to,
+                // while on Windows, junctions are links to directories
+                if entry.file_type().is_some_and(|t| t.is_symlink()) {
+                    if config.symlinks == SymlinkPolicy::Copy {
+                        links.lock().unwrap().push(entry.path().to_path_buf());
+                    }
+                    return WalkState::Continue;
+                }
+
+                let Some(file_type) = entry.file_type() else {
+                    return WalkState::Continue;
+                };
+                // Sockets, FIFOs and devices can't be copied like files
+                if !file_type.is_file() && !file_type.is_dir() {
+                    tracing::warn!(path = %entry.path().display(), "Skipping a special file");
+                    skipped.lock().unwrap().push(entry.path().to_path_buf());
                     return WalkState::Continue;
                 }
+                if config.filter_file && !file_type.is_file() {
+                    return WalkState::Continue;
+                }
+                let len = match entry.metadata() {
+                    Ok(metadata) => metadata.len(),
+                    Err(err) => {
+                        tracing::warn!(error = %err, "Skipping an unreadable path");
+                        skipped.lock().unwrap().push(entry.path().to_path_buf());
+                        return WalkState::Continue;
+                    }
+                };
 
                 let mut paths = paths.lock().unwrap();
 
@@ -101,8 +176,7 @@ pub fn list_dir(dir_path: &str, config: &ListDirConfig) -> (Vec<PathBuf>, f64) {
                 }
 
                 // add to size
-                let mut size = size.lock().unwrap();
-                *size += entry.metadata().unwrap().len();
+                *size.lock().unwrap() += len;
 
                 WalkState::Continue
             })
@@ -110,24 +184,252 @@ pub fn list_dir(dir_path: &str, config: &ListDirConfig) -> (Vec<PathBuf>, f64) {
 
     // convert to kb
     let size = *size.lock().unwrap() as f64 / 1024.0;
+    tracing::debug!(size, "listed directory");
 
     // Extract paths from the Mutex
-    (
-        Arc::try_unwrap(paths)
+    DirListing {
+        paths: Arc::try_unwrap(paths)
             .expect("Failed to unwrap Arc")
             .into_inner()
             .expect("Failed to extract paths from Mutex"),
         size,
-    )
+        skipped: Arc::try_unwrap(skipped)
+            .expect("Failed to unwrap Arc")
+            .into_inner()
+            .expect("Failed to extract skipped paths from Mutex"),
+        links: Arc::try_unwrap(links)
+            .expect("Failed to unwrap Arc")
+            .into_inner()
+            .expect("Failed to extract links from Mutex"),
+    }
+}
+
+/// The path an error from walking a directory is about, if it has one
+fn walk_error_path(err: &ignore::Error) -> Option<PathBuf> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path.clone()),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// Lists the files in a directory that would be excluded
+/// by the ignore and hidden rules in the given config
+///
+/// This walks the directory twice, once with the config and
+/// once without any filtering, and returns the difference
+pub fn list_ignored(dir_path: &str, config: &ListDirConfig) -> Vec<PathBuf> {
+    let unfiltered = ListDirConfig {
+        respect_ignore: false,
+        hidden: true,
+        ..config.clone()
+    };
+
+    let (kept, _) = list_dir(dir_path, config);
+    let (all, _) = list_dir(dir_path, &unfiltered);
+
+    let kept = kept.into_iter().collect::<HashSet<_>>();
+    let mut ignored = all
+        .into_iter()
+        .filter(|p| !kept.contains(p))
+        .collect::<Vec<_>>();
+    ignored.sort();
+
+    ignored
+}
+
+/// Hashes the contents of a file
+pub fn hash_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(path)?)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hashes the files in parallel on the rayon thread pool
+///
+/// The hashing runs in the background and the results are sent
+/// through the returned channel as soon as each file is done,
+/// so they can be consumed while the rest are still being hashed.
+/// The results are not in the same order as the paths
+pub fn hash_files(paths: Vec<PathBuf>) -> Receiver<(PathBuf, Result<String, std::io::Error>)> {
+    let (tx, rx) = channel();
+
+    rayon::spawn(move || {
+        paths.into_par_iter().for_each_with(tx, |tx, path| {
+            let hash = hash_file(&path);
+            let _ = tx.send((path, hash));
+        });
+    });
+
+    rx
+}
+
+/// Detects the MIME type of a path, from its contents
+/// or its extension when the contents aren't recognized
+///
+/// Directories are `inode/directory`, and files that can't be
+/// detected are `text/plain` if they look like text
+pub fn detect_file_type(path: &Path) -> String {
+    if path.is_dir() {
+        return "inode/directory".to_string();
+    }
+
+    if let Ok(Some(kind)) = infer::get_from_path(path) {
+        return kind.mime_type().to_string();
+    }
+    if let Some(mime) = mime_guess::from_path(path).first() {
+        return mime.essence_str().to_string();
+    }
+
+    // Sniff the start of the file for binary content
+    let mut buf = [0; 1024];
+    let read = File::open(path)
+        .and_then(|mut f| std::io::Read::read(&mut f, &mut buf))
+        .unwrap_or(0);
+    if std::str::from_utf8(&buf[..read]).is_ok() && !buf[..read].contains(&0) {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+/// Checks if a MIME type matches a `--type` filter, which is
+/// either a full MIME type, ex: `image/png`, or a part of one, ex: `image` or `pdf`
+pub fn matches_file_type(file_type: &str, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    if filter.contains('/') {
+        return file_type == filter;
+    }
+
+    let (kind, subtype) = file_type.split_once('/').unwrap_or((file_type, ""));
+    kind == filter || subtype == filter
+}
+
+/// The width of the terminal, or `None` when stdout isn't one
+pub fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(w, _)| w.0 as usize)
+}
+
+/// Formats a path to be shown to the user, with the home directory folded into `~`
+///
+/// Every path printed in tables, prompts and messages goes through this,
+/// and then through [`truncate_middle`] where space is short
+pub fn display_path(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~{}{}", std::path::MAIN_SEPARATOR, rest.to_string_lossy()),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Quotes a value so that it is passed to a POSIX shell as it is,
+/// for the commands suggested to the user, ex: `it's here` becomes `'it'\''s here'`
+///
+/// Values made of only safe characters are left as they are
+pub fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_alphanumeric() || "-_./:@%+=,~".contains(c);
+    if !value.is_empty() && value.chars().all(safe) && !value.starts_with('~') {
+        return value.to_string();
+    }
+
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Shortens a path to at most `max` columns by replacing its middle with `…`,
+/// keeping the first and as many of the last components as fit,
+/// ex: `/home/…/project/src/main.rs`
+///
+/// Widths are measured in terminal columns, so wide characters
+/// such as CJK and emoji count as two
+pub fn truncate_middle(path: &str, max: usize) -> String {
+    if path.width() <= max {
+        return path.to_string();
+    }
+
+    let sep = std::path::MAIN_SEPARATOR;
+    let parts = path.split(sep).collect::<Vec<_>>();
+    // An absolute path starts with an empty component
+    let head_len = if path.starts_with(sep) { 2 } else { 1 };
+
+    if parts.len() > head_len + 1 {
+        let head = parts[..head_len].join(&sep.to_string());
+        let mut tail = Vec::new();
+        // Each kept component comes with the separator before it
+        let mut tail_width = 0;
+        for part in parts[head_len..].iter().rev() {
+            let width = tail_width + part.width() + 1;
+            if head.width() + 2 + width > max {
+                break;
+            }
+            tail_width = width;
+            tail.push(*part);
+        }
+
+        if !tail.is_empty() {
+            tail.reverse();
+            return format!("{}{}…{}{}", head, sep, sep, tail.join(&sep.to_string()));
+        }
+    }
+
+    // Not even the file name fits, so cut the characters instead
+    let keep = max.saturating_sub(1);
+    let front = take_width(path.chars(), keep / 2);
+    let mut back = take_width(path.chars().rev(), keep - keep / 2);
+    back = back.chars().rev().collect();
+    format!("{}…{}", front, back)
+}
+
+/// Takes characters while they fit in `max` columns,
+/// stopping short rather than splitting a wide character
+fn take_width(chars: impl Iterator<Item = char>, max: usize) -> String {
+    let mut width = 0;
+    chars
+        .take_while(|c| {
+            width += c.width().unwrap_or_default();
+            width <= max
+        })
+        .collect()
+}
+
+/// Picks a Nerd Font glyph for a MIME type
+pub fn file_type_icon(file_type: &str) -> &'static str {
+    let (kind, subtype) = file_type.split_once('/').unwrap_or((file_type, ""));
+    match (kind, subtype) {
+        ("inode", "directory") => "\u{f07b}",
+        ("image", _) => "\u{f1c5}",
+        ("video", _) => "\u{f1c8}",
+        ("audio", _) => "\u{f1c7}",
+        (_, "pdf") => "\u{f1c1}",
+        (_, "zip" | "gzip" | "x-tar" | "x-xz" | "x-bzip2" | "x-7z-compressed" | "zstd") => {
+            "\u{f1c6}"
+        }
+        ("text", "plain" | "markdown") => "\u{f15c}",
+        ("text", _) | (_, "javascript" | "json" | "x-sh" | "x-rust" | "xml") => "\u{f1c9}",
+        _ => "\u{f15b}",
+    }
 }
 
 /// Constructs a vector of `EntryBuilder`s
 /// from a `HashMap` of `PathBuf`s
-pub fn construct_entry_builders(map: &HashMap<String, PathBuf>, is_dir: bool) -> Vec<EntryBuilder> {
+///
+/// The `hidden` and `respect_ignore` discovery options
+/// are recorded with every entry
+pub fn construct_entry_builders(
+    map: &HashMap<String, PathBuf>,
+    is_dir: bool,
+    hidden: bool,
+    respect_ignore: bool,
+) -> Vec<EntryBuilder> {
     let mut builders = Vec::new();
 
     for (name, path) in map {
-        let builder = EntryBuilder::new(name, path.to_str().unwrap(), is_dir);
+        let builder = EntryBuilder::new(name, path.to_str().unwrap(), is_dir)
+            .with_discovery(hidden, respect_ignore)
+            .with_file_type(detect_file_type(path));
         builders.push(builder);
     }
 
@@ -157,6 +459,66 @@ pub fn parse_file_name(path: &str) -> String {
     file_name.to_string()
 }
 
+/// The names entries added with `--preserve` are stored under, like `cp --parents` keeps them
+///
+/// Each path is named relative to `cwd` when all of them are under it,
+/// or else relative to the closest directory they share, so pasting them recreates
+/// `src/a.rs` and `tests/b.rs` in their directories. The paths are canonical
+pub fn preserved_names(paths: &[PathBuf], cwd: &Path) -> Vec<String> {
+    let root = if paths.iter().all(|p| p.starts_with(cwd) && p != cwd) {
+        cwd.to_path_buf()
+    } else {
+        let mut parents = paths.iter().map(|p| p.parent().unwrap_or(p));
+        let mut root = parents.next().map(Path::to_path_buf).unwrap_or_default();
+        for parent in parents {
+            while !parent.starts_with(&root) {
+                match root.parent() {
+                    Some(up) => root = up.to_path_buf(),
+                    None => break,
+                }
+            }
+        }
+        root
+    };
+
+    paths
+        .iter()
+        .map(|p| match p.strip_prefix(&root) {
+            Ok(rest) if !rest.as_os_str().is_empty() => rest.to_string_lossy().to_string(),
+            // The root of the filesystem has no name of its own
+            _ => p
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| p.to_string_lossy().to_string()),
+        })
+        .collect()
+}
+
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the status messages of the commands are left out, from `--silent`
+///
+/// Only the messages are, what a command is asked to print, such as the entries
+/// `list` prints or the file `pop --stdout` writes, is still printed
+pub fn set_silent(silent: bool) {
+    SILENT.store(silent, Ordering::Relaxed);
+}
+
+/// Whether the status messages are left out
+pub fn is_silent() -> bool {
+    SILENT.load(Ordering::Relaxed)
+}
+
+/// Prints a status message to stdout, like `println!`, unless `--silent` is given
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::utils::is_silent() {
+            println!($($arg)*);
+        }
+    };
+}
+
 /// Directly print a cool splash screen
 pub fn print_splash_screen() {
     println!("{}", "+-+-+-+".blue());
@@ -165,24 +527,196 @@ pub fn print_splash_screen() {
 }
 
 /// Checks if a directory is a git repo
-pub fn _is_git_repo(path: &str) -> bool {
-    let path = PathBuf::from(path);
+pub fn is_git_repo(path: &Path) -> bool {
     let git_path = path.join(".git");
 
     git_path.exists()
 }
 
-pub fn check_version() {
-    let pkg_name = env!("CARGO_PKG_NAME");
-    let current_version = env!("CARGO_PKG_VERSION");
+/// Finds the root of the git repo enclosing the path
+/// by walking up through its ancestors
+///
+/// Returns `None` if the path is not inside a git repo
+pub fn find_git_root(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path).canonicalize().ok()?;
 
-    let informer = update_informer::new(registry::Crates, pkg_name, current_version);
-    if let Some(version) = informer.check_version().unwrap() {
-        println!(
-            "A new version of ynk is available:: {}",
-            version.to_string().yellow()
-        );
+    let root = path.ancestors().find(|p| is_git_repo(p))?;
+
+    Some(root.to_path_buf())
+}
+
+/// Resolves a directory from a fuzzy query using zoxide,
+/// the same way `z QUERY` would jump to it
+///
+/// Returns an error message if zoxide isn't installed or nothing matches
+pub fn zoxide_query(query: &str) -> Result<PathBuf, String> {
+    let output = std::process::Command::new("zoxide")
+        .args(["query", "--", query])
+        .output()
+        .map_err(|e| format!("Could not run zoxide: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("No directory found by zoxide for \"{}\"", query));
+    }
+
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(PathBuf::from(path))
+}
+
+/// Opens a path with the platform opener, as if it was double clicked
+///
+/// With `reveal`, the folder containing the path is opened instead,
+/// with the path selected where the file manager supports it
+pub fn open_path(path: &Path, reveal: bool) -> Result<(), String> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = std::process::Command::new("open");
+        if reveal {
+            cmd.arg("-R");
+        }
+        cmd.arg(path);
+        cmd
+    } else {
+        let target = match (reveal, path.parent()) {
+            (true, Some(parent)) => parent,
+            _ => path,
+        };
+        if cfg!(windows) {
+            let mut cmd = std::process::Command::new("cmd");
+            cmd.args(["/C", "start", ""]).arg(target);
+            cmd
+        } else {
+            let mut cmd = std::process::Command::new("xdg-open");
+            cmd.arg(target);
+            cmd
+        }
+    };
+
+    let status = cmd
+        .status()
+        .map_err(|e| format!("Could not run the opener: {}", e))?;
+    if !status.success() {
+        return Err(format!("Could not open {}", path.display()));
+    }
+
+    Ok(())
+}
+
+/// Lets the user pick one of the candidates with an external fuzzy picker, such as fzf
+/// The candidates are written to the picker's stdin, one per line,
+/// and the line it prints is the selection
+///
+/// Returns an error message if the picker can't be run or nothing was picked
+pub fn run_picker(picker: &str, candidates: &[String]) -> Result<String, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // The picker is run through the platform shell, so it can be given arguments
+    #[cfg(windows)]
+    let mut cmd = {
+        use std::os::windows::process::CommandExt;
+
+        // cmd has its own quoting rules, so the command is passed as it is
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").raw_arg(picker);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", picker]);
+        cmd
+    };
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run the picker \"{}\": {}", picker, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The picker may exit before reading everything
+        let _ = stdin.write_all(candidates.join("\n").as_bytes());
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Could not run the picker \"{}\": {}", picker, e))?;
+    let selection = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if !output.status.success() || selection.is_empty() {
+        return Err("Nothing was picked".to_string());
+    }
+
+    Ok(selection)
+}
+
+/// The size after which the log file is rotated
+const LOG_FILE_SIZE: u64 = 1024 * 1024;
+/// The number of rotated log files that are kept
+const LOG_FILE_COUNT: usize = 5;
+
+/// Opens the log file in the logs directory for appending
+///
+/// If the log file has grown over `LOG_FILE_SIZE`, it is rotated first,
+/// ie `ynk.log` becomes `ynk.log.1`, `ynk.log.1` becomes `ynk.log.2` and so on,
+/// dropping the oldest one past `LOG_FILE_COUNT`
+pub fn open_log_file() -> Result<File, std::io::Error> {
+    let logs_path = get_logs_path();
+    std::fs::create_dir_all(&logs_path)?;
+
+    let log_file = logs_path.join("ynk.log");
+    let rotated = |n: usize| logs_path.join(format!("ynk.log.{}", n));
+
+    if log_file.metadata().map(|m| m.len()).unwrap_or(0) > LOG_FILE_SIZE {
+        let _ = std::fs::remove_file(rotated(LOG_FILE_COUNT));
+        for n in (1..LOG_FILE_COUNT).rev() {
+            if rotated(n).exists() {
+                std::fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        std::fs::rename(&log_file, rotated(1))?;
     }
+
+    OpenOptions::new().create(true).append(true).open(log_file)
+}
+
+/// Sets up the tracing subscriber that writes diagnostics to stderr
+///
+/// The level is picked from the number of `-v` flags passed,
+/// unless it is overridden by the `RUST_LOG` environment variable
+///
+/// With `to_file`, the invocation is also logged as JSON lines to the log file
+/// in the logs directory, irrespective of the verbosity
+pub fn setup_tracing(verbosity: u8, to_file: bool) {
+    let level = match verbosity {
+        0 => "warn",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("ynk={}", level)));
+
+    let stderr_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(atty::is(atty::Stream::Stderr))
+        .with_target(false)
+        .without_time()
+        .with_filter(filter);
+
+    let file_layer = to_file
+        .then(open_log_file)
+        .and_then(Result::ok)
+        .map(|file| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(Mutex::new(file))
+                .with_filter(EnvFilter::new("ynk=info"))
+        });
+
+    tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .init();
 }
 
 /// Converts the size from bytes to human readable string
@@ -207,19 +741,254 @@ pub fn convert_size(num: f64) -> String {
     format!("{}{} {}", negative, pretty_bytes, unit)
 }
 
+/// Expands a leading `~` in a path to the home directory
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home.to_string_lossy(), rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Turns a path given on the command line into an absolute, normalized one,
+/// without requiring it to exist
+pub fn absolute_path(path: &str) -> PathBuf {
+    let path = PathBuf::from(expand_home(path));
+    if path.is_absolute() {
+        return normalize_path(&path);
+    }
+
+    normalize_path(
+        &std::env::current_dir()
+            .expect("Could not get the current directory")
+            .join(path),
+    )
+}
+
+/// Resolves the `.` and `..` components of a path without touching the disk,
+/// unlike `canonicalize`, which needs every component to exist
+///
+/// Symlinks aren't followed, so `a/link/..` becomes `a`
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            // `..` can't go above the root, nor cancel out a leading `..`
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+/// Checks that files can be created in a directory by creating and removing one,
+/// which catches read only filesystems and missing permissions alike
+pub fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".ynk-probe-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(probe)
+}
+
+/// Builds a matcher for a glob over stored paths, such as `~/Downloads/**`
+/// A leading `~` is expanded to the home directory and `*` doesn't match `/`
+///
+/// Returns `None` if the glob is invalid
+pub fn glob_matcher(pattern: &str) -> Option<GlobMatcher> {
+    GlobBuilder::new(&expand_home(pattern))
+        .literal_separator(true)
+        .build()
+        .ok()
+        .map(|g| g.compile_matcher())
+}
+
+/// Parses a human readable duration such as `30d` or `12h`
+/// Supports minutes, hours, days and weeks
+///
+/// Returns `None` if the duration can't be parsed
+pub fn parse_duration(duration: &str) -> Option<chrono::Duration> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (num, unit) = duration.split_at(split);
+    let num = num.parse::<i64>().ok()?;
+
+    match unit.trim().to_lowercase().as_str() {
+        "m" | "min" => Some(chrono::Duration::minutes(num)),
+        "h" => Some(chrono::Duration::hours(num)),
+        "" | "d" => Some(chrono::Duration::days(num)),
+        "w" => Some(chrono::Duration::weeks(num)),
+        _ => None,
+    }
+}
+
+/// Parses a human readable size such as `5GB` or `512 kB` into bytes
+/// Uses the same decimal units as `convert_size`
+///
+/// Returns `None` if the size can't be parsed
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (num, unit) = size.split_at(split);
+    let num = num.parse::<f64>().ok()?;
+
+    let exponent = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" => 1,
+        "m" | "mb" => 2,
+        "g" | "gb" => 3,
+        "t" | "tb" => 4,
+        "p" | "pb" => 5,
+        _ => return None,
+    };
+
+    Some((num * 1000_f64.powi(exponent)) as u64)
+}
+
+/// Parses an octal file mode like `chmod` takes, such as `644` or `0755`
+///
+/// Returns `None` if the mode isn't octal or is larger than `7777`
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    let mode = mode.trim();
+    let mode = mode.strip_prefix("0o").unwrap_or(mode);
+    u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o7777)
+}
+
 pub fn sort_entries(entries: &mut [Entry]) {
     entries.sort_by(|a, b| b.id.cmp(&a.id));
 }
 
-pub fn deep_search(queries: Vec<String>, entries: &[Entry]) -> Vec<Entry> {
-    let mut res = HashSet::new();
+/// Sorts the entries by the given key
+///
+/// * `id` - the latest entry first, same as `sort_entries`
+/// * `created` - the most recently added entry first
+/// * `accessed` - the most recently pasted entry first
+/// * `name` - alphabetically by name
+pub fn sort_entries_by(entries: &mut [Entry], key: &str) {
+    match key {
+        "created" => entries.sort_by_key(|e| std::cmp::Reverse(e.created_at)),
+        "accessed" => entries.sort_by_key(|e| std::cmp::Reverse(e.accessed_at)),
+        "name" => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        "size" => entries.sort_by_key(|e| std::cmp::Reverse(e.size)),
+        _ => sort_entries(entries),
+    }
+}
 
-    if queries.is_empty() {
-        return entries.to_vec();
+/// Checks if a query refers to an entry by its uuid
+/// Any prefix of at least 8 characters, such as the short uuid, is accepted
+pub fn matches_uuid(query: &str, entry: &Entry) -> bool {
+    query == entry.uuid || (query.len() >= 8 && entry.uuid.starts_with(query))
+}
+
+/// What `prompt-status` prints by default
+pub const DEFAULT_PROMPT_FORMAT: &str = "{count}⧉";
+
+/// The similarity a query needs to fuzzily match an entry name by default
+pub const DEFAULT_MATCH_THRESHOLD: f64 = 0.5;
+
+/// How the queries are matched against the entries,
+/// from `--exact`, `--threshold` or `exact_match` and `match_threshold` in the config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Matching {
+    /// Only the id, name, path or uuid of an entry
+    Exact,
+    /// Also the names starting with the query, or the paths under it,
+    /// or else the names at least this similar to it, from 0 to 1
+    Fuzzy(f64),
+}
+
+impl Default for Matching {
+    fn default() -> Self {
+        Matching::Fuzzy(DEFAULT_MATCH_THRESHOLD)
+    }
+}
+
+/// Why an entry matched a query, as shown by `--explain`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchReason {
+    Id,
+    Name,
+    Path,
+    Uuid,
+    NamePrefix,
+    /// The entry is under the path given as the query
+    PathPrefix,
+    /// The name is this similar to the query, from 0 to 1
+    Fuzzy(f64),
+}
+
+impl std::fmt::Display for MatchReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchReason::Id => write!(f, "id"),
+            MatchReason::Name => write!(f, "exact name"),
+            MatchReason::Path => write!(f, "exact path"),
+            MatchReason::Uuid => write!(f, "uuid"),
+            MatchReason::NamePrefix => write!(f, "name prefix"),
+            MatchReason::PathPrefix => write!(f, "path prefix"),
+            MatchReason::Fuzzy(score) => write!(f, "fuzzy, {:.2}", score),
+        }
+    }
+}
+
+/// An entry a query matched, and why
+#[derive(Debug, Clone)]
+pub struct QueryMatch {
+    pub query: String,
+    pub id: i32,
+    pub reason: MatchReason,
+}
+
+/// How similar a query is to a name, from 0 to 1, by their levenshtein distance
+pub fn similarity(query: &str, name: &str) -> f64 {
+    let leven_dis = levenshtein_distance(query.to_string(), name.to_string());
+    1.0 - (leven_dis as f64 / std::cmp::max(query.len(), name.len()) as f64)
+}
+
+/// Why the entry matches the query exactly or by prefix, if it does
+fn exact_reason(query: &str, entry: &Entry, matching: Matching) -> Option<MatchReason> {
+    if query.parse::<i32>().ok() == Some(entry.id) {
+        Some(MatchReason::Id)
+    } else if query == entry.name {
+        Some(MatchReason::Name)
+    } else if query == entry.path {
+        Some(MatchReason::Path)
+    } else if matches_uuid(query, entry) {
+        Some(MatchReason::Uuid)
+    } else if matching == Matching::Exact {
+        None
+    } else if entry.name.starts_with(query) {
+        Some(MatchReason::NamePrefix)
+    } else if entry.path.starts_with(query) {
+        Some(MatchReason::PathPrefix)
+    } else {
+        None
     }
+}
+
+/// Matches every query against the entries, returning each match with the reason for it
+///
+/// A query matching some entries exactly or by prefix only matches those,
+/// so the fuzzy matches are left for when nothing else matches it
+pub fn match_queries(queries: &[String], entries: &[Entry], matching: Matching) -> Vec<QueryMatch> {
+    let mut matches = Vec::new();
 
     for query in queries {
-        let mut query = query;
+        let mut query = query.clone();
         if PathBuf::from(query.clone()).exists() {
             query = PathBuf::from(query.clone())
                 .canonicalize()
@@ -227,23 +996,49 @@ pub fn deep_search(queries: Vec<String>, entries: &[Entry]) -> Vec<Entry> {
                 .to_string_lossy()
                 .to_string();
         }
-        entries.iter().for_each(|e| {
-            let leven_dis = levenshtein_distance(query.to_string(), e.name.clone());
-            let dis = 1.0 - (leven_dis as f64 / std::cmp::max(query.len(), e.name.len()) as f64);
-            if let Ok(id) = query.parse::<i32>() {
-                res.insert(id);
-            }
-            if query == e.name
-                || query == e.path
-                || e.name.starts_with(&query)
-                || e.path.starts_with(&query)
-                || dis >= 0.5
-            {
-                res.insert(e.id);
-            }
-        });
+
+        let mut matched = entries
+            .iter()
+            .filter_map(|e| {
+                exact_reason(&query, e, matching).map(|reason| QueryMatch {
+                    query: query.clone(),
+                    id: e.id,
+                    reason,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let (true, Matching::Fuzzy(threshold)) = (matched.is_empty(), matching) {
+            matched = entries
+                .iter()
+                .filter_map(|e| {
+                    let score = similarity(&query, &e.name);
+                    (score >= threshold).then(|| QueryMatch {
+                        query: query.clone(),
+                        id: e.id,
+                        reason: MatchReason::Fuzzy(score),
+                    })
+                })
+                .collect();
+        }
+        matches.extend(matched);
+    }
+
+    matches
+}
+
+/// Finds the entries matching any of the queries, or all of them without queries,
+/// as [`match_queries`] matches them
+pub fn deep_search(queries: Vec<String>, entries: &[Entry], matching: Matching) -> Vec<Entry> {
+    if queries.is_empty() {
+        return entries.to_vec();
     }
 
+    let res = match_queries(&queries, entries, matching)
+        .into_iter()
+        .map(|m| m.id)
+        .collect::<HashSet<_>>();
+
     entries
         .iter()
         .filter(|y| res.contains(&y.id))
@@ -252,7 +1047,7 @@ pub fn deep_search(queries: Vec<String>, entries: &[Entry]) -> Vec<Entry> {
 }
 
 pub fn setup_cli() -> Command {
-    command!()
+    let cli = command!()
         .author("NoobScience <noobscience@duck.com>")
         .about("Copy paste files in the terminal")
         .arg(
@@ -263,30 +1058,172 @@ pub fn setup_cli() -> Command {
                 .global(true)
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("exact")
+                .long("exact")
+                .help("Only match the exact id, name, path or uuid of the entries")
+                .global(true)
+                .conflicts_with("threshold")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .help("How similar a query has to be to fuzzily match a name, from 0 to 1")
+                .global(true)
+                .value_parser(clap::value_parser!(f64))
+                .value_name("F"),
+        )
+        .arg(
+            Arg::new("explain")
+                .long("explain")
+                .help("Show why each entry matched the queries")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Show diagnostics, pass twice for more detail")
+                .global(true)
+                .action(ArgAction::Count),
+        )
         .arg(
             Arg::new("yes")
                 .short('y')
-                .help("Prompt yes to all prompts")
+                .help("Answer yes to all prompts instead of asking")
                 .long("yes")
+                .visible_alias("no-prompt")
                 .global(true)
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("all")
-                .short('a')
-                .long("all")
-                .help("Also include hidden files in discovery")
+            Arg::new("silent")
+                .long("silent")
+                .help("Don't print status messages, only errors and what was asked for, and don't prompt")
                 .global(true)
                 .action(ArgAction::SetTrue),
         )
-        .subcommand(
-            Command::new("list").arg(
-                Arg::new("size")
-                    .id("size")
-                    .long("size")
-                    .short('s')
-                    .help("Calculate and show the size column")
-                    .action(ArgAction::SetTrue),
+        .arg(
+            Arg::new("no-progress")
+                .long("no-progress")
+                .help("Don't show progress bars")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("store")
+                .long("store")
+                .value_name("PATH")
+                .help("Keep the store in PATH, or in memory with :memory:")
+                .global(true)
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .help("Never change the store, refusing the commands that would")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("all")
+                .short('a')
+                .long("all")
+                .help("Also include hidden files in discovery")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rpc")
+                .long("rpc")
+                .help("Speak JSON-RPC over stdin and stdout, for editor plugins")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(
+            Command::new("list").arg(
+                Arg::new("size")
+                    .id("size")
+                    .long("size")
+                    .short('s')
+                    .help("Calculate and show the size column")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("created")
+                    .long("created")
+                    .help("Show the created column")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("sort")
+                    .long("sort")
+                    .help("Sort the entries by the given key")
+                    .value_parser(["id", "created", "accessed", "name", "size"])
+                    .num_args(1),
+            ).arg(
+                Arg::new("style")
+                    .long("style")
+                    .help("How the entries are displayed")
+                    .value_parser(["detailed", "compact", "icons"])
+                    .num_args(1),
+            ).arg(
+                Arg::new("names-only")
+                    .long("names-only")
+                    .help("Print only the names of the entries, one per line")
+                    .group("plain")
+                    .conflicts_with("deleted")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("paths-only")
+                    .long("paths-only")
+                    .help("Print only the paths of the entries, one per line")
+                    .group("plain")
+                    .conflicts_with_all(["deleted", "names-only"])
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("print0")
+                    .long("print0")
+                    .short('0')
+                    .help("Separate the names or paths with NUL instead of newlines")
+                    .requires("plain")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("full-path")
+                    .long("full-path")
+                    .help("Don't shorten long paths to fit the terminal")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("top")
+                    .long("top")
+                    .help("Only list the first N entries, ex: the largest with --sort size")
+                    .value_parser(clap::value_parser!(usize))
+                    .num_args(1),
+            ).arg(
+                Arg::new("dirs")
+                    .long("dirs")
+                    .help("Only list directories")
+                    .conflicts_with("files")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("files")
+                    .long("files")
+                    .help("Only list files")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("types")
+                    .long("types")
+                    .help("Show the type column")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("type")
+                    .long("type")
+                    .help("Only list the entries of the type, ex: image, pdf or text/plain")
+                    .num_args(1),
+            ).arg(
+                Arg::new("deleted")
+                    .long("deleted")
+                    .help("List the deleted entries in the trash instead")
+                    .action(ArgAction::SetTrue),
             ).long_about("List the entires in the store"),
         )
         .subcommand(
@@ -303,6 +1240,48 @@ pub fn setup_cli() -> Command {
                         .help("Preserves the dir structure")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("snapshot")
+                        .long("snapshot")
+                        .help("Keep a copy of the entries in the store")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Show what would be added without writing to the store")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("strict").help("Refuse entries over the warn_entry_size").long("strict").action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("from-clipboard")
+                        .long("from-clipboard")
+                        .help("Add the files copied in a file manager")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("from-selection")
+                        .long("from-selection")
+                        .help("Add the files in a lf, nnn or ranger selection file, or - for stdin")
+                        .num_args(0..=1)
+                        .default_missing_value("")
+                        .value_hint(ValueHint::FilePath)
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .help("Where to paste the entries when no output is given")
+                        .value_hint(ValueHint::DirPath)
+                        .num_args(1)
+                        .value_name("PATH"),
+                )
+                .arg(
+                    Arg::new("show-ignored")
+                        .long("show-ignored")
+                        .help("Show the files that ignore rules would exclude instead of adding")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("files")
                         .help("The list of files to add")
@@ -316,6 +1295,23 @@ pub fn setup_cli() -> Command {
                     .help("The queries to file the entries")
                     .num_args(1..)
                     .value_name("QUERIES"),
+            ).arg(
+                Arg::new("range").long("range").help("Specify the range of entries to delete: Works using the syntax of n..[m]").short('r').num_args(1)
+            ).arg(
+                Arg::new("glob")
+                    .long("glob")
+                    .help("Only delete entries whose path matches the glob, ex: '~/Downloads/**'")
+                    .num_args(1),
+            ).arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .help("Only show the entries that would be deleted")
+                    .action(ArgAction::SetTrue),
+            ).arg(
+                Arg::new("all-matches")
+                    .long("all-matches")
+                    .help("Delete every entry a query matches instead of asking which were meant")
+                    .action(ArgAction::SetTrue),
             ),
         )
         .subcommand(
@@ -332,11 +1328,445 @@ pub fn setup_cli() -> Command {
                         .long("output")
                         .short('o')
                         .help("The output dir or file")
+                        .value_hint(ValueHint::DirPath)
+                        .num_args(1)
+                )
+                .arg(Arg::new("strict").help("Error on any IO error").long("strict").action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .help("Keep the entry in the store after pasting it")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("symlinks")
+                        .long("symlinks")
+                        .help("Follow, copy or skip the symlinks in the entry")
+                        .value_parser(["follow", "copy", "skip"])
+                        .value_name("POLICY"),
+                )
+                .arg(
+                    Arg::new("preserve")
+                        .long("preserve")
+                        .help("Keep these attributes of the files, ex: mode,times,xattr or all")
+                        .num_args(1)
+                        .value_name("ATTRS"),
+                )
+                .arg(
+                    Arg::new("no-preserve-timestamps")
+                        .long("no-preserve-timestamps")
+                        .help("Don't keep the times of the files, even if preserve_attributes does")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("chmod")
+                        .long("chmod")
+                        .help("Set the mode of the pasted files, ex: 644")
+                        .num_args(1)
+                        .value_name("MODE"),
+                )
+                .arg(
+                    Arg::new("dir-mode")
+                        .long("dir-mode")
+                        .help("Set the mode of the directories created for the files, ex: 755")
+                        .num_args(1)
+                        .value_name("MODE"),
+                )
+                .arg(
+                    Arg::new("chown")
+                        .long("chown")
+                        .help("Give the files to this user and group, ex: alice:staff")
+                        .num_args(1)
+                        .value_name("OWNER"),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .long("stdout")
+                        .help("Print the contents of the file to stdout instead of pasting it")
+                        .conflicts_with("output")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("open")
+                .long_about("Open entries with the default application")
+                .arg(
+                    Arg::new("reveal")
+                        .long("reveal")
+                        .help("Open the folder containing the entry instead")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("all-matches")
+                        .long("all-matches")
+                        .help("Open every entry the queries match instead of asking which was meant")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .required(true)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("count")
+                .long_about("Print the number of entries matching the queries")
+                .arg(
+                    Arg::new("files")
+                        .long("files")
+                        .help("Print the total number of files in the entries instead")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("du")
+                .long_about("Show what takes up the space in a directory entry")
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("refresh")
+                .long_about("Re-resolve the entries against the filesystem, updating what is cached about them")
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(Command::new("targets").long_about("List the recent paste targets"))
+        .subcommand(
+            Command::new("suggest")
+                .long_about("Suggest the entries you usually paste into places like the current directory, and paste the ones chosen")
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .help("Suggest at most N entries")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5")
+                        .value_name("N"),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .long_about("Summarize the store from what is cached, without walking any directory")
+                .arg(
+                    Arg::new("short")
+                        .long("short")
+                        .help("Only the number of entries, the top entry and the total size")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the summary as JSON, for prompt integrations")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("prompt-status")
+                .long_about("Print a short summary of the store for a shell prompt, nothing when it is empty")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("What is printed, with {count} replaced by the number of entries")
+                        .default_value(DEFAULT_PROMPT_FORMAT),
+                ),
+        )
+        .subcommand(
+            Command::new("complete-queries")
+                .hide(true)
+                .long_about("List the entry ids and names the queries are completed with"),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .long_about("Serve the ynk store over a local socket with JSON-RPC, for editor integrations")
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .help("Listen on this socket, or named pipe on Windows")
                         .num_args(1)
+                        .value_name("PATH"),
+                ),
+        )
+        .subcommand(
+            Command::new("serve")
+                .long_about("Serve entries read-only over HTTP, to fetch them from another machine")
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .short('p')
+                        .help("Listen on this port instead of a random one")
+                        .value_parser(clap::value_parser!(u16))
+                        .value_name("PORT"),
+                )
+                .arg(
+                    Arg::new("bind")
+                        .long("bind")
+                        .help("Listen on this address, ex: 127.0.0.1 for this machine only")
+                        .default_value("0.0.0.0")
+                        .value_name("ADDR"),
+                )
+                .arg(
+                    Arg::new("token")
+                        .long("token")
+                        .help("Require this token, or a random one if none is given")
+                        .num_args(0..=1)
+                        .default_missing_value("")
+                        .value_name("TOKEN"),
+                )
+                .arg(
+                    Arg::new("no-token")
+                        .long("no-token")
+                        .help("Answer anyone who can reach the server, without a token")
+                        .conflicts_with("token")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("qr")
+                        .long("qr")
+                        .help("Print the link as a QR code, to open it on a phone")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires, the top entry if none are given")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .long_about("Show the log of the changes made to the ynk store")
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .help("Show only the latest N changes")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("50")
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::new("full")
+                        .long("full")
+                        .help("Show every path a change affected")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("pin")
+                .long_about("Pin entries, so that clear --keep-pinned keeps them")
+                .arg(
+                    Arg::new("remove")
+                        .long("remove")
+                        .help("Unpin the entries instead")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .required(true)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("tag")
+                .long_about("Tag entries, so that they can be cleared with clear --tag")
+                .arg(
+                    Arg::new("remove")
+                        .long("remove")
+                        .help("Remove the tag from the entries instead")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .help("The tag to give the entries")
+                        .required(true)
+                        .value_name("TAG"),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .required(true)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(Command::new("swap").long_about("Swap the top two entries in the ynk store"))
+        .subcommand(
+            Command::new("roll")
+                .long_about("Rotate the top N entries, moving the Nth entry to the top")
+                .arg(
+                    Arg::new("n")
+                        .help("The number of entries to rotate")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true)
+                        .value_name("N"),
+                ),
+        )
+        .subcommand(
+            Command::new("clear")
+                .long_about("Clear entries from the ynk store, all of them unless filtered")
+                .arg(
+                    Arg::new("older-than")
+                        .long("older-than")
+                        .help("Only clear entries added longer ago than this, ex: 30d")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("dirs-only")
+                        .long("dirs-only")
+                        .help("Only clear directories")
+                        .conflicts_with("files-only")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("files-only")
+                        .long("files-only")
+                        .help("Only clear files")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .help("Keep the given number of most recently added entries")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("keep-pinned")
+                        .long("keep-pinned")
+                        .help("Keep the entries pinned with ynk pin")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .help("Only clear the entries with the tag")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Only clear the entries matching the queries")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .long_about("Show the details of entries in the ynk store")
+                .arg(
+                    Arg::new("versions")
+                        .long("versions")
+                        .help("Also show the snapshot versions of the entries")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .long_about("Restore a snapshot of entries over their source, or deleted entries with --deleted")
+                .arg(
+                    Arg::new("version")
+                        .long("version")
+                        .help("Restore the given snapshot version instead of the latest")
+                        .value_parser(clap::value_parser!(i32))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("deleted")
+                        .long("deleted")
+                        .help("Bring back deleted entries from the trash, by default the last ones deleted")
+                        .conflicts_with("version")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .help("Queries to filter the entires")
+                        .num_args(1..)
+                        .required_unless_present("deleted")
+                        .value_name("QUERIES"),
+                ),
+        )
+        .subcommand(
+            Command::new("gc")
+                .long_about("Remove snapshots no longer used by any entry")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Only report what would be removed")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(Command::new("stats").long_about("Show statistics about the ynk store"))
+        .subcommand(
+            Command::new("config")
+                .long_about("Inspect the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("validate")
+                        .long_about("Check the config file for unknown keys and invalid values"),
+                )
+                .subcommand(Command::new("show").long_about(
+                    "Show the effective config and whether each value comes from the defaults, the config file or a flag",
+                )),
+        )
+        .subcommand(
+            Command::new("bench")
+                .hide(true)
+                .long_about("Benchmark the copy engine on a generated tree")
+                .arg(
+                    Arg::new("files")
+                        .long("files")
+                        .help("The number of files to generate")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::new("size")
+                        .long("size")
+                        .help("The size of each generated file, ex: 64kB")
+                        .default_value("64kB"),
                 )
-                .arg(Arg::new("strict").help("Error on any IO error").long("strict").action(ArgAction::SetTrue)),
+                .arg(
+                    Arg::new("buffer-sizes")
+                        .long("buffer-sizes")
+                        .help("The sizes of the buffers to stream the copies through")
+                        .value_delimiter(',')
+                        .default_value("64kB,1MB,8MB"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .long_about("Check every entry in the store against the filesystem")
+                .arg(
+                    Arg::new("fix")
+                        .long("fix")
+                        .help("Point the entries whose files were moved at where they are now")
+                        .action(ArgAction::SetTrue),
+                ),
         )
-        .subcommand(Command::new("clear").long_about("Clear all entries from the ynk store"))
         .subcommand(
             Command::new("paste")
                 .long_about("Paste entries from the ynk store")
@@ -354,21 +1784,157 @@ pub fn setup_cli() -> Command {
                         .short('d')
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("symlinks")
+                        .long("symlinks")
+                        .help("Follow, copy or skip the symlinks in the entries")
+                        .value_parser(["follow", "copy", "skip"])
+                        .value_name("POLICY"),
+                )
+                .arg(
+                    Arg::new("preserve")
+                        .long("preserve")
+                        .help("Keep these attributes of the files, ex: mode,times,xattr or all")
+                        .num_args(1)
+                        .value_name("ATTRS"),
+                )
+                .arg(
+                    Arg::new("no-preserve-timestamps")
+                        .long("no-preserve-timestamps")
+                        .help("Don't keep the times of the files, even if preserve_attributes does")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("chmod")
+                        .long("chmod")
+                        .help("Set the mode of the pasted files, ex: 644")
+                        .num_args(1)
+                        .value_name("MODE"),
+                )
+                .arg(
+                    Arg::new("dir-mode")
+                        .long("dir-mode")
+                        .help("Set the mode of the directories created for the files, ex: 755")
+                        .num_args(1)
+                        .value_name("MODE"),
+                )
+                .arg(
+                    Arg::new("chown")
+                        .long("chown")
+                        .help("Give the files to this user and group, ex: alice:staff")
+                        .num_args(1)
+                        .value_name("OWNER"),
+                )
+                .arg(
+                    Arg::new("buffer-size")
+                        .long("buffer-size")
+                        .help("Stream the copies through a buffer of this size, ex: 1MB")
+                        .num_args(1)
+                        .value_name("SIZE"),
+                )
                 .arg(
                     Arg::new("output")
                         .long("output")
                         .short('o')
                         .help("The output dir or file")
+                        .value_hint(ValueHint::DirPath)
                         .num_args(1)
                 )
+                .arg(
+                    Arg::new("cd")
+                        .long("cd")
+                        .help("Paste into the directory zoxide finds for the query")
+                        .conflicts_with_all(["output", "at-root", "to-recent"])
+                        .num_args(1)
+                        .value_name("QUERY"),
+                )
+                .arg(
+                    Arg::new("pick-target")
+                        .long("pick-target")
+                        .help("Choose the output with the picker set in the config")
+                        .conflicts_with_all(["output", "at-root", "to-recent", "cd"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("to-recent")
+                        .long("to-recent")
+                        .help("Choose the output from the recent paste targets")
+                        .conflicts_with_all(["output", "at-root"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("at-root")
+                        .long("at-root")
+                        .help("Paste at the root of the enclosing git repo")
+                        .conflicts_with("output")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("all-matches")
+                        .long("all-matches")
+                        .help("Paste every entry a query matches instead of asking which were meant")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("select")
+                        .long("select")
+                        .help("Choose which of the matched entries to paste from a checklist")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("queries")
                         .help("Queries to filter the entires")
                         .num_args(1..)
                         .value_name("QUERIES")
                 )
+                .arg(
+                    Arg::new("version")
+                        .long("version")
+                        .help("Paste the given snapshot version of the entries")
+                        .value_parser(clap::value_parser!(i32))
+                        .num_args(1)
+                )
                 .arg(
                     Arg::new("range").long("range").help("Specify the range of entries to paste: Works using the syntax of n..[m]").short('r').num_args(1)
+                )
+                .arg(
+                    Arg::new("latest")
+                        .long("latest")
+                        .help("Paste the given number of most recently added entries")
+                        .value_parser(clap::value_parser!(usize))
+                        .conflicts_with("range")
+                        .num_args(1),
+                ),
+        ).subcommand(
+            Command::new("init")
+                .long_about("Print the completions and line editor widgets for the shell, to be evaluated in its rc file")
+                .arg(
+                    Arg::new("shell")
+                        .num_args(1)
+                        .value_name("SHELL")
+                        .value_parser(["bash", "zsh", "fish"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("no-bindings")
+                        .long("no-bindings")
+                        .help("Define the widgets without binding them to Alt-Y and Alt-P")
+                        .action(ArgAction::SetTrue),
+                ),
+        ).subcommand(
+            Command::new("update")
+                .long_about("Replace ynk with the binary of its latest release on GitHub")
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Only check if there is a newer release")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Update even if installed with cargo, or reinstall the latest release")
+                        .action(ArgAction::SetTrue),
                 ),
         ).subcommand(Command::new("completions")
                 .arg(
@@ -378,5 +1944,107 @@ pub fn setup_cli() -> Command {
                         .value_name("SHELL")
                         .required(true)
                 ).long_about("Generate and write completions")
-        )
+        );
+
+    // `cut` takes the arguments of `paste`, always deleting the entries
+    let cut = cli
+        .find_subcommand("paste")
+        .expect("paste is a subcommand")
+        .clone()
+        .name("cut")
+        .long_about("Move entries out of their sources to the output, deleting them from the store")
+        .mut_arg("delete", |a| a.hide(true));
+    cli.subcommand(cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add, store};
+
+    #[test]
+    fn queries_prefer_exact_and_prefix_matches() {
+        let conn = store();
+        let entries = add(&conn, &["/home/me/notes.txt", "/home/me/nodes.txt"]);
+        let names = |query: &str, matching| {
+            let mut names = deep_search(vec![query.to_string()], &entries, matching)
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        // nodes.txt is close enough to fuzzily match, but notes.txt starts with the query
+        assert_eq!(names("notes.tx", Matching::default()), vec!["notes.txt"]);
+        assert!(names("notes.tx", Matching::Exact).is_empty());
+        assert_eq!(names("notes.txt", Matching::Exact), vec!["notes.txt"]);
+
+        // Without a better match, the threshold decides how close a name has to be
+        assert_eq!(
+            names("notez.txt", Matching::default()),
+            vec!["nodes.txt", "notes.txt"]
+        );
+        assert_eq!(names("notez.txt", Matching::Fuzzy(0.85)), vec!["notes.txt"]);
+    }
+
+    #[test]
+    fn matches_are_explained() {
+        let conn = store();
+        let entries = add(&conn, &["/home/me/notes.txt"]);
+        let reasons = |query: &str| {
+            match_queries(&[query.to_string()], &entries, Matching::default())
+                .into_iter()
+                .map(|m| m.reason.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(reasons(&entries[0].id.to_string()), vec!["id"]);
+        assert_eq!(reasons("notes.txt"), vec!["exact name"]);
+        assert_eq!(reasons("/home/me/notes.txt"), vec!["exact path"]);
+        assert_eq!(reasons("not"), vec!["name prefix"]);
+        assert_eq!(reasons("notez.txt"), vec!["fuzzy, 0.89"]);
+    }
+
+    #[test]
+    fn preserved_names_keep_the_directories() {
+        let cwd = Path::new("/home/me/project");
+        let names = |paths: &[&str]| {
+            let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+            preserved_names(&paths, cwd)
+        };
+
+        assert_eq!(
+            names(&["/home/me/project/src/a.rs", "/home/me/project/tests/b.rs"]),
+            vec!["src/a.rs", "tests/b.rs"]
+        );
+        // Outside the current directory, the paths are named from the directory they share
+        assert_eq!(
+            names(&["/home/me/notes/a.md", "/home/me/docs/b/c.md"]),
+            vec!["notes/a.md", "docs/b/c.md"]
+        );
+        assert_eq!(names(&["/home/me/project"]), vec!["project"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sockets_are_skipped_even_in_strict_mode() {
+        let dir = crate::testing::TempDir::new();
+        let file = dir.write("notes.txt", "notes");
+        let socket = dir.0.join("ynk.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let config = ListDirConfig {
+            respect_ignore: true,
+            full_path: false,
+            strict: true,
+            hidden: true,
+            filter_file: false,
+            symlinks: SymlinkPolicy::Skip,
+        };
+        let listing = walk_dir(dir.0.to_str().unwrap(), &config);
+        assert!(listing.paths.contains(&file));
+        assert!(!listing.paths.contains(&socket));
+        assert_eq!(listing.skipped, vec![socket]);
+    }
 }
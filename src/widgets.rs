@@ -0,0 +1,83 @@
+//! The line editor widgets `ynk init` sets up in bash, zsh and fish,
+//! so the store can be used like a clipboard without leaving the command line
+//!
+//! * `_ynk_add_word`, bound to Alt-Y, adds the path under the cursor as an entry
+//! * `_ynk_insert_paste`, bound to Alt-P, inserts `ynk paste --latest 1`, pasting the top entry
+
+use clap_complete::Shell;
+
+const ZSH: &str = r#"
+_ynk_add_word() {
+    local word="${LBUFFER##* }${RBUFFER%% *}"
+    [[ -n "$word" ]] || return 1
+    word="${(Q)word}"
+    [[ "$word" == "~"* ]] && word="$HOME${word:1}"
+    # The output goes above the prompt, which is drawn again afterwards
+    zle -I
+    ynk --yes add -- "$word"
+}
+_ynk_insert_paste() {
+    LBUFFER+="ynk paste --latest 1 "
+}
+zle -N _ynk_add_word
+zle -N _ynk_insert_paste
+"#;
+
+const ZSH_BINDINGS: &str = r#"bindkey '\ey' _ynk_add_word
+bindkey '\ep' _ynk_insert_paste
+"#;
+
+const BASH: &str = r#"
+_ynk_add_word() {
+    local left="${READLINE_LINE:0:READLINE_POINT}" right="${READLINE_LINE:READLINE_POINT}"
+    local word="${left##* }${right%% *}"
+    [[ -n "$word" ]] || return
+    [[ "$word" == "~"* ]] && word="$HOME${word:1}"
+    ynk --yes add -- "$word"
+}
+_ynk_insert_paste() {
+    local insert="ynk paste --latest 1 "
+    READLINE_LINE="${READLINE_LINE:0:READLINE_POINT}$insert${READLINE_LINE:READLINE_POINT}"
+    READLINE_POINT=$((READLINE_POINT + ${#insert}))
+}
+"#;
+
+const BASH_BINDINGS: &str = r#"if [[ $- == *i* ]]; then
+    bind -x '"\ey": _ynk_add_word'
+    bind -x '"\ep": _ynk_insert_paste'
+fi
+"#;
+
+const FISH: &str = r#"
+function _ynk_add_word
+    set -l word (commandline -t)
+    test -n "$word"; or return
+    echo
+    ynk --yes add -- (string replace -r '^~' $HOME -- $word)
+    commandline -f repaint
+end
+function _ynk_insert_paste
+    commandline -i 'ynk paste --latest 1 '
+end
+"#;
+
+const FISH_BINDINGS: &str = r#"bind \ey _ynk_add_word
+bind \ep _ynk_insert_paste
+"#;
+
+/// The widgets for the shell, bound to their keys unless `bind` is false,
+/// or `None` for the shells without any
+pub fn script(shell: Shell, bind: bool) -> Option<String> {
+    let (widgets, bindings) = match shell {
+        Shell::Zsh => (ZSH, ZSH_BINDINGS),
+        Shell::Bash => (BASH, BASH_BINDINGS),
+        Shell::Fish => (FISH, FISH_BINDINGS),
+        _ => return None,
+    };
+
+    let mut script = widgets.to_string();
+    if bind {
+        script.push_str(bindings);
+    }
+    Some(script)
+}
@@ -0,0 +1,578 @@
+//! Adds, lists, pastes and pops entries through the engine,
+//! against a temporary directory and an in-memory store
+
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+use indicatif::ProgressBar;
+use rusqlite::Connection;
+use ynk::{
+    config, db, engine,
+    fs::{MemoryFs, RealFs},
+    symlinks::SymlinkPolicy,
+    utils, ConstructedArgs,
+};
+
+/// A temporary directory, removed once dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("ynk-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        // Entries are stored by their canonical path
+        Self(path.canonicalize().unwrap())
+    }
+
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn store() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    db::prep_db(&conn).unwrap();
+    conn
+}
+
+/// The arguments with every config option at its default
+fn args() -> ConstructedArgs {
+    ConstructedArgs::new(config::get_config(String::new()))
+}
+
+fn add(conn: &Connection, paths: &[&Path]) -> Vec<db::Entry> {
+    add_with(conn, paths, &args())
+}
+
+/// Adds the directories with `--dir`, so they are pasted as a directory of their own
+fn add_dirs(conn: &Connection, paths: &[&Path]) -> Vec<db::Entry> {
+    let mut args = args();
+    args.dir = true;
+    add_with(conn, paths, &args)
+}
+
+fn add_with(conn: &Connection, paths: &[&Path], args: &ConstructedArgs) -> Vec<db::Entry> {
+    let files = paths
+        .iter()
+        .map(|p| (utils::parse_file_name(p.to_str().unwrap()), p.to_path_buf()))
+        .collect::<HashMap<_, _>>();
+    engine::add(conn, &files, args).unwrap()
+}
+
+async fn paste_to(
+    conn: &Connection,
+    entries: &[db::Entry],
+    output: &Path,
+    overwrite: bool,
+) -> (engine::PastePlan, engine::PasteResult) {
+    let output = output.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, conn, entries, &args(), |_| output.clone()).unwrap();
+    let result = engine::paste(&RealFs, &plan, overwrite, &ProgressBar::hidden()).await;
+    (plan, result)
+}
+
+#[test]
+fn add_lists_entries() {
+    let dir = TempDir::new();
+    let file = dir.write("notes.txt", "hello");
+    let conn = store();
+
+    add(&conn, &[file.as_path()]);
+
+    let entries = db::get_all(&conn).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "notes.txt");
+    assert_eq!(PathBuf::from(&entries[0].path), file);
+    assert!(!entries[0].is_dir);
+}
+
+#[tokio::test]
+async fn paste_copies_a_file() {
+    let dir = TempDir::new();
+    let file = dir.write("src/notes.txt", "hello");
+    let out = TempDir::new();
+    let conn = store();
+
+    let entries = add(&conn, &[file.as_path()]);
+    let (_, result) = paste_to(&conn, &entries, &out.0, false).await;
+
+    assert!(result.is_success());
+    assert_eq!(result.pasted, vec![out.0.join("notes.txt")]);
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("notes.txt")).unwrap(),
+        "hello"
+    );
+}
+
+#[tokio::test]
+async fn paste_keeps_the_structure_of_a_dir() {
+    let dir = TempDir::new();
+    dir.write("project/a.txt", "a");
+    dir.write("project/nested/b.txt", "b");
+    let out = TempDir::new();
+    let conn = store();
+
+    let entries = add_dirs(&conn, &[dir.0.join("project").as_path()]);
+    assert!(entries[0].is_dir);
+
+    let (plan, result) = paste_to(&conn, &entries, &out.0, false).await;
+
+    assert!(result.is_success());
+    assert_eq!(plan.files.len(), 2);
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("project/a.txt")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("project/nested/b.txt")).unwrap(),
+        "b"
+    );
+}
+
+#[tokio::test]
+async fn paste_puts_the_files_of_a_dir_into_the_target() {
+    let dir = TempDir::new();
+    dir.write("project/a.txt", "a");
+    dir.write("project/nested/b.txt", "b");
+    let out = TempDir::new();
+    let conn = store();
+
+    let entries = add(&conn, &[dir.0.join("project").as_path()]);
+    assert!(!entries[0].is_dir);
+
+    let (_, result) = paste_to(&conn, &entries, &out.0, false).await;
+
+    assert!(result.is_success());
+    assert_eq!(std::fs::read_to_string(out.0.join("a.txt")).unwrap(), "a");
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("nested/b.txt")).unwrap(),
+        "b"
+    );
+}
+
+#[test]
+fn plan_refuses_two_files_pasted_to_one_target() {
+    let dir = TempDir::new();
+    let first = dir.write("one/notes.txt", "one");
+    let second = dir.write("two/notes.txt", "two");
+    let out = TempDir::new();
+    let conn = store();
+
+    let mut entries = add(&conn, &[first.as_path()]);
+    entries.extend(add(&conn, &[second.as_path()]));
+
+    let output = out.0.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, &conn, &entries, &args(), |_| output.clone());
+    assert!(matches!(
+        plan,
+        Err(engine::PlanError::Clash(_, _, target)) if target == out.0.join("notes.txt")
+    ));
+}
+
+#[tokio::test]
+async fn paste_reports_conflicts_unless_overwriting() {
+    let dir = TempDir::new();
+    let file = dir.write("notes.txt", "new");
+    let out = TempDir::new();
+    out.write("notes.txt", "old");
+    let conn = store();
+
+    let entries = add(&conn, &[file.as_path()]);
+
+    let (_, result) = paste_to(&conn, &entries, &out.0, false).await;
+    assert_eq!(result.conflicts, vec![out.0.join("notes.txt")]);
+    assert!(result.pasted.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("notes.txt")).unwrap(),
+        "old"
+    );
+
+    let (_, result) = paste_to(&conn, &entries, &out.0, true).await;
+    assert!(result.is_success());
+    assert_eq!(
+        std::fs::read_to_string(out.0.join("notes.txt")).unwrap(),
+        "new"
+    );
+}
+
+#[tokio::test]
+async fn pop_pastes_the_last_entry_and_removes_it() {
+    let dir = TempDir::new();
+    let first = dir.write("first.txt", "1");
+    let second = dir.write("second.txt", "2");
+    let out = TempDir::new();
+    let conn = store();
+
+    add(&conn, &[first.as_path()]);
+    add(&conn, &[second.as_path()]);
+
+    let top = db::pop_one(&conn).unwrap();
+    assert_eq!(top.name, "second.txt");
+
+    let entries = [top];
+    let (plan, result) = paste_to(&conn, &entries, &out.0, false).await;
+    assert!(result.is_success());
+    engine::finish(&conn, &entries, &plan, true).unwrap();
+
+    assert!(out.0.join("second.txt").exists());
+    assert!(!out.0.join("first.txt").exists());
+
+    let remaining = db::get_all(&conn).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].name, "first.txt");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unwritable_targets_fail_and_keep_the_entries() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Permissions don't hold back root
+    if nix::unistd::geteuid().is_root() {
+        return;
+    }
+    let dir = TempDir::new();
+    let file = dir.write("notes.txt", "hello");
+    let out = TempDir::new();
+    let target = out.0.join("locked");
+    std::fs::create_dir(&target).unwrap();
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o555)).unwrap();
+    let conn = store();
+
+    let entries = add(&conn, &[file.as_path()]);
+    let (plan, result) = paste_to(&conn, &entries, &target, false).await;
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The source could be read, so the file failed rather than being skipped
+    assert!(!result.is_success());
+    assert!(result.skipped.is_empty());
+    assert_eq!(result.failed.len(), 1);
+
+    let delete = true;
+    engine::finish(&conn, &entries, &plan, delete && result.is_success()).unwrap();
+    assert_eq!(db::get_all(&conn).unwrap().len(), 1);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_are_skipped_or_recreated() {
+    let dir = TempDir::new();
+    dir.write("project/a.txt", "a");
+    std::os::unix::fs::symlink("a.txt", dir.0.join("project/link")).unwrap();
+    let conn = store();
+    let entries = add(&conn, &[dir.0.join("project").as_path()]);
+
+    let skipped = TempDir::new();
+    let (plan, result) = paste_to(&conn, &entries, &skipped.0, false).await;
+    assert!(result.is_success());
+    assert!(plan.links.is_empty());
+    assert!(!skipped.0.join("project/link").exists());
+
+    let copied = TempDir::new();
+    let mut args = args();
+    args.symlinks = SymlinkPolicy::Copy;
+    let output = copied.0.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, &conn, &entries, &args, |_| output.clone()).unwrap();
+    assert_eq!(plan.files.len(), 1);
+    assert_eq!(plan.links.len(), 1);
+
+    let result = engine::paste(&RealFs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    assert_eq!(
+        std::fs::read_link(copied.0.join("project/link")).unwrap(),
+        PathBuf::from("a.txt")
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn paste_keeps_extended_attributes_when_asked() {
+    let dir = TempDir::new();
+    let file = dir.write("notes.txt", "hello");
+    // Not every filesystem has user attributes
+    if xattr::set(&file, "user.ynk", b"kept").is_err() {
+        return;
+    }
+    let conn = store();
+    let entries = add(&conn, &[file.as_path()]);
+
+    let plain = TempDir::new();
+    let (_, result) = paste_to(&conn, &entries, &plain.0, false).await;
+    assert!(result.is_success());
+    assert_eq!(
+        xattr::get(plain.0.join("notes.txt"), "user.ynk").unwrap(),
+        None
+    );
+
+    let out = TempDir::new();
+    let mut args = args();
+    args.preserve = "xattr".parse().unwrap();
+    let output = out.0.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, &conn, &entries, &args, |_| output.clone()).unwrap();
+    let result = engine::paste(&RealFs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    assert_eq!(
+        xattr::get(out.0.join("notes.txt"), "user.ynk").unwrap(),
+        Some(b"kept".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn paste_keeps_the_times_when_asked() {
+    let dir = TempDir::new();
+    let file = dir.write("notes.txt", "hello");
+    let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let conn = store();
+    let entries = add(&conn, &[file.as_path()]);
+
+    let out = TempDir::new();
+    let mut args = args();
+    args.preserve = "mode,times".parse().unwrap();
+    let output = out.0.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, &conn, &entries, &args, |_| output.clone()).unwrap();
+    let result = engine::paste(&RealFs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    let pasted = std::fs::metadata(out.0.join("notes.txt")).unwrap();
+    assert_eq!(pasted.modified().unwrap(), modified);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn paste_sets_the_modes_asked_for() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let file = dir.write("project/nested/a.txt", "a");
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o400)).unwrap();
+    let conn = store();
+    let entries = add_dirs(&conn, &[dir.0.join("project").as_path()]);
+
+    let out = TempDir::new();
+    // A directory that is already there isn't the paste's to change
+    std::fs::create_dir(out.0.join("project")).unwrap();
+    std::fs::set_permissions(
+        out.0.join("project"),
+        std::fs::Permissions::from_mode(0o700),
+    )
+    .unwrap();
+    let mut args = args();
+    args.file_mode = utils::parse_mode("644");
+    args.dir_mode = utils::parse_mode("0750");
+    let output = out.0.to_string_lossy().to_string();
+    let plan = engine::plan(&RealFs, &conn, &entries, &args, |_| output.clone()).unwrap();
+    let result = engine::paste(&RealFs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+
+    let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&out.0.join("project/nested/a.txt")), 0o644);
+    assert_eq!(mode(&out.0.join("project/nested")), 0o750);
+    assert_eq!(mode(&out.0.join("project")), 0o700);
+    assert_ne!(mode(&out.0), 0o750);
+}
+
+#[tokio::test]
+async fn plans_and_pastes_in_memory() {
+    let fs = MemoryFs::new()
+        .with_file("/src/project/a.txt", "a")
+        .with_file("/src/project/nested/b.txt", "b")
+        .with_file("/src/project/.hidden", "h");
+    let conn = store();
+
+    let entry = db::insert_into_db(
+        &conn,
+        db::EntryBuilder::new("project", "/src/project", true).with_discovery(false, true),
+    )
+    .unwrap();
+
+    let plan = engine::plan(&fs, &conn, &[entry], &args(), |_| "/out".to_string()).unwrap();
+    assert_eq!(plan.files.len(), 2);
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    assert_eq!(fs.contents("/out/project/a.txt"), Some(b"a".to_vec()));
+    assert_eq!(
+        fs.contents("/out/project/nested/b.txt"),
+        Some(b"b".to_vec())
+    );
+    assert_eq!(fs.contents("/out/project/.hidden"), None);
+}
+
+#[tokio::test]
+async fn finds_the_files_over_the_size_limit() {
+    let fs = MemoryFs::new()
+        .with_file("/src/small.txt", vec![0; 10])
+        .with_file("/src/large.bin", vec![0; 2000])
+        .with_file("/src/larger.bin", vec![0; 3000]);
+    let plan = engine::PastePlan {
+        files: ["small.txt", "large.bin", "larger.bin"]
+            .iter()
+            .map(|name| engine::PasteFile {
+                source: PathBuf::from("/src").join(name),
+                target: PathBuf::from("/out").join(name),
+                move_source: false,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let large = engine::large_files(&fs, &plan, 1000).await;
+    assert_eq!(
+        large,
+        vec![
+            (PathBuf::from("/src/larger.bin"), 3000),
+            (PathBuf::from("/src/large.bin"), 2000),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn memory_paste_reports_missing_sources() {
+    let fs = MemoryFs::new().with_file("/out/existing.txt", "old");
+    let plan = engine::PastePlan {
+        files: vec![
+            engine::PasteFile {
+                source: PathBuf::from("/src/missing.txt"),
+                target: PathBuf::from("/out/missing.txt"),
+                move_source: false,
+            },
+            engine::PasteFile {
+                source: PathBuf::from("/src/missing.txt"),
+                target: PathBuf::from("/out/existing.txt"),
+                move_source: false,
+            },
+        ],
+        ..Default::default()
+    };
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert_eq!(result.failed.len(), 1);
+    assert_eq!(result.failed[0].0, PathBuf::from("/out/missing.txt"));
+    assert_eq!(result.conflicts, vec![PathBuf::from("/out/existing.txt")]);
+    assert_eq!(fs.contents("/out/existing.txt"), Some(b"old".to_vec()));
+}
+
+fn move_plan(files: &[(&str, &str)], roots: &[&str]) -> engine::PastePlan {
+    engine::PastePlan {
+        files: files
+            .iter()
+            .map(|(source, target)| engine::PasteFile {
+                source: PathBuf::from(source),
+                target: PathBuf::from(target),
+                move_source: true,
+            })
+            .collect(),
+        move_roots: roots.iter().map(PathBuf::from).collect(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn move_renames_and_removes_emptied_dirs() {
+    let fs = MemoryFs::new()
+        .with_file("/src/project/a.txt", "a")
+        .with_file("/src/project/nested/b.txt", "b");
+    let plan = move_plan(
+        &[
+            ("/src/project/a.txt", "/out/project/a.txt"),
+            ("/src/project/nested/b.txt", "/out/project/nested/b.txt"),
+        ],
+        &["/src/project"],
+    );
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    assert_eq!(
+        fs.contents("/out/project/nested/b.txt"),
+        Some(b"b".to_vec())
+    );
+    assert!(!fs.exists_at("/src/project/a.txt"));
+    assert!(!fs.exists_at("/src/project"));
+    assert!(fs.exists_at("/src"));
+}
+
+#[tokio::test]
+async fn move_across_devices_copies_then_removes_the_source() {
+    let fs = MemoryFs::new()
+        .with_mount("/mnt/usb")
+        .with_file("/src/notes.txt", "notes")
+        .with_file("/src/kept.txt", "new")
+        .with_file("/mnt/usb/kept.txt", "old");
+    let plan = move_plan(
+        &[
+            ("/src/notes.txt", "/mnt/usb/notes.txt"),
+            ("/src/kept.txt", "/mnt/usb/kept.txt"),
+        ],
+        &[],
+    );
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert_eq!(result.pasted, vec![PathBuf::from("/mnt/usb/notes.txt")]);
+    assert_eq!(fs.contents("/mnt/usb/notes.txt"), Some(b"notes".to_vec()));
+    assert!(!fs.exists_at("/src/notes.txt"));
+
+    // A conflicting target leaves the source where it was
+    assert_eq!(result.conflicts, vec![PathBuf::from("/mnt/usb/kept.txt")]);
+    assert_eq!(fs.contents("/src/kept.txt"), Some(b"new".to_vec()));
+}
+
+#[tokio::test]
+async fn move_keeps_the_source_when_the_copy_fails() {
+    let fs = MemoryFs::new()
+        .with_mount("/mnt/usb")
+        .with_file("/src/notes.txt", "notes")
+        .with_file("/mnt/usb/blocked", "a file where a directory is needed");
+    let plan = move_plan(&[("/src/notes.txt", "/mnt/usb/blocked/notes.txt")], &[]);
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(!result.is_success());
+    assert!(result.pasted.is_empty());
+    assert_eq!(fs.contents("/src/notes.txt"), Some(b"notes".to_vec()));
+}
+
+#[tokio::test]
+async fn paste_delete_moves_out_of_the_snapshot() {
+    let fs = MemoryFs::new()
+        .with_file("/src/notes.txt", "changed")
+        .with_file("/snapshots/1/notes.txt", "notes");
+    let conn = store();
+    let entry = db::insert_into_db(
+        &conn,
+        db::EntryBuilder::new("notes.txt", "/src/notes.txt", false),
+    )
+    .unwrap();
+    db::insert_snapshot(&conn, "/src/notes.txt", "/snapshots/1/notes.txt").unwrap();
+
+    let mut args = args();
+    args.delete = true;
+    let plan = engine::plan(&fs, &conn, &[entry], &args, |_| "/out".to_string()).unwrap();
+    assert_eq!(
+        plan.files,
+        vec![engine::PasteFile {
+            source: PathBuf::from("/snapshots/1/notes.txt"),
+            target: PathBuf::from("/out/notes.txt"),
+            move_source: true,
+        }]
+    );
+
+    let result = engine::paste(&fs, &plan, false, &ProgressBar::hidden()).await;
+    assert!(result.is_success());
+    assert_eq!(fs.contents("/out/notes.txt"), Some(b"notes".to_vec()));
+    // The source is left alone, while the emptied snapshot goes
+    assert_eq!(fs.contents("/src/notes.txt"), Some(b"changed".to_vec()));
+    assert!(!fs.exists_at("/snapshots/1"));
+}